@@ -1,7 +1,7 @@
 #![no_main]
 
 use libfuzzer_sys::fuzz_target;
-use sbat::{ImageSbatOwned, RevocationSbatOwned};
+use sbat::{ImageSbatOwned, RevocationIndex, RevocationSbatOwned};
 
 // Generate both ImageSbat and RevocationSbat so they can be tested
 // together.
@@ -19,8 +19,21 @@ fuzz_target!(|data: (&[u8], &[u8])| {
         for _ in revocations.revoked_components() {}
 
         let _ = revocations.validate_image(&image);
+        for _ in revocations.revoked_entries(&image) {}
         for entry in image.entries() {
             let _ = revocations.is_component_revoked(&entry.component);
         }
+
+        // The index must give the same results as the unindexed data.
+        let index = RevocationIndex::new(&revocations);
+        assert_eq!(
+            index.validate_image(&image),
+            revocations.validate_image(&image)
+        );
+        assert!(
+            index
+                .revoked_entries(&image)
+                .eq(revocations.revoked_entries(&image))
+        );
     }
 });
@@ -0,0 +1,564 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Add or replace a section in a PE executable.
+
+use anyhow::{Context, Result, bail, ensure};
+
+/// Offset within the DOS header of the PE header offset.
+const PE_OFFSET_OFFSET: usize = 0x3c;
+
+/// Size of the PE signature plus the COFF file header.
+const PE_HEADERS_SIZE: usize = 24;
+
+/// Size of a section header.
+const SECTION_HEADER_SIZE: usize = 40;
+
+/// Optional header magic for PE32.
+const PE32_MAGIC: u16 = 0x10b;
+
+/// Optional header magic for PE32+.
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+
+/// Index of the certificate table (Authenticode signature) in the data
+/// directories.
+const CERTIFICATE_TABLE_INDEX: usize = 4;
+
+/// Section characteristics for read-only initialized data, the same as
+/// used for `.sbat` in shim.
+pub const READ_ONLY_DATA_CHARACTERISTICS: u32 = 0x4000_0040;
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .context("PE header is truncated")?;
+    Ok(u16::from_le_bytes(bytes.try_into()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .context("PE header is truncated")?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+fn read_usize(data: &[u8], offset: usize) -> Result<usize> {
+    Ok(usize::try_from(read_u32(data, offset)?)?)
+}
+
+fn write_u16(data: &mut [u8], offset: usize, val: u16) {
+    data[offset..offset + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+fn write_u32(data: &mut [u8], offset: usize, val: usize) -> Result<()> {
+    let val = u32::try_from(val).context("value does not fit in u32")?;
+    data[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+    Ok(())
+}
+
+fn align_up(val: usize, alignment: usize) -> usize {
+    val.div_ceil(alignment) * alignment
+}
+
+/// Section header fields that are needed to place a new section.
+struct SectionHeader {
+    offset: usize,
+    name: [u8; 8],
+    virtual_size: usize,
+    virtual_address: usize,
+    size_of_raw_data: usize,
+    pointer_to_raw_data: usize,
+}
+
+/// Offsets and fields of the PE headers.
+struct Headers {
+    coff_offset: usize,
+    optional_header_offset: usize,
+    section_table_offset: usize,
+    num_sections: usize,
+    section_alignment: usize,
+    file_alignment: usize,
+    size_of_headers: usize,
+    checksum_offset: usize,
+    /// Offset of the certificate table data directory entry, if the
+    /// optional header has one.
+    certificate_table_offset: Option<usize>,
+    sections: Vec<SectionHeader>,
+}
+
+impl Headers {
+    fn parse(data: &[u8]) -> Result<Self> {
+        ensure!(data.starts_with(b"MZ"), "invalid DOS header");
+        let pe_offset = read_usize(data, PE_OFFSET_OFFSET)?;
+        ensure!(
+            data.get(pe_offset..pe_offset + 4) == Some(b"PE\0\0"),
+            "invalid PE signature"
+        );
+        let coff_offset = pe_offset + 4;
+        let num_sections = usize::from(read_u16(data, coff_offset + 2)?);
+        let optional_header_size =
+            usize::from(read_u16(data, coff_offset + 16)?);
+        let optional_header_offset = pe_offset + PE_HEADERS_SIZE;
+
+        let opt = optional_header_offset;
+        let data_directories_offset = match read_u16(data, opt)? {
+            PE32_MAGIC => opt + 96,
+            PE32_PLUS_MAGIC => opt + 112,
+            magic => bail!("invalid optional header magic: {magic:#06x}"),
+        };
+        let optional_header_end = opt + optional_header_size;
+        ensure!(
+            data_directories_offset <= optional_header_end,
+            "optional header is too small"
+        );
+        let num_data_directories =
+            read_usize(data, data_directories_offset - 4)?;
+        ensure!(
+            data_directories_offset + num_data_directories * 8
+                <= optional_header_end,
+            "data directories extend past the optional header"
+        );
+        let certificate_table_offset = (num_data_directories
+            > CERTIFICATE_TABLE_INDEX)
+            .then_some(data_directories_offset + CERTIFICATE_TABLE_INDEX * 8);
+
+        let section_table_offset = opt + optional_header_size;
+        let sections = (0..num_sections)
+            .map(|i| {
+                let offset = section_table_offset + i * SECTION_HEADER_SIZE;
+                let name = data
+                    .get(offset..offset + 8)
+                    .context("section table is truncated")?;
+                Ok(SectionHeader {
+                    offset,
+                    name: name.try_into()?,
+                    virtual_size: read_usize(data, offset + 8)?,
+                    virtual_address: read_usize(data, offset + 12)?,
+                    size_of_raw_data: read_usize(data, offset + 16)?,
+                    pointer_to_raw_data: read_usize(data, offset + 20)?,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let headers = Self {
+            coff_offset,
+            optional_header_offset,
+            section_table_offset,
+            num_sections,
+            section_alignment: read_usize(data, opt + 32)?,
+            file_alignment: read_usize(data, opt + 36)?,
+            size_of_headers: read_usize(data, opt + 60)?,
+            checksum_offset: opt + 64,
+            certificate_table_offset,
+            sections,
+        };
+        ensure!(
+            headers.section_alignment.is_power_of_two()
+                && headers.file_alignment.is_power_of_two(),
+            "invalid section or file alignment"
+        );
+        Ok(headers)
+    }
+
+    /// Get the file offset and size of the Authenticode signature, if
+    /// present.
+    fn certificate_table(&self, data: &[u8]) -> Result<Option<(usize, usize)>> {
+        let Some(offset) = self.certificate_table_offset else {
+            return Ok(None);
+        };
+        let size = read_usize(data, offset + 4)?;
+        if size == 0 {
+            return Ok(None);
+        }
+        Ok(Some((read_usize(data, offset)?, size)))
+    }
+
+    fn find_section(&self, name: &str) -> Option<&SectionHeader> {
+        self.sections
+            .iter()
+            .find(|section| section_name_matches(&section.name, name))
+    }
+
+    /// End of the last section in memory, aligned to the section
+    /// alignment.
+    fn end_of_image(&self) -> usize {
+        let end = self
+            .sections
+            .iter()
+            .map(|section| {
+                section.virtual_address
+                    + section.virtual_size.max(section.size_of_raw_data)
+            })
+            .max()
+            .unwrap_or(self.size_of_headers);
+        align_up(end, self.section_alignment)
+    }
+}
+
+fn section_name_matches(raw_name: &[u8; 8], name: &str) -> bool {
+    let len = raw_name.iter().position(|b| *b == 0).unwrap_or(8);
+    &raw_name[..len] == name.as_bytes()
+}
+
+/// Compute the PE checksum of `data`. The existing checksum field at
+/// `checksum_offset` is treated as zero.
+pub fn pe_checksum(data: &[u8], checksum_offset: usize) -> u32 {
+    let mut sum: u64 = 0;
+    for (i, chunk) in data.chunks(2).enumerate() {
+        let offset = i * 2;
+        if offset == checksum_offset || offset == checksum_offset + 2 {
+            continue;
+        }
+        let word = u16::from_le_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]);
+        sum += u64::from(word);
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    // OK to unwrap: the carries have been folded, so the sum fits in
+    // 16 bits, and the length is masked to 32 bits.
+    let sum = u32::try_from(sum).unwrap();
+    let len = u32::try_from(data.len() & 0xffff_ffff).unwrap();
+    sum.wrapping_add(len)
+}
+
+/// Remove the Authenticode signature from `data`. The signature must be
+/// at the end of the file.
+fn strip_signature(data: &mut Vec<u8>, headers: &Headers) -> Result<()> {
+    let Some((offset, size)) = headers.certificate_table(data)? else {
+        return Ok(());
+    };
+    ensure!(
+        offset + size == data.len(),
+        "the signature is not at the end of the file"
+    );
+    data.truncate(offset);
+    // The signature is padded to eight bytes, but there may also be
+    // padding before it that was added by the signing tool. Leave that
+    // in place, it's harmless.
+    let entry = headers
+        .certificate_table_offset
+        .context("missing certificate table data directory")?;
+    write_u32(data, entry, 0)?;
+    write_u32(data, entry + 4, 0)?;
+    Ok(())
+}
+
+/// Add a section named `name` containing `section_data` to the PE
+/// executable `input`, or replace the section if it already exists.
+///
+/// A new section is appended after all existing sections, both in the
+/// file and in memory. An existing section is replaced in place if the
+/// new data fits in the space it occupies; otherwise it is an error.
+///
+/// Executables with an Authenticode signature are rejected, since
+/// modifying them would invalidate the signature, unless
+/// `strip_signature` is true.
+///
+/// The number of sections, `SizeOfImage`, `SizeOfInitializedData`, and
+/// the checksum are updated.
+pub fn add_section(
+    input: &[u8],
+    name: &str,
+    section_data: &[u8],
+    strip_signature: bool,
+) -> Result<Vec<u8>> {
+    ensure!(name.len() <= 8, "section name is longer than 8 bytes");
+    ensure!(!section_data.is_empty(), "section data is empty");
+
+    let mut data = input.to_vec();
+    let headers = Headers::parse(&data)?;
+
+    if headers.certificate_table(&data)?.is_some() {
+        ensure!(
+            strip_signature,
+            "the executable is signed; adding a section would invalidate \
+             the signature (use --strip-signature to remove it)"
+        );
+        self::strip_signature(&mut data, &headers)?;
+    }
+
+    let raw_size = align_up(section_data.len(), headers.file_alignment);
+
+    if let Some(section) = headers.find_section(name) {
+        replace_section(&mut data, &headers, section, section_data)?;
+    } else {
+        append_section(&mut data, &headers, name, section_data)?;
+
+        // Update the COFF header and optional header.
+        let opt = headers.optional_header_offset;
+        write_u16(
+            &mut data,
+            headers.coff_offset + 2,
+            u16::try_from(headers.num_sections + 1)
+                .context("too many sections")?,
+        );
+        let size_of_image = align_up(
+            headers.end_of_image() + section_data.len(),
+            headers.section_alignment,
+        );
+        write_u32(&mut data, opt + 56, size_of_image)?;
+        let initialized_data_size = read_usize(&data, opt + 8)?;
+        write_u32(&mut data, opt + 8, initialized_data_size + raw_size)?;
+    }
+
+    let checksum = pe_checksum(&data, headers.checksum_offset);
+    write_u32(
+        &mut data,
+        headers.checksum_offset,
+        usize::try_from(checksum)?,
+    )?;
+
+    Ok(data)
+}
+
+/// Replace the data of an existing section.
+fn replace_section(
+    data: &mut [u8],
+    headers: &Headers,
+    section: &SectionHeader,
+    section_data: &[u8],
+) -> Result<()> {
+    // The new data must fit in the file space of the section, and
+    // must not extend the section in memory into the next one.
+    let virtual_space = align_up(
+        section.virtual_size.max(section.size_of_raw_data),
+        headers.section_alignment,
+    );
+    ensure!(
+        section_data.len() <= section.size_of_raw_data
+            && section_data.len() <= virtual_space,
+        "the existing section is too small for the new data ({} bytes \
+         available, {} needed)",
+        section.size_of_raw_data.min(virtual_space),
+        section_data.len()
+    );
+
+    let start = section.pointer_to_raw_data;
+    let raw = data
+        .get_mut(start..start + section.size_of_raw_data)
+        .context("section data is out of bounds")?;
+    raw.fill(0);
+    raw[..section_data.len()].copy_from_slice(section_data);
+    write_u32(data, section.offset + 8, section_data.len())
+}
+
+/// Append a new section after all existing sections.
+fn append_section(
+    data: &mut Vec<u8>,
+    headers: &Headers,
+    name: &str,
+    section_data: &[u8],
+) -> Result<()> {
+    // There must be room in the headers for another section header.
+    let header_offset = headers.section_table_offset
+        + headers.num_sections * SECTION_HEADER_SIZE;
+    let header_end = header_offset + SECTION_HEADER_SIZE;
+    let first_section_data = headers
+        .sections
+        .iter()
+        .map(|section| section.pointer_to_raw_data)
+        .filter(|offset| *offset != 0)
+        .min()
+        .unwrap_or(headers.size_of_headers);
+    ensure!(
+        header_end <= headers.size_of_headers
+            && header_end <= first_section_data,
+        "no room in the headers for another section header"
+    );
+    let header_space = data
+        .get(header_offset..header_end)
+        .context("headers extend past the end of the file")?;
+    ensure!(
+        header_space.iter().all(|b| *b == 0),
+        "the space after the section table is not empty"
+    );
+
+    // Place the section data at the end of the file, and the section in
+    // memory after the last section.
+    let raw_offset = align_up(data.len(), headers.file_alignment);
+    let raw_size = align_up(section_data.len(), headers.file_alignment);
+    let virtual_address = headers.end_of_image();
+
+    let mut name_bytes = [0; 8];
+    name_bytes[..name.len()].copy_from_slice(name.as_bytes());
+    data[header_offset..header_offset + 8].copy_from_slice(&name_bytes);
+    write_u32(data, header_offset + 8, section_data.len())?;
+    write_u32(data, header_offset + 12, virtual_address)?;
+    write_u32(data, header_offset + 16, raw_size)?;
+    write_u32(data, header_offset + 20, raw_offset)?;
+    let characteristics = usize::try_from(READ_ONLY_DATA_CHARACTERISTICS)?;
+    write_u32(data, header_offset + 36, characteristics)?;
+
+    data.resize(raw_offset, 0);
+    data.extend(section_data);
+    data.resize(raw_offset + raw_size, 0);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TestSection, make_pe};
+    use object::{Object, ObjectSection, pe};
+
+    const SBAT: &[u8] = b"sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md\n";
+
+    fn read_section(data: &[u8], name: &str) -> Vec<u8> {
+        let file = object::File::parse(data).unwrap();
+        file.section_by_name(name).unwrap().data().unwrap().to_vec()
+    }
+
+    fn optional_header_u32(data: &[u8], offset: usize) -> u32 {
+        let pe_offset = read_usize(data, PE_OFFSET_OFFSET).unwrap();
+        read_u32(data, pe_offset + PE_HEADERS_SIZE + offset).unwrap()
+    }
+
+    fn checksum_offset(data: &[u8]) -> usize {
+        read_usize(data, PE_OFFSET_OFFSET).unwrap() + PE_HEADERS_SIZE + 64
+    }
+
+    #[test]
+    fn test_append_section() {
+        for pe32_plus in [false, true] {
+            let input =
+                make_pe(&[TestSection::new(".text", &[0xc3])], pe32_plus);
+            let output = add_section(&input, ".sbat", SBAT, false).unwrap();
+
+            let file = object::File::parse(&*output).unwrap();
+            assert_eq!(file.sections().count(), 2);
+            let section = file.section_by_name(".sbat").unwrap();
+            assert_eq!(section.data().unwrap(), SBAT);
+            assert_eq!(
+                section.address() - file.relative_address_base(),
+                0x2000
+            );
+            assert_eq!(
+                section.flags(),
+                object::SectionFlags::Coff {
+                    characteristics: pe::SectionFlags(
+                        READ_ONLY_DATA_CHARACTERISTICS
+                    )
+                }
+            );
+            assert_eq!(section.file_range().unwrap().0 % 0x200, 0);
+
+            // SizeOfImage covers the new section.
+            assert_eq!(optional_header_u32(&output, 56), 0x3000);
+
+            // The checksum is valid.
+            let offset = checksum_offset(&output);
+            assert_eq!(
+                read_u32(&output, offset).unwrap(),
+                pe_checksum(&output, offset)
+            );
+            assert_ne!(read_u32(&output, offset).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_replace_section() {
+        let input = make_pe(
+            &[
+                TestSection::new(".text", &[0xc3]),
+                TestSection::new(".sbat", b"old,1\n"),
+                TestSection::new(".data", &[1, 2, 3]),
+            ],
+            true,
+        );
+        let output = add_section(&input, ".sbat", SBAT, false).unwrap();
+        assert_eq!(read_section(&output, ".sbat"), SBAT);
+        assert_eq!(read_section(&output, ".data"), [1, 2, 3]);
+        assert_eq!(output.len(), input.len());
+        assert_eq!(optional_header_u32(&output, 56), 0x4000);
+
+        // Too big to replace in place.
+        let big = vec![b'a'; 0x201];
+        assert!(add_section(&input, ".sbat", &big, false).is_err());
+    }
+
+    #[test]
+    fn test_signed() {
+        let mut input = make_pe(&[TestSection::new(".text", &[0xc3])], true);
+        let signature_offset = input.len();
+        input.extend([0xaa; 16]);
+
+        // Point the certificate table data directory at the signature.
+        let pe_offset = read_usize(&input, PE_OFFSET_OFFSET).unwrap();
+        let entry = pe_offset
+            + PE_HEADERS_SIZE
+            + 112
+            + pe::IMAGE_DIRECTORY_ENTRY_SECURITY * 8;
+        write_u32(&mut input, entry, signature_offset).unwrap();
+        write_u32(&mut input, entry + 4, 16).unwrap();
+
+        assert!(add_section(&input, ".sbat", SBAT, false).is_err());
+
+        let output = add_section(&input, ".sbat", SBAT, true).unwrap();
+        assert_eq!(read_section(&output, ".sbat"), SBAT);
+        assert_eq!(read_u32(&output, entry).unwrap(), 0);
+        assert_eq!(read_u32(&output, entry + 4).unwrap(), 0);
+        assert!(!output.windows(16).any(|w| w == [0xaa; 16]));
+    }
+
+    #[test]
+    fn test_no_room_for_header() {
+        // With 17 sections, the section table fills the headers.
+        let names: Vec<_> = (0..17).map(|i| format!(".s{i}")).collect();
+        let sections: Vec<_> = names
+            .iter()
+            .map(|name| TestSection::new(name, &[0]))
+            .collect();
+        let input = make_pe(&sections, true);
+        let err = add_section(&input, ".sbat", SBAT, false).unwrap_err();
+        assert!(err.to_string().contains("no room"), "{err}");
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(add_section(b"", ".sbat", SBAT, false).is_err());
+        assert!(add_section(b"MZ", ".sbat", SBAT, false).is_err());
+        let input = make_pe(&[], true);
+        assert!(add_section(&input, ".sbatlevel", SBAT, false).is_err());
+        assert!(add_section(&input, ".sbat", b"", false).is_err());
+
+        let pe_offset = read_usize(&input, PE_OFFSET_OFFSET).unwrap();
+        let coff = pe_offset + 4;
+        let opt = pe_offset + PE_HEADERS_SIZE;
+
+        // The optional header is too small for the data directories.
+        let mut data = input.clone();
+        write_u16(&mut data, coff + 16, 96);
+        let err = add_section(&data, ".sbat", SBAT, false).unwrap_err();
+        assert!(err.to_string().contains("too small"), "{err}");
+
+        // More data directories than fit in the optional header.
+        let mut data = input.clone();
+        write_u32(&mut data, opt + 108, 17).unwrap();
+        let err = add_section(&data, ".sbat", SBAT, false).unwrap_err();
+        assert!(err.to_string().contains("data directories"), "{err}");
+
+        // SizeOfHeaders is larger than the file.
+        let mut data = input;
+        data.truncate(opt + 240);
+        let err = add_section(&data, ".sbat", SBAT, false).unwrap_err();
+        assert!(err.to_string().contains("past the end"), "{err}");
+    }
+
+    #[test]
+    fn test_checksum() {
+        // Odd length, with the checksum field excluded.
+        let mut data = vec![0x01, 0x00, 0xff, 0xff, 0xff, 0xff, 0x02];
+        assert_eq!(pe_checksum(&data, 2), 1 + 2 + 7);
+        data[2] = 0;
+        assert_eq!(pe_checksum(&data, 2), 1 + 2 + 7);
+
+        // Carries are folded.
+        let data = [0xff, 0xff, 0, 0, 0, 0, 0x02, 0x00];
+        assert_eq!(pe_checksum(&data, 2), 0x0001 + 0x0001 + 8);
+    }
+}
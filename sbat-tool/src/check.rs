@@ -0,0 +1,437 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Check images against revocation data.
+
+use crate::input::find_pe_files;
+use crate::{
+    image_sbat_to_table_string, parse_error_to_string, pe_section,
+    read_pe_section, revocation_section_to_table_string,
+};
+use anyhow::{Context, Result, anyhow};
+use fs_err as fs;
+use sbat::variables::{Efivarfs, VariableName, VariableStore};
+use sbat::{
+    ImageSbat, REVOCATION_SECTION_NAME, RevocationIndex, RevocationSbatOwned,
+    RevocationSection, SBAT_SECTION_NAME,
+};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Length of a vendor GUID in an efivarfs file name.
+const EFIVAR_GUID_LEN: usize = 36;
+
+/// Where to get revocation data from.
+///
+/// If no source is given, the `SbatLevelRT` variable of the running
+/// system is read from efivarfs.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct RevocationArgs {
+    /// CSV file containing revocation data.
+    #[arg(long, conflicts_with_all = ["sbatlevel", "efivar"])]
+    pub revocations: Option<PathBuf>,
+
+    /// PE executable (typically shim) to read revocation data from. By
+    /// default the previous revocations in the '.sbatlevel' section are
+    /// used.
+    #[arg(long, conflicts_with = "efivar")]
+    pub sbatlevel: Option<PathBuf>,
+
+    /// Use the latest rather than the previous revocations from the
+    /// '.sbatlevel' section.
+    #[arg(long, requires = "sbatlevel")]
+    pub latest: bool,
+
+    /// File in efivarfs containing the revocation variable, named
+    /// 'Name-GUID'. If no revocation source is given, the running
+    /// system's SbatLevelRT-605dab50-e046-4300-abb6-3dd810dd8b23 is
+    /// used. Shim creates this runtime-accessible copy of the SbatLevel
+    /// variable, which is only accessible at boot time.
+    #[arg(long)]
+    pub efivar: Option<PathBuf>,
+}
+
+impl RevocationArgs {
+    /// Read and parse the revocation data.
+    pub fn load(&self) -> Result<RevocationSbatOwned> {
+        let parse = |path: &Path, data: &[u8]| {
+            RevocationSbatOwned::parse(data).map_err(|err| {
+                anyhow!(
+                    "{}: {}",
+                    path.display(),
+                    parse_error_to_string(data, &err)
+                )
+            })
+        };
+
+        if let Some(path) = &self.revocations {
+            parse(path, &fs::read(path)?)
+        } else if let Some(path) = &self.sbatlevel {
+            let data = read_pe_section(path, REVOCATION_SECTION_NAME)?;
+            let section = RevocationSection::parse(&data)
+                .with_context(|| format!("{}", path.display()))?;
+            if self.latest {
+                parse(path, section.latest())
+            } else {
+                parse(path, section.previous())
+            }
+        } else {
+            let (store, name) = match &self.efivar {
+                Some(path) => split_efivar_path(path)?,
+                None => (Efivarfs::default(), VariableName::SBAT_LEVEL_RT),
+            };
+            let path = store.path(name);
+            let variable = store
+                .get_variable(name)
+                .with_context(|| format!("{}", path.display()))?
+                .ok_or_else(|| {
+                    anyhow!("{}: variable does not exist", path.display())
+                })?;
+            parse(&path, &variable.data)
+        }
+    }
+}
+
+/// Split the path of a file in efivarfs into the directory, as an
+/// [`Efivarfs`], and the name of the variable. The file name is the
+/// variable name followed by `-` and the vendor GUID.
+fn split_efivar_path(path: &Path) -> Result<(Efivarfs, VariableName<'_>)> {
+    let invalid = || {
+        anyhow!(
+            "{}: file name is not in the 'Name-GUID' form",
+            path.display()
+        )
+    };
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(invalid)?;
+    let (name, vendor) = file_name
+        .len()
+        .checked_sub(EFIVAR_GUID_LEN)
+        .and_then(|mid| file_name.split_at_checked(mid))
+        .and_then(|(name, vendor)| Some((name.strip_suffix('-')?, vendor)))
+        .ok_or_else(invalid)?;
+    let root = path.parent().unwrap_or(Path::new(""));
+    Ok((Efivarfs::new(root), VariableName::new(name, vendor)))
+}
+
+/// Overall result of checking images.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum CheckStatus {
+    /// All images are allowed.
+    Allowed,
+
+    /// At least one image is revoked.
+    Revoked,
+
+    /// At least one image could not be checked.
+    Error,
+}
+
+impl CheckStatus {
+    /// Exit code when at least one image is revoked.
+    pub const REVOKED_EXIT_CODE: u8 = 3;
+
+    /// Exit code when at least one image could not be checked. This is
+    /// the same code used when the command fails entirely.
+    pub const ERROR_EXIT_CODE: u8 = 1;
+
+    pub fn exit_code(self) -> ExitCode {
+        match self {
+            Self::Allowed => ExitCode::SUCCESS,
+            Self::Revoked => ExitCode::from(Self::REVOKED_EXIT_CODE),
+            Self::Error => ExitCode::from(Self::ERROR_EXIT_CODE),
+        }
+    }
+}
+
+/// Check `image_sbat` against the revocations in `index`, writing
+/// `name: Allowed` or `name: Revoked` to `out`. Each revoked component
+/// is listed on its own line after `name: Revoked`.
+pub fn check_image_sbat(
+    index: &RevocationIndex,
+    name: &str,
+    image_sbat: &ImageSbat,
+    out: &mut impl Write,
+) -> Result<CheckStatus> {
+    let revoked = index.validate_image_report(image_sbat);
+    if revoked.is_empty() {
+        writeln!(out, "{name}: Allowed")?;
+        return Ok(CheckStatus::Allowed);
+    }
+
+    writeln!(out, "{name}: Revoked")?;
+    for revoked_entry in revoked {
+        writeln!(
+            out,
+            "  {}: generation {} is less than {}",
+            revoked_entry.entry.component.name,
+            revoked_entry.image_generation(),
+            revoked_entry.minimum_generation(),
+        )?;
+    }
+    Ok(CheckStatus::Revoked)
+}
+
+/// Check the `.sbat` section of the PE executable `pe` against the
+/// revocations in `index`, writing the result to `out`. Each line of
+/// output starts with `name`. If `show_sbat` is true, the SBAT entries
+/// are also printed as a table, as is the `.sbatlevel` section if the
+/// executable has one.
+///
+/// Errors in the executable are written to `out` and reported as
+/// [`CheckStatus::Error`]; only errors writing the output are returned.
+pub fn check_pe(
+    index: &RevocationIndex,
+    name: &str,
+    pe: &[u8],
+    show_sbat: bool,
+    out: &mut impl Write,
+) -> Result<CheckStatus> {
+    let data = match pe_section(pe, SBAT_SECTION_NAME) {
+        Ok(data) => data,
+        Err(err) => {
+            writeln!(out, "{name}: Error: {err}")?;
+            return Ok(CheckStatus::Error);
+        }
+    };
+    let image_sbat = match ImageSbat::parse(data) {
+        Ok(image_sbat) => image_sbat,
+        Err(err) => {
+            let err = parse_error_to_string(data, &err);
+            writeln!(out, "{name}: Error: {err}")?;
+            return Ok(CheckStatus::Error);
+        }
+    };
+
+    let mut status = check_image_sbat(index, name, image_sbat, out)?;
+
+    if show_sbat {
+        writeln!(out, "{}", image_sbat_to_table_string(image_sbat))?;
+
+        // Shim also embeds revocation data.
+        if let Ok(data) = pe_section(pe, REVOCATION_SECTION_NAME) {
+            match revocation_section_to_table_string(data) {
+                Ok(table) => writeln!(out, "{table}")?,
+                Err(err) => {
+                    writeln!(
+                        out,
+                        "{name}: Error: {REVOCATION_SECTION_NAME}: {err:#}"
+                    )?;
+                    status = CheckStatus::Error;
+                }
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Check a PE executable passed to a [`FoundFn`] callback. An error
+/// reading the executable is written to `out`.
+///
+/// [`FoundFn`]: crate::input::FoundFn
+pub fn check_found(
+    index: &RevocationIndex,
+    name: &str,
+    data: Result<Vec<u8>>,
+    show_sbat: bool,
+    out: &mut impl Write,
+) -> Result<CheckStatus> {
+    match data {
+        Ok(data) => check_pe(index, name, &data, show_sbat, out),
+        Err(err) => {
+            writeln!(out, "{name}: Error: {err:#}")?;
+            Ok(CheckStatus::Error)
+        }
+    }
+}
+
+/// Check the `.sbat` section of each image in `inputs` against the
+/// revocation data. Failures to read or parse an image are reported,
+/// but don't stop the remaining images from being checked.
+///
+/// Inputs that are FAT filesystem images, GPT disk images, or ISO9660
+/// images are read directly, and each PE executable in them is checked.
+///
+/// The returned status is the most severe of all the images: an error
+/// takes precedence over a revoked image.
+pub fn check_images(
+    revocation_args: &RevocationArgs,
+    inputs: &[PathBuf],
+    out: &mut impl Write,
+) -> Result<CheckStatus> {
+    let revocations = revocation_args.load()?;
+    let index = RevocationIndex::new(&revocations);
+
+    let mut status = CheckStatus::Allowed;
+    for input in inputs {
+        find_pe_files(input, true, &mut |name, data| {
+            status = status.max(check_found(&index, name, data, false, out)?);
+            Ok(())
+        })?;
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::ESP_TYPE_GUID;
+    use crate::test_util::{
+        TestSection, csv_revocation_args, make_fat, make_gpt, make_pe,
+        make_sbat_pe,
+    };
+    use sbat::RevocationSbat;
+    use tempfile::TempDir;
+
+    const REVOCATIONS: &[u8] = b"sbat,1,2023012900\nshim,2\ngrub,3\n";
+
+    /// Write test images to a temporary directory, returning their
+    /// paths.
+    fn write_images(dir: &TempDir) -> Vec<PathBuf> {
+        let files: &[(&str, Vec<u8>)] = &[
+            (
+                "allowed.efi",
+                make_sbat_pe(b"sbat,1,S,s,1,u\ngrub,3,G,g,1,u\n"),
+            ),
+            (
+                "revoked.efi",
+                make_sbat_pe(b"sbat,1,S,s,1,u\ngrub,2,G,g,1,u\n"),
+            ),
+            ("invalid.efi", make_sbat_pe(b"sbat,x\n")),
+            ("no-section.efi", make_pe(&[], true)),
+        ];
+        files
+            .iter()
+            .map(|(name, data)| {
+                let path = dir.path().join(name);
+                fs::write(&path, data).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn check(
+        args: &RevocationArgs,
+        inputs: &[PathBuf],
+    ) -> (CheckStatus, String) {
+        let mut out = Vec::new();
+        let status = check_images(args, inputs, &mut out).unwrap();
+        (status, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_check_images() {
+        let dir = TempDir::new().unwrap();
+        let images = write_images(&dir);
+        let args = csv_revocation_args(dir.path(), REVOCATIONS);
+
+        let (status, out) = check(&args, &images[..1]);
+        assert_eq!(status, CheckStatus::Allowed);
+        assert!(out.ends_with("allowed.efi: Allowed\n"), "{out}");
+
+        let (status, out) = check(&args, &images[..2]);
+        assert_eq!(status, CheckStatus::Revoked);
+        assert!(
+            out.ends_with(
+                "revoked.efi: Revoked\n  grub: generation 2 is less than 3\n"
+            ),
+            "{out}"
+        );
+
+        // Errors don't stop other images from being checked.
+        let (status, out) = check(&args, &images);
+        assert_eq!(status, CheckStatus::Error);
+        assert_eq!(out.lines().count(), 5 + 2, "{out}");
+        assert!(out.contains("invalid.efi: Error: line 1"), "{out}");
+        assert!(out.contains("no-section.efi: Error: missing"), "{out}");
+    }
+
+    #[test]
+    fn test_check_disk_image() {
+        let dir = TempDir::new().unwrap();
+        let args = csv_revocation_args(dir.path(), REVOCATIONS);
+
+        let shim = make_sbat_pe(b"sbat,1,S,s,1,u\nshim,2,S,s,1,u\n");
+        let grub = make_sbat_pe(b"sbat,1,S,s,1,u\ngrub,2,G,g,1,u\n");
+        let esp = make_fat(
+            fatfs::FatType::Fat16,
+            &[
+                ("EFI/BOOT/BOOTX64.EFI", &shim),
+                ("EFI/BOOT/grubx64.efi", &grub),
+            ],
+        );
+        let disk = dir.path().join("disk.img");
+        fs::write(&disk, make_gpt(&[(ESP_TYPE_GUID, &esp)])).unwrap();
+
+        let (status, out) = check(&args, &[disk]);
+        assert_eq!(status, CheckStatus::Revoked);
+        let out = out.replace(dir.path().to_str().unwrap(), "");
+        assert_eq!(
+            out,
+            "/disk.img (partition 1):/EFI/BOOT/BOOTX64.EFI: Allowed\n\
+             /disk.img (partition 1):/EFI/BOOT/grubx64.efi: Revoked\n  \
+             grub: generation 2 is less than 3\n"
+        );
+    }
+
+    #[test]
+    fn test_revocation_sources() {
+        let dir = TempDir::new().unwrap();
+        let images = write_images(&dir);
+
+        // Previous and latest revocations in a '.sbatlevel' section.
+        let previous = RevocationSbat::parse(b"sbat,1,2022111500\n").unwrap();
+        let latest = RevocationSbat::parse(REVOCATIONS).unwrap();
+        let section = RevocationSection::new(previous, latest).to_bytes();
+        let shim = dir.path().join("shim.efi");
+        fs::write(
+            &shim,
+            make_pe(&[TestSection::new(".sbatlevel", &section)], true),
+        )
+        .unwrap();
+        let mut args = RevocationArgs {
+            sbatlevel: Some(shim),
+            ..Default::default()
+        };
+        assert_eq!(check(&args, &images[1..2]).0, CheckStatus::Allowed);
+        args.latest = true;
+        assert_eq!(check(&args, &images[1..2]).0, CheckStatus::Revoked);
+
+        // Variable in efivarfs, with the attributes prefix.
+        let efivar = dir
+            .path()
+            .join("SbatLevelRT-605dab50-e046-4300-abb6-3dd810dd8b23");
+        let mut data = 7u32.to_le_bytes().to_vec();
+        data.extend(REVOCATIONS);
+        fs::write(&efivar, data).unwrap();
+        let args = RevocationArgs {
+            efivar: Some(efivar.clone()),
+            ..Default::default()
+        };
+        assert_eq!(check(&args, &images[1..2]).0, CheckStatus::Revoked);
+
+        // Invalid revocation data is an error for the whole command.
+        fs::write(&efivar, b"\0\0").unwrap();
+        assert!(check_images(&args, &images, &mut Vec::new()).is_err());
+        fs::write(&efivar, b"\0\0\0\0sbat,x").unwrap();
+        assert!(check_images(&args, &images, &mut Vec::new()).is_err());
+
+        // Missing variable, and a file name without a GUID.
+        fs::remove_file(&efivar).unwrap();
+        assert!(check_images(&args, &images, &mut Vec::new()).is_err());
+        let args = RevocationArgs {
+            efivar: Some(dir.path().join("SbatLevelRT")),
+            ..Default::default()
+        };
+        let err = args.load().unwrap_err();
+        assert!(err.to_string().contains("'Name-GUID'"), "{err}");
+    }
+}
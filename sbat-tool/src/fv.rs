@@ -0,0 +1,577 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Find PE32 and TE images in UEFI firmware volumes.
+//!
+//! Firmware volumes (as built by EDK2, for example OVMF) contain FFS
+//! files, which in turn contain sections. Images are found in PE32 and
+//! TE sections, including those nested inside uncompressed compression
+//! sections, GUID-defined sections that don't require processing, and
+//! firmware volume image sections. Sections that are actually
+//! compressed (typically with LZMA) are skipped.
+//!
+//! See volume 3 of the UEFI Platform Initialization specification for
+//! the formats.
+
+use anyhow::{Context, Result, bail, ensure};
+use std::fmt::Write;
+
+/// Offset of the signature in a firmware volume header.
+const FV_SIGNATURE_OFFSET: usize = 40;
+
+/// Firmware volume header signature.
+const FV_SIGNATURE: &[u8] = b"_FVH";
+
+/// Size of the fixed part of a firmware volume header.
+const FV_HEADER_SIZE: usize = 56;
+
+/// Firmware volume attribute: erased flash reads as 1 bits.
+const FVB2_ERASE_POLARITY: u32 = 0x800;
+
+/// File system GUID for FFS version 2
+/// (8C8CE578-8A3D-4F1C-9935-896185C32DD3), in its on-disk byte order.
+const FFS2_GUID: [u8; 16] = [
+    0x78, 0xe5, 0x8c, 0x8c, 0x3d, 0x8a, 0x1c, 0x4f, 0x99, 0x35, 0x89, 0x61,
+    0x85, 0xc3, 0x2d, 0xd3,
+];
+
+/// File system GUID for FFS version 3
+/// (5473C07A-3DCB-4DCA-BD6F-1E9689E7349A), in its on-disk byte order.
+const FFS3_GUID: [u8; 16] = [
+    0x7a, 0xc0, 0x73, 0x54, 0xcb, 0x3d, 0xca, 0x4d, 0xbd, 0x6f, 0x1e, 0x96,
+    0x89, 0xe7, 0x34, 0x9a,
+];
+
+/// Size of an FFS file header.
+const FILE_HEADER_SIZE: usize = 24;
+
+/// Size of an FFS file header for large files (FFS3 only).
+const LARGE_FILE_HEADER_SIZE: usize = 32;
+
+/// File attribute: the file has a large file header.
+const FFS_ATTRIB_LARGE_FILE: u8 = 0x01;
+
+/// File state: the file data is valid.
+const FILE_DATA_VALID: u8 = 0x04;
+
+/// File state: the file has been deleted.
+const FILE_DELETED: u8 = 0x10;
+
+/// File type of raw files, which don't contain sections.
+const FILE_TYPE_RAW: u8 = 0x01;
+
+/// File type of padding files.
+const FILE_TYPE_PAD: u8 = 0xf0;
+
+/// Section types.
+const SECTION_COMPRESSION: u8 = 0x01;
+const SECTION_GUID_DEFINED: u8 = 0x02;
+const SECTION_PE32: u8 = 0x10;
+const SECTION_TE: u8 = 0x12;
+const SECTION_USER_INTERFACE: u8 = 0x15;
+const SECTION_FIRMWARE_VOLUME_IMAGE: u8 = 0x17;
+
+/// Compression type of a compression section that is not compressed.
+const NOT_COMPRESSED: u8 = 0;
+
+/// GUID-defined section attribute: the section data must be processed
+/// (for example, decompressed) before use.
+const GUIDED_SECTION_PROCESSING_REQUIRED: u16 = 0x01;
+
+/// Maximum nesting of firmware volumes and encapsulation sections.
+const MAX_DEPTH: usize = 16;
+
+/// Size of a TE image header.
+const TE_HEADER_SIZE: usize = 40;
+
+/// Size of a PE section header.
+const SECTION_HEADER_SIZE: usize = 40;
+
+/// Kind of image found in a firmware volume.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImageKind {
+    Pe32,
+    Te,
+}
+
+/// PE32 or TE image found in a firmware volume.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FvImage {
+    /// GUID of the FFS file containing the image.
+    pub file_name: String,
+
+    /// Name from the file's user interface section, if any.
+    pub ui_name: Option<String>,
+
+    pub kind: ImageKind,
+
+    pub data: Vec<u8>,
+}
+
+impl FvImage {
+    /// Get a name for the image: the file GUID, followed by the user
+    /// interface name in parentheses if there is one.
+    pub fn name(&self) -> String {
+        match &self.ui_name {
+            Some(ui_name) => format!("{} ({ui_name})", self.file_name),
+            None => self.file_name.clone(),
+        }
+    }
+}
+
+/// Check whether `header`, the start of a file, is a firmware volume.
+pub fn is_firmware_volume(header: &[u8]) -> bool {
+    header.get(FV_SIGNATURE_OFFSET..FV_SIGNATURE_OFFSET + FV_SIGNATURE.len())
+        == Some(FV_SIGNATURE)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u24_at(data: &[u8], offset: usize) -> usize {
+    usize::from(data[offset])
+        | usize::from(data[offset + 1]) << 8
+        | usize::from(data[offset + 2]) << 16
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Format a GUID stored in the on-disk (mixed-endian) byte order.
+fn format_guid(guid: &[u8]) -> String {
+    let mut s = format!(
+        "{:08x}-{:04x}-{:04x}-",
+        u32_at(guid, 0),
+        u16_at(guid, 4),
+        u16_at(guid, 6)
+    );
+    for (i, b) in guid[8..16].iter().enumerate() {
+        if i == 2 {
+            s.push('-');
+        }
+        write!(s, "{b:02x}").unwrap();
+    }
+    s
+}
+
+/// Images and errors found while parsing, in order.
+type Found = Vec<Result<FvImage>>;
+
+/// Find all PE32 and TE images in `data`, which contains one or more
+/// consecutive firmware volumes (such as an EDK2 `.fd` file).
+///
+/// Errors in one part of the data don't stop the rest from being
+/// parsed; they are returned in order with the images.
+pub fn find_images(data: &[u8]) -> Vec<Result<FvImage>> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+    while let Some(rest) = data.get(offset..) {
+        if !is_firmware_volume(rest) {
+            break;
+        }
+        match parse_volume(rest, 0, &mut found) {
+            Ok(len) => offset += len,
+            Err(err) => {
+                found.push(Err(err));
+                break;
+            }
+        }
+    }
+    if found.is_empty() && offset == 0 {
+        found.push(Err(anyhow::anyhow!("invalid firmware volume")));
+    }
+    found
+}
+
+/// Parse the firmware volume at the start of `data`, returning its
+/// length.
+fn parse_volume(data: &[u8], depth: usize, found: &mut Found) -> Result<usize> {
+    ensure!(depth <= MAX_DEPTH, "firmware volumes are nested too deeply");
+    ensure!(
+        data.len() >= FV_HEADER_SIZE && is_firmware_volume(data),
+        "invalid firmware volume header"
+    );
+
+    let fv_len = usize::try_from(u64_at(data, 32))?;
+    let header_len = usize::from(u16_at(data, 48));
+    ensure!(
+        fv_len <= data.len()
+            && header_len >= FV_HEADER_SIZE
+            && header_len <= fv_len,
+        "invalid firmware volume length"
+    );
+    let data = &data[..fv_len];
+
+    let guid = &data[16..32];
+    let ffs3 = match guid.try_into()? {
+        FFS2_GUID => false,
+        FFS3_GUID => true,
+        _ => bail!("unsupported firmware file system {}", format_guid(guid)),
+    };
+    let erase_polarity = u32_at(data, 44) & FVB2_ERASE_POLARITY != 0;
+    let erased_byte = if erase_polarity { 0xff } else { 0 };
+
+    // Files start after the extended header, if present.
+    let ext_header_offset = usize::from(u16_at(data, 52));
+    let mut offset = if ext_header_offset == 0 {
+        header_len
+    } else {
+        let size = data
+            .get(ext_header_offset + 16..ext_header_offset + 20)
+            .context("invalid firmware volume extended header")?;
+        ext_header_offset + usize::try_from(u32_at(size, 0))?
+    };
+
+    loop {
+        offset = offset.next_multiple_of(8);
+        let Some(header) = data.get(offset..offset + FILE_HEADER_SIZE) else {
+            break;
+        };
+        // The rest of the volume is free space.
+        if header.iter().all(|b| *b == erased_byte) {
+            break;
+        }
+
+        let attributes = header[19];
+        let (header_size, size) =
+            if ffs3 && attributes & FFS_ATTRIB_LARGE_FILE != 0 {
+                let size = data
+                    .get(offset + 24..offset + 32)
+                    .context("invalid FFS file header")?;
+                (LARGE_FILE_HEADER_SIZE, usize::try_from(u64_at(size, 0))?)
+            } else {
+                (FILE_HEADER_SIZE, u24_at(header, 20))
+            };
+        let file = data
+            .get(offset..offset.saturating_add(size))
+            .filter(|_| size >= header_size)
+            .with_context(|| {
+                format!("invalid size of FFS file {}", format_guid(header))
+            })?;
+        offset += size;
+
+        let state = if erase_polarity {
+            !header[23]
+        } else {
+            header[23]
+        };
+        if state & FILE_DATA_VALID == 0 || state & FILE_DELETED != 0 {
+            continue;
+        }
+        if matches!(header[18], FILE_TYPE_RAW | FILE_TYPE_PAD) {
+            continue;
+        }
+
+        let file_name = format_guid(&header[..16]);
+        let mut ui_name = None;
+        let mut images = Vec::new();
+        let sections = &file[header_size..];
+        if let Err(err) =
+            parse_sections(sections, depth, &mut ui_name, &mut images, found)
+        {
+            found.push(Err(err.context(file_name.clone())));
+        }
+        found.extend(images.into_iter().map(|(kind, data)| {
+            Ok(FvImage {
+                file_name: file_name.clone(),
+                ui_name: ui_name.clone(),
+                kind,
+                data,
+            })
+        }));
+    }
+
+    Ok(fv_len)
+}
+
+/// Parse the sections of an FFS file (or of an encapsulation section).
+/// Images are added to `images` and the user interface name is stored
+/// in `ui_name`; images in nested firmware volumes are added to
+/// `found`.
+fn parse_sections(
+    data: &[u8],
+    depth: usize,
+    ui_name: &mut Option<String>,
+    images: &mut Vec<(ImageKind, Vec<u8>)>,
+    found: &mut Found,
+) -> Result<()> {
+    ensure!(depth <= MAX_DEPTH, "sections are nested too deeply");
+
+    let mut offset = 0;
+    while offset + 4 <= data.len() {
+        let (header_size, size) = match u24_at(data, offset) {
+            0xff_ffff => {
+                let size = data
+                    .get(offset + 4..offset + 8)
+                    .context("invalid section header")?;
+                (8, usize::try_from(u32_at(size, 0))?)
+            }
+            size => (4, size),
+        };
+        let section_type = data[offset + 3];
+        let section = data
+            .get(offset..offset.saturating_add(size))
+            .filter(|_| size >= header_size)
+            .context("invalid section size")?;
+        let body = &section[header_size..];
+        offset = (offset + size).next_multiple_of(4);
+
+        match section_type {
+            SECTION_PE32 => images.push((ImageKind::Pe32, body.to_vec())),
+            SECTION_TE => images.push((ImageKind::Te, body.to_vec())),
+            SECTION_USER_INTERFACE => {
+                let units = body
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|c| *c != 0);
+                *ui_name = Some(
+                    char::decode_utf16(units)
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect(),
+                );
+            }
+            SECTION_COMPRESSION => {
+                // Uncompressed length (u32), then compression type (u8).
+                let compression_type =
+                    *body.get(4).context("invalid compression section")?;
+                if compression_type == NOT_COMPRESSED {
+                    parse_sections(
+                        &body[5..],
+                        depth + 1,
+                        ui_name,
+                        images,
+                        found,
+                    )?;
+                }
+            }
+            SECTION_GUID_DEFINED => {
+                // GUID, then data offset (relative to the start of the
+                // section) and attributes.
+                let header =
+                    body.get(..20).context("invalid GUID-defined section")?;
+                let data_offset = usize::from(u16_at(header, 16));
+                let attributes = u16_at(header, 18);
+                if attributes & GUIDED_SECTION_PROCESSING_REQUIRED == 0 {
+                    let nested = section
+                        .get(data_offset..)
+                        .context("invalid GUID-defined section data offset")?;
+                    parse_sections(nested, depth + 1, ui_name, images, found)?;
+                }
+            }
+            SECTION_FIRMWARE_VOLUME_IMAGE => {
+                parse_volume(body, depth + 1, found)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Get the data of the section named `section_name` in the TE image
+/// `data`.
+///
+/// TE images are PE images with the headers replaced by a smaller TE
+/// header. Section file offsets are relative to the original PE image,
+/// so they are adjusted by the number of bytes stripped from the
+/// headers.
+pub fn te_section<'a>(data: &'a [u8], section_name: &str) -> Result<&'a [u8]> {
+    ensure!(
+        data.len() >= TE_HEADER_SIZE && data.starts_with(b"VZ"),
+        "invalid TE header"
+    );
+    let num_sections = usize::from(data[4]);
+    let stripped_size = usize::from(u16_at(data, 6));
+
+    for i in 0..num_sections {
+        let start = TE_HEADER_SIZE + i * SECTION_HEADER_SIZE;
+        let header = data
+            .get(start..start + SECTION_HEADER_SIZE)
+            .context("TE section table is truncated")?;
+        let name_len = header[..8].iter().position(|b| *b == 0).unwrap_or(8);
+        if &header[..name_len] != section_name.as_bytes() {
+            continue;
+        }
+
+        let virtual_size = usize::try_from(u32_at(header, 8))?;
+        let raw_size = usize::try_from(u32_at(header, 16))?;
+        let size = match virtual_size {
+            0 => raw_size,
+            virtual_size => virtual_size.min(raw_size),
+        };
+        let offset = usize::try_from(u32_at(header, 20))?
+            .checked_sub(stripped_size)
+            .and_then(|offset| offset.checked_add(TE_HEADER_SIZE))
+            .context("invalid TE section offset")?;
+        return data
+            .get(offset..offset.saturating_add(size))
+            .context("TE section data is out of bounds");
+    }
+    bail!("missing '{section_name}' section")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        TestSection, make_ffs_section, make_fv, make_sbat_pe, make_te,
+    };
+
+    fn guid(n: u8) -> [u8; 16] {
+        let mut guid = [0; 16];
+        guid[0] = n;
+        guid
+    }
+
+    fn pe_section(sbat: &[u8]) -> Vec<u8> {
+        make_ffs_section(SECTION_PE32, &make_sbat_pe(sbat))
+    }
+
+    fn ui_section(name: &str) -> Vec<u8> {
+        let mut body: Vec<u8> =
+            name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        body.extend([0, 0]);
+        make_ffs_section(SECTION_USER_INTERFACE, &body)
+    }
+
+    /// Get the name and SBAT data of each image found, or the error.
+    fn find(data: &[u8]) -> Vec<String> {
+        find_images(data)
+            .into_iter()
+            .map(|image| match image {
+                Ok(image) => {
+                    let sbat = match image.kind {
+                        ImageKind::Pe32 => {
+                            crate::pe_section(&image.data, ".sbat")
+                        }
+                        ImageKind::Te => te_section(&image.data, ".sbat"),
+                    };
+                    let sbat = String::from_utf8_lossy(sbat.unwrap());
+                    format!("{}: {sbat}", image.name())
+                }
+                Err(err) => format!("Error: {err:#}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_find_images() {
+        let te = make_te(&[TestSection::new(".sbat", b"te")]);
+        let guid_defined = {
+            // GUID, data offset, attributes.
+            let mut body = vec![0; 16];
+            body.extend(24u16.to_le_bytes());
+            body.extend(0u16.to_le_bytes());
+            body.extend(pe_section(b"guided"));
+            make_ffs_section(SECTION_GUID_DEFINED, &body)
+        };
+        let guid_defined_lzma = {
+            let mut body = vec![0; 16];
+            body.extend(24u16.to_le_bytes());
+            body.extend(GUIDED_SECTION_PROCESSING_REQUIRED.to_le_bytes());
+            body.extend(b"lzma data");
+            make_ffs_section(SECTION_GUID_DEFINED, &body)
+        };
+        let compressed = {
+            let mut body = 0u32.to_le_bytes().to_vec();
+            body.push(2);
+            body.extend(b"compressed data");
+            make_ffs_section(SECTION_COMPRESSION, &body)
+        };
+        let uncompressed = {
+            let mut body = 0u32.to_le_bytes().to_vec();
+            body.push(NOT_COMPRESSED);
+            body.extend(pe_section(b"uncompressed"));
+            make_ffs_section(SECTION_COMPRESSION, &body)
+        };
+        let nested_fv = make_fv(&[(guid(5), 0x07, &[pe_section(b"nested")])]);
+
+        let fv = make_fv(&[
+            (guid(1), 0x09, &[pe_section(b"app"), ui_section("Shell")]),
+            (guid(2), 0x06, &[make_ffs_section(SECTION_TE, &te)]),
+            (
+                guid(3),
+                0x07,
+                &[guid_defined, guid_defined_lzma, compressed, uncompressed],
+            ),
+            (
+                guid(4),
+                0x0b,
+                &[make_ffs_section(SECTION_FIRMWARE_VOLUME_IMAGE, &nested_fv)],
+            ),
+            (guid(6), FILE_TYPE_RAW, &[make_sbat_pe(b"raw")]),
+        ]);
+
+        let name = |n: u8| format!("{n:08x}-0000-0000-0000-000000000000");
+        let expected = [
+            format!("{} (Shell): app", name(1)),
+            format!("{}: te", name(2)),
+            format!("{}: guided", name(3)),
+            format!("{}: uncompressed", name(3)),
+            format!("{}: nested", name(5)),
+        ];
+        assert!(is_firmware_volume(&fv));
+        assert_eq!(find(&fv), expected);
+
+        // Volumes can be concatenated, as in OVMF.fd.
+        let mut fd = make_fv(&[]);
+        fd.extend(&fv);
+        assert_eq!(find(&fd), expected);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(find(b""), ["Error: invalid firmware volume"]);
+
+        // File size past the end of the volume.
+        let mut fv = make_fv(&[(guid(1), 0x07, &[pe_section(b"a")])]);
+        fv[72 + 22] = 0x10;
+        let found = find(&fv);
+        assert_eq!(found.len(), 1);
+        assert!(
+            found[0].starts_with("Error: invalid size of FFS file"),
+            "{found:?}"
+        );
+
+        // An invalid section in one file doesn't stop others from being
+        // found.
+        let fv = make_fv(&[
+            (guid(1), 0x07, &[vec![0xff, 0, 0, SECTION_PE32]]),
+            (guid(2), 0x07, &[pe_section(b"ok")]),
+        ]);
+        let found = find(&fv);
+        assert_eq!(found.len(), 2);
+        assert!(found[0].contains("invalid section size"), "{found:?}");
+        assert!(found[1].ends_with(": ok"), "{found:?}");
+    }
+
+    #[test]
+    fn test_te_section() {
+        let te = make_te(&[
+            TestSection::new(".text", &[0xc3]),
+            TestSection::new(".sbat", b"sbat,1\n"),
+        ]);
+        assert_eq!(te_section(&te, ".sbat").unwrap(), b"sbat,1\n");
+        assert!(te_section(&te, ".data").is_err());
+        assert!(te_section(b"VZ", ".sbat").is_err());
+        assert!(te_section(&te[..TE_HEADER_SIZE + 10], ".sbat").is_err());
+    }
+
+    #[test]
+    fn test_format_guid() {
+        assert_eq!(
+            format_guid(&FFS2_GUID),
+            "8c8ce578-8a3d-4f1c-9935-896185c32dd3"
+        );
+    }
+}
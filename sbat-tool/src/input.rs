@@ -0,0 +1,757 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Find PE executables in input files.
+//!
+//! An input file can be a PE executable, a FAT12/16/32 filesystem image
+//! (such as an EFI System Partition image), a GPT disk image containing
+//! an EFI System Partition, or an ISO9660 image with El Torito EFI boot
+//! images. Images are read directly, without mounting them. UEFI
+//! firmware volumes (such as OVMF.fd) are also supported, in which case
+//! the PE32 and TE images of firmware drivers and applications are
+//! found.
+
+use crate::fv::{self, is_firmware_volume};
+use crate::iso::{IsoVolume, is_iso9660};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use fs_err as fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Number of bytes at the start of a file used to detect its kind. This
+/// is enough to include the first ISO9660 volume descriptor.
+const HEADER_SIZE: usize = 36 * 1024;
+
+/// Signature of a GPT header.
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+
+/// Logical block sizes to look for a GPT header with.
+const GPT_SECTOR_SIZES: [u64; 2] = [512, 4096];
+
+/// Partition type GUID of an EFI System Partition
+/// (C12A7328-F81F-11D2-BA4B-00A0C93EC93B), in its on-disk byte order.
+pub const ESP_TYPE_GUID: [u8; 16] = [
+    0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0,
+    0xc9, 0x3e, 0xc9, 0x3b,
+];
+
+/// Upper limit on the number of GPT partition entries, to avoid huge
+/// allocations for corrupt headers.
+const MAX_GPT_ENTRIES: u32 = 1024;
+
+/// Maximum nesting of directories in a FAT filesystem. The FAT driver
+/// doesn't expose the cluster of a directory, so loops in corrupt
+/// images are cut off by depth rather than by tracking visited
+/// directories.
+const MAX_FAT_DEPTH: usize = 16;
+
+/// Upper limit on the number of directories read from a FAT filesystem,
+/// so that loops with several entries per directory also end quickly.
+const MAX_FAT_DIRS: usize = 4096;
+
+/// Callback for each PE executable found. The first argument is the
+/// name of the executable, and the second is its contents, or an error
+/// that prevented reading it.
+pub type FoundFn<'a> = dyn FnMut(&str, Result<Vec<u8>>) -> Result<()> + 'a;
+
+/// Kind of an input file, detected from its contents.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum InputKind {
+    Pe,
+    Fat,
+    Gpt { sector_size: u64 },
+    Iso,
+    FirmwareVolume,
+    Other,
+}
+
+impl InputKind {
+    fn detect(header: &[u8]) -> Self {
+        // Hybrid ISO images also have a partition table, but the ISO9660
+        // filesystem is more complete, so check for it first.
+        if is_iso9660(header) {
+            return Self::Iso;
+        }
+        if is_firmware_volume(header) {
+            return Self::FirmwareVolume;
+        }
+        // Check for GPT before FAT, since the protective MBR looks
+        // similar to a FAT boot sector.
+        for sector_size in GPT_SECTOR_SIZES {
+            let start = usize::try_from(sector_size).unwrap();
+            if header.get(start..start + GPT_SIGNATURE.len())
+                == Some(GPT_SIGNATURE)
+            {
+                return Self::Gpt { sector_size };
+            }
+        }
+        if is_fat_boot_sector(header) {
+            return Self::Fat;
+        }
+        if header.starts_with(b"MZ") {
+            return Self::Pe;
+        }
+        Self::Other
+    }
+}
+
+/// Check whether `sector` looks like the boot sector of a FAT
+/// filesystem. This is a quick check to decide whether to try reading
+/// the filesystem; the full validation is done by [`fatfs`].
+fn is_fat_boot_sector(sector: &[u8]) -> bool {
+    if sector.len() < 512 || sector[510..512] != [0x55, 0xaa] {
+        return false;
+    }
+    let jump = sector[0] == 0xeb || sector[0] == 0xe9;
+    let bytes_per_sector = u16::from_le_bytes([sector[11], sector[12]]);
+    let sectors_per_cluster = sector[13];
+    let num_fats = sector[16];
+    jump && matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+        && sectors_per_cluster.is_power_of_two()
+        && matches!(num_fats, 1 | 2)
+}
+
+/// Read-only view of a range of bytes in a stream.
+///
+/// [`fatfs`] requires a writable stream, so this implements [`Write`],
+/// but all writes fail.
+pub struct StreamSlice<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<R: Read + Seek> StreamSlice<R> {
+    pub fn new(inner: R, start: u64, len: u64) -> Self {
+        Self {
+            inner,
+            start,
+            len,
+            pos: 0,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for StreamSlice<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let len = usize::try_from(remaining).unwrap_or(usize::MAX);
+        let len = len.min(buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        self.inner.seek(SeekFrom::Start(self.start + self.pos))?;
+        let n = self.inner.read(&mut buf[..len])?;
+        self.pos += u64::try_from(n).unwrap();
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for StreamSlice<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.len, offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        self.pos = base.checked_add_signed(offset).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "invalid seek")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+impl<R> Write for StreamSlice<R> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "image is read-only",
+        ))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Partition in a GPT disk image.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct GptPartition {
+    /// One-based index of the partition.
+    number: u32,
+    type_guid: [u8; 16],
+    first_lba: u64,
+    last_lba: u64,
+}
+
+impl GptPartition {
+    fn is_esp(&self) -> bool {
+        self.type_guid == ESP_TYPE_GUID
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Read the partition table of a GPT disk image. Unused entries are
+/// skipped.
+///
+/// Only the primary GPT header is read, and checksums are not verified.
+fn read_gpt_partitions(
+    disk: &mut (impl Read + Seek),
+    sector_size: u64,
+) -> Result<Vec<GptPartition>> {
+    let mut header = [0; 92];
+    disk.seek(SeekFrom::Start(sector_size))?;
+    disk.read_exact(&mut header)
+        .context("GPT header is truncated")?;
+
+    let entries_lba = u64_at(&header, 72);
+    let num_entries = u32_at(&header, 80);
+    let entry_size = u32_at(&header, 84);
+    if num_entries > MAX_GPT_ENTRIES || entry_size < 128 {
+        bail!("invalid GPT header: {num_entries} entries of size {entry_size}");
+    }
+
+    let entries_offset = entries_lba
+        .checked_mul(sector_size)
+        .context("invalid GPT partition entry LBA")?;
+    disk.seek(SeekFrom::Start(entries_offset))?;
+    let mut entry = vec![0; usize::try_from(entry_size)?];
+    let mut partitions = Vec::new();
+    for number in 1..=num_entries {
+        disk.read_exact(&mut entry)
+            .context("GPT partition entries are truncated")?;
+        let type_guid: [u8; 16] = entry[..16].try_into()?;
+        if type_guid == [0; 16] {
+            continue;
+        }
+        partitions.push(GptPartition {
+            number,
+            type_guid,
+            first_lba: u64_at(&entry, 32),
+            last_lba: u64_at(&entry, 40),
+        });
+    }
+    Ok(partitions)
+}
+
+/// Call `found` for each PE executable in the FAT filesystem `disk`.
+/// Names of executables are `name` followed by their path in the
+/// filesystem.
+fn find_pe_files_in_fat<R: Read + Seek>(
+    name: &str,
+    disk: StreamSlice<R>,
+    found: &mut FoundFn,
+) -> Result<()> {
+    let fs = match fatfs::FileSystem::new(disk, fatfs::FsOptions::new()) {
+        Ok(fs) => fs,
+        Err(err) => {
+            return found(name, Err(anyhow!("invalid FAT filesystem: {err}")));
+        }
+    };
+
+    let mut dirs = vec![(String::new(), fs.root_dir(), 0)];
+    let mut num_dirs: usize = 0;
+    while let Some((dir_path, dir, depth)) = dirs.pop() {
+        // Guard against loops in corrupt images.
+        let dir_name = format!("{name}:{dir_path}/");
+        if depth > MAX_FAT_DEPTH {
+            let err = anyhow!("directories are nested too deeply");
+            found(&dir_name, Err(err))?;
+            continue;
+        }
+        num_dirs += 1;
+        if num_dirs > MAX_FAT_DIRS {
+            return found(&dir_name, Err(anyhow!("too many directories")));
+        }
+
+        let mut entries = Vec::new();
+        for entry in dir.iter() {
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(err) => found(&dir_name, Err(err.into()))?,
+            }
+        }
+        entries.sort_by_key(fatfs::DirEntry::file_name);
+
+        // Visit subdirectories in order after the files in this
+        // directory.
+        for entry in entries.iter().rev() {
+            let file_name = entry.file_name();
+            if entry.is_dir() && file_name != "." && file_name != ".." {
+                let path = format!("{dir_path}/{file_name}");
+                dirs.push((path, entry.to_dir(), depth + 1));
+            }
+        }
+        for entry in entries.iter().filter(|entry| entry.is_file()) {
+            let file_name = format!("{name}:{dir_path}/{}", entry.file_name());
+            match read_if_pe(entry.to_file()) {
+                Ok(Some(data)) => found(&file_name, Ok(data))?,
+                Ok(None) => {}
+                Err(err) => found(&file_name, Err(err.into()))?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read all of `file` if it looks like a PE executable. Otherwise only
+/// its first bytes are read, and `None` is returned.
+fn read_if_pe(mut file: impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    (&mut file).take(2).read_to_end(&mut data)?;
+    if InputKind::detect(&data) != InputKind::Pe {
+        return Ok(None);
+    }
+    file.read_to_end(&mut data)?;
+    Ok(Some(data))
+}
+
+/// Get the size in bytes of the FAT filesystem starting at `offset` in
+/// `disk`, according to its boot sector.
+fn fat_size(disk: &mut (impl Read + Seek), offset: u64) -> Result<u64> {
+    let mut sector = [0; 512];
+    disk.seek(SeekFrom::Start(offset))?;
+    disk.read_exact(&mut sector)
+        .context("boot sector is truncated")?;
+    ensure!(is_fat_boot_sector(&sector), "not a FAT filesystem");
+
+    let bytes_per_sector = u16::from_le_bytes([sector[11], sector[12]]);
+    let total_sectors = match u16::from_le_bytes([sector[19], sector[20]]) {
+        0 => u32_at(&sector, 32),
+        total_sectors => u32::from(total_sectors),
+    };
+    Ok(u64::from(total_sectors) * u64::from(bytes_per_sector))
+}
+
+/// Call `found` for each PE executable in the ISO9660 image `disk`: the
+/// executables in each El Torito EFI boot image, followed by the
+/// executables stored as regular files.
+fn find_pe_files_in_iso<R: Read + Seek>(
+    name: &str,
+    mut disk: R,
+    found: &mut FoundFn,
+) -> Result<()> {
+    let volume = match IsoVolume::read(&mut disk) {
+        Ok(volume) => volume,
+        Err(err) => return found(name, Err(err)),
+    };
+
+    match volume.efi_boot_images(&mut disk) {
+        Ok(images) => {
+            for (i, offset) in images.into_iter().enumerate() {
+                let image_name = format!("{name} (El Torito image {})", i + 1);
+                match fat_size(&mut disk, offset) {
+                    Ok(len) => {
+                        let slice = StreamSlice::new(&mut disk, offset, len);
+                        find_pe_files_in_fat(&image_name, slice, found)?;
+                    }
+                    Err(err) => found(&image_name, Err(err))?,
+                }
+            }
+        }
+        Err(err) => found(name, Err(err))?,
+    }
+
+    let files = match volume.files(&mut disk) {
+        Ok(files) => files,
+        Err(err) => return found(name, Err(err)),
+    };
+    for file in files {
+        let file_name = format!("{name}:{}", file.path);
+        let slice = StreamSlice::new(&mut disk, file.offset, file.size);
+        match read_if_pe(slice) {
+            Ok(Some(data)) => found(&file_name, Ok(data))?,
+            Ok(None) => {}
+            Err(err) => found(&file_name, Err(err.into()))?,
+        }
+    }
+    Ok(())
+}
+
+/// Call `found` for each PE executable in the EFI System Partitions of
+/// the GPT disk image `disk`.
+fn find_pe_files_in_gpt<R: Read + Seek>(
+    name: &str,
+    mut disk: R,
+    sector_size: u64,
+    found: &mut FoundFn,
+) -> Result<()> {
+    let partitions = match read_gpt_partitions(&mut disk, sector_size) {
+        Ok(partitions) => partitions,
+        Err(err) => return found(name, Err(err)),
+    };
+    let mut esps = partitions.iter().filter(|p| p.is_esp()).peekable();
+    if esps.peek().is_none() {
+        return found(name, Err(anyhow!("no EFI System Partition found")));
+    }
+
+    for partition in esps {
+        let partition_name = format!("{name} (partition {})", partition.number);
+        let range = partition
+            .first_lba
+            .checked_mul(sector_size)
+            .zip(
+                partition
+                    .last_lba
+                    .checked_add(1)
+                    .and_then(|end| end.checked_mul(sector_size)),
+            )
+            .filter(|(start, end)| start < end);
+        let Some((start, end)) = range else {
+            found(&partition_name, Err(anyhow!("invalid partition range")))?;
+            continue;
+        };
+
+        let slice = StreamSlice::new(&mut disk, start, end - start);
+        find_pe_files_in_fat(&partition_name, slice, found)?;
+    }
+    Ok(())
+}
+
+/// Call `found` for each PE32 or TE image in the firmware volumes in
+/// `data`. Names of images are `name` followed by the GUID of the file
+/// containing the image, and its user interface name if it has one.
+fn find_pe_files_in_fv(
+    name: &str,
+    data: &[u8],
+    found: &mut FoundFn,
+) -> Result<()> {
+    for image in fv::find_images(data) {
+        match image {
+            Ok(image) => {
+                found(&format!("{name}:{}", image.name()), Ok(image.data))?
+            }
+            Err(err) => found(name, Err(err))?,
+        }
+    }
+    Ok(())
+}
+
+/// Find PE executables in the file at `path`.
+///
+/// If the file is a FAT filesystem image, a GPT disk image with an EFI
+/// System Partition, an ISO9660 image, or a UEFI firmware volume,
+/// `found` is called for each PE executable in the image. Otherwise,
+/// `found` is called with the file itself if it starts with the DOS
+/// signature of a PE executable, or if `include_other` is true.
+///
+/// Errors that prevent reading part of an input are passed to `found`
+/// rather than returned; only errors returned by `found` are returned.
+pub fn find_pe_files(
+    path: &Path,
+    include_other: bool,
+    found: &mut FoundFn,
+) -> Result<()> {
+    let name = path.display().to_string();
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) => return found(&name, Err(err.into())),
+    };
+    let mut header = Vec::new();
+    if let Err(err) = (&mut file)
+        .take(u64::try_from(HEADER_SIZE)?)
+        .read_to_end(&mut header)
+    {
+        return found(&name, Err(err.into()));
+    }
+
+    let kind = InputKind::detect(&header);
+    match kind {
+        InputKind::Fat | InputKind::Gpt { .. } | InputKind::Iso => {
+            let len = match file.metadata() {
+                Ok(metadata) => metadata.len(),
+                Err(err) => return found(&name, Err(err.into())),
+            };
+            let disk = StreamSlice::new(file, 0, len);
+            match kind {
+                InputKind::Fat => find_pe_files_in_fat(&name, disk, found),
+                InputKind::Gpt { sector_size } => {
+                    find_pe_files_in_gpt(&name, disk, sector_size, found)
+                }
+                _ => find_pe_files_in_iso(&name, disk, found),
+            }
+        }
+        InputKind::FirmwareVolume => {
+            let mut data = header;
+            if let Err(err) = file.read_to_end(&mut data) {
+                return found(&name, Err(err.into()));
+            }
+            find_pe_files_in_fv(&name, &data, found)
+        }
+        InputKind::Pe | InputKind::Other => {
+            if kind == InputKind::Other && !include_other {
+                return Ok(());
+            }
+            let mut data = header;
+            if let Err(err) = file.read_to_end(&mut data) {
+                return found(&name, Err(err.into()));
+            }
+            found(&name, Ok(data))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        make_fat, make_ffs_section, make_fv, make_gpt, make_iso, make_sbat_pe,
+    };
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    fn find(path: &Path, include_other: bool) -> Vec<(String, String)> {
+        let mut found = Vec::new();
+        find_pe_files(path, include_other, &mut |name, data| {
+            let name = name.replace(path.to_str().unwrap(), "<input>");
+            let data = match data {
+                Ok(data) => {
+                    let sbat = crate::pe_section(&data, ".sbat").unwrap();
+                    String::from_utf8(sbat.to_vec()).unwrap()
+                }
+                Err(err) => format!("Error: {err}"),
+            };
+            found.push((name, data));
+            Ok(())
+        })
+        .unwrap();
+        found
+    }
+
+    #[test]
+    fn test_fat() {
+        let shim = make_sbat_pe(b"shim");
+        let grub = make_sbat_pe(b"grub");
+        let files: &[(&str, &[u8])] = &[
+            ("EFI/BOOT/BOOTX64.EFI", &shim),
+            ("EFI/BOOT/grub.cfg", b"set timeout=5"),
+            ("EFI/vendor/grubx64.efi", &grub),
+            ("startup.nsh", b"\\EFI\\BOOT\\BOOTX64.EFI"),
+        ];
+        let dir = TempDir::new().unwrap();
+        for fat_type in [
+            fatfs::FatType::Fat12,
+            fatfs::FatType::Fat16,
+            fatfs::FatType::Fat32,
+        ] {
+            let path = dir.path().join("esp.img");
+            fs::write(&path, make_fat(fat_type, files)).unwrap();
+            assert_eq!(
+                find(&path, false),
+                [
+                    ("<input>:/EFI/BOOT/BOOTX64.EFI".into(), "shim".into()),
+                    ("<input>:/EFI/vendor/grubx64.efi".into(), "grub".into()),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_fat_directory_loop() {
+        let shim = make_sbat_pe(b"shim");
+        let mut image = make_fat(
+            fatfs::FatType::Fat12,
+            &[("A/B/x", b""), ("A/a.efi", &shim)],
+        );
+
+        // Point the entry for B at the first cluster of A, its parent.
+        let entry_offset = |name: &[u8; 11]| {
+            image
+                .windows(12)
+                .position(|w| w[..11] == *name && w[11] == 0x10)
+                .unwrap()
+        };
+        let a = entry_offset(b"A          ");
+        let b = entry_offset(b"B          ");
+        let cluster = [image[a + 26], image[a + 27]];
+        image[b + 26..b + 28].copy_from_slice(&cluster);
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("loop.img");
+        fs::write(&path, image).unwrap();
+        let found = find(&path, false);
+        assert_eq!(found.len(), MAX_FAT_DEPTH + 1, "{found:?}");
+        assert_eq!(found[0], ("<input>:/A/a.efi".into(), "shim".into()));
+        assert_eq!(found[1], ("<input>:/A/B/a.efi".into(), "shim".into()));
+        let (name, result) = found.last().unwrap();
+        assert!(name.ends_with("/B/"), "{name}");
+        assert_eq!(result, "Error: directories are nested too deeply");
+    }
+
+    #[test]
+    fn test_gpt() {
+        let shim = make_sbat_pe(b"shim");
+        let esp =
+            make_fat(fatfs::FatType::Fat12, &[("EFI/BOOT/BOOTX64.EFI", &shim)]);
+        // Linux filesystem partition, which is not read.
+        let linux_guid = [0xaf; 16];
+        let other = make_fat(fatfs::FatType::Fat12, &[("a.efi", &shim)]);
+        let disk = make_gpt(&[(linux_guid, &other), (ESP_TYPE_GUID, &esp)]);
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("disk.img");
+        fs::write(&path, disk).unwrap();
+        assert_eq!(
+            find(&path, false),
+            [(
+                "<input> (partition 2):/EFI/BOOT/BOOTX64.EFI".into(),
+                "shim".into()
+            )]
+        );
+
+        // Disk without an ESP.
+        fs::write(&path, make_gpt(&[(linux_guid, &other)])).unwrap();
+        assert_eq!(
+            find(&path, false),
+            [(
+                "<input>".into(),
+                "Error: no EFI System Partition found".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_iso() {
+        let shim = make_sbat_pe(b"shim");
+        let grub = make_sbat_pe(b"grub");
+        let boot_image = make_fat(
+            fatfs::FatType::Fat12,
+            &[
+                ("EFI/BOOT/BOOTX64.EFI", &shim),
+                ("EFI/BOOT/grubx64.efi", &grub),
+            ],
+        );
+        let iso = make_iso(
+            &[
+                ("EFI/BOOT/BOOTX64.EFI", &shim),
+                ("EFI/BOOT/GRUB.CFG", b"set timeout=5"),
+                ("IMAGES/EFIBOOT.IMG", &boot_image),
+            ],
+            Some(&boot_image),
+        );
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("install.iso");
+        fs::write(&path, iso).unwrap();
+        assert_eq!(
+            find(&path, false),
+            [
+                (
+                    "<input> (El Torito image 1):/EFI/BOOT/BOOTX64.EFI".into(),
+                    "shim".into()
+                ),
+                (
+                    "<input> (El Torito image 1):/EFI/BOOT/grubx64.efi".into(),
+                    "grub".into()
+                ),
+                ("<input>:/EFI/BOOT/BOOTX64.EFI".into(), "shim".into()),
+            ]
+        );
+
+        // The El Torito image must be a FAT filesystem.
+        let iso = make_iso(&[], Some(&shim));
+        fs::write(&path, iso).unwrap();
+        assert_eq!(
+            find(&path, false),
+            [(
+                "<input> (El Torito image 1)".into(),
+                "Error: not a FAT filesystem".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_firmware_volume() {
+        let mut ui_name: Vec<u8> =
+            "Shell".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        ui_name.extend([0, 0]);
+        let mut guid = [0; 16];
+        guid[0] = 0xaa;
+        let fv = make_fv(&[(
+            guid,
+            // Application.
+            0x09,
+            &[
+                make_ffs_section(0x10, &make_sbat_pe(b"shell")),
+                make_ffs_section(0x15, &ui_name),
+            ],
+        )]);
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("OVMF.fd");
+        fs::write(&path, fv).unwrap();
+        assert_eq!(
+            find(&path, false),
+            [(
+                "<input>:000000aa-0000-0000-0000-000000000000 (Shell)".into(),
+                "shell".into()
+            )]
+        );
+
+        // Truncated volume.
+        fs::write(&path, &make_fv(&[])[..60]).unwrap();
+        assert_eq!(
+            find(&path, false),
+            [(
+                "<input>".into(),
+                "Error: invalid firmware volume length".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_other_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("shimx64.efi");
+        fs::write(&path, make_sbat_pe(b"shim")).unwrap();
+        assert_eq!(find(&path, false), [("<input>".into(), "shim".into())]);
+
+        let path = dir.path().join("grub.cfg");
+        fs::write(&path, b"set timeout=5").unwrap();
+        assert!(find(&path, false).is_empty());
+
+        let path = dir.path().join("missing");
+        assert_eq!(find(&path, false).len(), 1);
+        assert!(find(&path, false)[0].1.starts_with("Error: "));
+
+        // Corrupt FAT image.
+        let mut image = make_fat(fatfs::FatType::Fat12, &[]);
+        image[17..19].copy_from_slice(&[0, 0]);
+        image[22..24].copy_from_slice(&[0, 0]);
+        let path = dir.path().join("esp.img");
+        fs::write(&path, image).unwrap();
+        let found = find(&path, false);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].1.starts_with("Error: invalid FAT"), "{found:?}");
+    }
+
+    #[test]
+    fn test_stream_slice() {
+        let mut slice = StreamSlice::new(Cursor::new(b"0123456789"), 2, 5);
+        let mut data = Vec::new();
+        slice.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"23456");
+        assert_eq!(slice.seek(SeekFrom::End(-2)).unwrap(), 3);
+        data.clear();
+        slice.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"56");
+        assert!(slice.seek(SeekFrom::Current(-10)).is_err());
+        assert!(slice.write(b"x").is_err());
+    }
+}
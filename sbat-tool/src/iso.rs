@@ -0,0 +1,384 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Minimal read-only ISO9660 and El Torito support.
+//!
+//! Only what is needed to find EFI executables on install media is
+//! implemented: listing regular files (using Joliet names if present),
+//! and locating El Torito boot images for the EFI platform.
+
+use anyhow::{Context, Result, bail, ensure};
+use std::collections::HashSet;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Size of a logical sector.
+pub const SECTOR_SIZE: u64 = 2048;
+
+/// Sector of the first volume descriptor.
+const FIRST_DESCRIPTOR_SECTOR: u64 = 16;
+
+/// Maximum number of volume descriptors to read before giving up on
+/// finding the terminator.
+const MAX_DESCRIPTORS: u64 = 64;
+
+/// Identifier in each volume descriptor.
+const STANDARD_ID: &[u8] = b"CD001";
+
+/// System identifier of an El Torito boot record.
+const EL_TORITO_ID: &[u8] = b"EL TORITO SPECIFICATION";
+
+/// El Torito platform ID for EFI.
+const EFI_PLATFORM_ID: u8 = 0xef;
+
+/// Minimum size of a directory record.
+const MIN_RECORD_SIZE: usize = 34;
+
+/// Maximum size of a directory to read, to avoid huge allocations for
+/// corrupt images.
+const MAX_DIR_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Check whether `header`, the start of a file, contains an ISO9660
+/// volume descriptor.
+pub fn is_iso9660(header: &[u8]) -> bool {
+    let start =
+        usize::try_from(FIRST_DESCRIPTOR_SECTOR * SECTOR_SIZE + 1).unwrap();
+    header.get(start..start + STANDARD_ID.len()) == Some(STANDARD_ID)
+}
+
+fn read_sector(
+    reader: &mut (impl Read + Seek),
+    sector: u64,
+) -> Result<Vec<u8>> {
+    let offset = sector
+        .checked_mul(SECTOR_SIZE)
+        .context("invalid sector number")?;
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0; usize::try_from(SECTOR_SIZE)?];
+    reader.read_exact(&mut data).context("image is truncated")?;
+    Ok(data)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Location of a file or directory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Extent {
+    sector: u32,
+    size: u32,
+}
+
+/// Parsed directory record.
+struct DirRecord<'a> {
+    extent: Extent,
+    is_dir: bool,
+    name: &'a [u8],
+}
+
+impl<'a> DirRecord<'a> {
+    /// Parse a directory record. The record's length must already have
+    /// been checked.
+    fn parse(record: &'a [u8]) -> Result<Self> {
+        let name_len = usize::from(record[32]);
+        let name = record
+            .get(33..33 + name_len)
+            .context("invalid directory record")?;
+        Ok(Self {
+            extent: Extent {
+                // Fields are stored in both byte orders; use the
+                // little-endian copy.
+                sector: u32_at(record, 2),
+                size: u32_at(record, 10),
+            },
+            is_dir: record[25] & 0x02 != 0,
+            name,
+        })
+    }
+
+    /// Whether this is the entry for the directory itself or its parent.
+    fn is_self_or_parent(&self) -> bool {
+        self.name == [0] || self.name == [1]
+    }
+
+    /// Decode the name, dropping the version suffix.
+    fn decode_name(&self, joliet: bool) -> String {
+        let name = if joliet {
+            let units = self
+                .name
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]));
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        } else {
+            String::from_utf8_lossy(self.name).into_owned()
+        };
+        let name = match name.rsplit_once(';') {
+            Some((name, _version)) => name,
+            None => &name,
+        };
+        // Files without an extension have a trailing dot.
+        name.strip_suffix('.').unwrap_or(name).to_string()
+    }
+}
+
+/// Regular file in an ISO9660 filesystem.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IsoFile {
+    /// Absolute path of the file, starting with `/`.
+    pub path: String,
+
+    /// Byte offset of the file data in the image.
+    pub offset: u64,
+
+    /// Size of the file in bytes.
+    pub size: u64,
+}
+
+/// ISO9660 volume.
+pub struct IsoVolume {
+    root: Extent,
+    joliet: bool,
+    boot_catalog: Option<u32>,
+}
+
+impl IsoVolume {
+    /// Read the volume descriptors.
+    ///
+    /// The Joliet directory tree is used if present, since it has the
+    /// original file names; otherwise the primary tree is used.
+    pub fn read(reader: &mut (impl Read + Seek)) -> Result<Self> {
+        let mut primary_root = None;
+        let mut joliet_root = None;
+        let mut boot_catalog = None;
+
+        for sector in (FIRST_DESCRIPTOR_SECTOR..).take(MAX_DESCRIPTORS as usize)
+        {
+            let descriptor = read_sector(reader, sector)?;
+            ensure!(
+                &descriptor[1..6] == STANDARD_ID,
+                "invalid ISO9660 volume descriptor"
+            );
+            let root_record = &descriptor[156..156 + MIN_RECORD_SIZE];
+            match descriptor[0] {
+                // Boot record.
+                0 if descriptor[7..7 + EL_TORITO_ID.len()] == *EL_TORITO_ID => {
+                    boot_catalog = Some(u32_at(&descriptor, 0x47));
+                }
+                // Primary volume descriptor.
+                1 => {
+                    primary_root = Some(DirRecord::parse(root_record)?.extent);
+                }
+                // Supplementary volume descriptor. Joliet is identified
+                // by the escape sequence for UCS-2.
+                2 if matches!(
+                    &descriptor[88..91],
+                    b"%/@" | b"%/C" | b"%/E"
+                ) =>
+                {
+                    joliet_root = Some(DirRecord::parse(root_record)?.extent);
+                }
+                // Terminator.
+                255 => break,
+                _ => {}
+            }
+        }
+
+        let (root, joliet) = match (joliet_root, primary_root) {
+            (Some(root), _) => (root, true),
+            (None, Some(root)) => (root, false),
+            (None, None) => bail!("missing ISO9660 primary volume descriptor"),
+        };
+        Ok(Self {
+            root,
+            joliet,
+            boot_catalog,
+        })
+    }
+
+    /// Get all regular files, in directory order.
+    pub fn files(
+        &self,
+        reader: &mut (impl Read + Seek),
+    ) -> Result<Vec<IsoFile>> {
+        let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        let mut dirs = vec![(String::new(), self.root)];
+        while let Some((dir_path, extent)) = dirs.pop() {
+            // Guard against loops in corrupt images.
+            if !visited.insert(extent.sector) {
+                continue;
+            }
+            ensure!(extent.size <= MAX_DIR_SIZE, "directory is too large");
+
+            reader.seek(SeekFrom::Start(
+                u64::from(extent.sector) * SECTOR_SIZE,
+            ))?;
+            let mut data = vec![0; usize::try_from(extent.size)?];
+            reader.read_exact(&mut data).context("image is truncated")?;
+
+            let mut subdirs = Vec::new();
+            let mut pos = 0;
+            while pos < data.len() {
+                let len = usize::from(data[pos]);
+                if len == 0 {
+                    // Records don't cross sector boundaries; the rest of
+                    // the sector is padding.
+                    pos = (pos + 1).next_multiple_of(SECTOR_SIZE as usize);
+                    continue;
+                }
+                let record = data
+                    .get(pos..pos + len)
+                    .filter(|record| record.len() >= MIN_RECORD_SIZE)
+                    .context("invalid directory record")?;
+                pos += len;
+
+                let record = DirRecord::parse(record)?;
+                if record.is_self_or_parent() {
+                    continue;
+                }
+                let path =
+                    format!("{dir_path}/{}", record.decode_name(self.joliet));
+                if record.is_dir {
+                    subdirs.push((path, record.extent));
+                } else {
+                    files.push(IsoFile {
+                        path,
+                        offset: u64::from(record.extent.sector) * SECTOR_SIZE,
+                        size: u64::from(record.extent.size),
+                    });
+                }
+            }
+            // Visit subdirectories in directory order.
+            dirs.extend(subdirs.into_iter().rev());
+        }
+        Ok(files)
+    }
+
+    /// Get the byte offsets of the El Torito boot images for the EFI
+    /// platform. These are typically FAT filesystem images.
+    pub fn efi_boot_images(
+        &self,
+        reader: &mut (impl Read + Seek),
+    ) -> Result<Vec<u64>> {
+        let Some(catalog_sector) = self.boot_catalog else {
+            return Ok(Vec::new());
+        };
+        let catalog = read_sector(reader, u64::from(catalog_sector))?;
+        let mut entries = catalog.chunks_exact(32);
+
+        // The validation entry has the platform of the default entry.
+        let validation = entries.next().unwrap();
+        ensure!(
+            validation[0] == 1 && validation[30..32] == [0x55, 0xaa],
+            "invalid El Torito validation entry"
+        );
+        let mut platform = validation[1];
+
+        let mut images = Vec::new();
+        let mut remaining_in_section = 1;
+        let mut last_section = false;
+        for entry in entries {
+            match entry[0] {
+                // Section header.
+                0x90 | 0x91 if remaining_in_section == 0 => {
+                    platform = entry[1];
+                    remaining_in_section =
+                        u16::from_le_bytes([entry[2], entry[3]]);
+                    last_section = entry[0] == 0x91;
+                }
+                // Section entry extension.
+                0x44 => {}
+                // Bootable or non-bootable entry.
+                0x88 | 0x00 if remaining_in_section > 0 => {
+                    remaining_in_section -= 1;
+                    if entry[0] == 0x88 && platform == EFI_PLATFORM_ID {
+                        images.push(u64::from(u32_at(entry, 8)) * SECTOR_SIZE);
+                    }
+                    if remaining_in_section == 0 && last_section {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+        }
+        Ok(images)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::make_iso;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_files() {
+        let iso = make_iso(
+            &[
+                ("EFI/BOOT/BOOTX64.EFI", b"shim"),
+                ("EFI/BOOT/GRUBX64.EFI", b"grub"),
+                ("README", b"readme"),
+            ],
+            None,
+        );
+        assert!(is_iso9660(&iso));
+        let mut reader = Cursor::new(iso);
+        let volume = IsoVolume::read(&mut reader).unwrap();
+        let files = volume.files(&mut reader).unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["/README", "/EFI/BOOT/BOOTX64.EFI", "/EFI/BOOT/GRUBX64.EFI"]
+        );
+
+        let file = &files[2];
+        assert_eq!(file.size, 4);
+        let data = &reader.get_ref()[usize::try_from(file.offset).unwrap()..];
+        assert_eq!(&data[..4], b"grub");
+
+        assert!(volume.efi_boot_images(&mut reader).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_efi_boot_images() {
+        let iso = make_iso(&[], Some(b"boot image"));
+        let mut reader = Cursor::new(iso);
+        let volume = IsoVolume::read(&mut reader).unwrap();
+        let images = volume.efi_boot_images(&mut reader).unwrap();
+        assert_eq!(images.len(), 1);
+        let offset = usize::try_from(images[0]).unwrap();
+        assert_eq!(&reader.get_ref()[offset..offset + 10], b"boot image");
+    }
+
+    #[test]
+    fn test_decode_name() {
+        let record = |name: &'static [u8]| DirRecord {
+            extent: Extent { sector: 0, size: 0 },
+            is_dir: false,
+            name,
+        };
+        assert_eq!(record(b"BOOTX64.EFI;1").decode_name(false), "BOOTX64.EFI");
+        assert_eq!(record(b"README.;1").decode_name(false), "README");
+        assert_eq!(record(b"\0a\0.\0e\0;\x001").decode_name(true), "a.e");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut iso = make_iso(&[], None);
+        assert!(IsoVolume::read(&mut Cursor::new(&iso[..34000])).is_err());
+
+        // Break the root directory's first record.
+        let volume = IsoVolume::read(&mut Cursor::new(&iso)).unwrap();
+        let root = usize::try_from(u64::from(volume.root.sector) * SECTOR_SIZE)
+            .unwrap();
+        iso[root] = 10;
+        assert!(volume.files(&mut Cursor::new(&iso)).is_err());
+    }
+}
@@ -6,6 +6,16 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+mod add_section;
+mod check;
+mod fv;
+mod input;
+mod iso;
+mod scan;
+#[cfg(test)]
+mod test_util;
+mod uki;
+
 use anyhow::{Result, anyhow};
 use ascii::AsciiStr;
 use clap::{Parser, Subcommand};
@@ -13,11 +23,12 @@ use fs_err as fs;
 use itertools::Itertools;
 use object::{Object, ObjectSection};
 use sbat::{
-    ImageSbat, REVOCATION_SECTION_NAME, RevocationSbat, RevocationSection,
-    SBAT_SECTION_NAME,
+    ImageSbat, ParseError, REVOCATION_SECTION_NAME, RevocationSbat,
+    RevocationSection, SBAT_SECTION_NAME,
 };
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 /// Tool for working with SBAT (UEFI Secure Boot Advanced Targeting).
 #[derive(Parser)]
@@ -29,8 +40,6 @@ struct Args {
 
 // TODO:
 //
-// * Action to add a '.sbat' section to an existing PE file.
-//
 // * Validate/pretty-print a CSV file.
 
 #[derive(Subcommand)]
@@ -44,19 +53,111 @@ enum Action {
     },
 
     /// Validate and pretty-print the '.sbat' section of a PE executable.
-    Validate { input: Vec<PathBuf> },
+    Validate {
+        /// Fail if the 'sbat' header entry is missing, is not the first
+        /// entry, or is repeated.
+        #[arg(long)]
+        require_header: bool,
+
+        input: Vec<PathBuf>,
+    },
 
     /// Validate and pretty-print the '.sbatlevel' section of a PE executable.
     ValidateRevocations { input: Vec<PathBuf> },
+
+    /// Add a '.sbat' section to a PE executable, or replace the existing
+    /// one.
+    AddSection {
+        /// CSV file containing the SBAT data.
+        #[arg(long)]
+        sbat: PathBuf,
+
+        /// Remove an existing Authenticode signature instead of failing.
+        #[arg(long)]
+        strip_signature: bool,
+
+        input: PathBuf,
+        output: PathBuf,
+    },
+
+    /// Check the '.sbat' section of PE executables against revocation
+    /// data.
+    ///
+    /// An input can also be a FAT filesystem image, a GPT disk image, an
+    /// ISO9660 image, or a UEFI firmware volume (such as OVMF.fd), in
+    /// which case every PE executable in it (or in its EFI System
+    /// Partition or El Torito EFI boot image) is checked. In firmware
+    /// volumes, PE32 and TE images in uncompressed sections are checked.
+    ///
+    /// The exit code is 0 if all images are allowed, 3 if any image is
+    /// revoked, and 1 if any image could not be checked. Invalid
+    /// arguments give exit code 2.
+    Check {
+        #[command(flatten)]
+        revocations: check::RevocationArgs,
+
+        #[arg(required = true)]
+        input: Vec<PathBuf>,
+    },
+
+    /// Find all PE executables in directories, such as a mounted EFI
+    /// System Partition, and check them against revocation data.
+    ///
+    /// Files are found by their contents, whatever their extension.
+    /// FAT filesystem images, GPT disk images, ISO9660 images, and UEFI
+    /// firmware volumes are read as well. For each executable, the
+    /// '.sbat' section (and the '.sbatlevel' section, if present) and
+    /// whether it is allowed or revoked is printed; unreadable
+    /// executables are listed as errors.
+    /// The exit code is the same as for 'check'.
+    Scan {
+        #[command(flatten)]
+        revocations: check::RevocationArgs,
+
+        /// Directories (or individual files) to scan.
+        #[arg(required = true)]
+        input: Vec<PathBuf>,
+    },
+
+    /// Inspect the SBAT data of a Unified Kernel Image (UKI).
+    ///
+    /// The '.sbat' sections of the systemd-stub and of the embedded
+    /// kernel (the '.linux' section) are printed, along with the merged
+    /// SBAT data that ukify would produce from them. Each is then
+    /// checked against revocation data.
+    ///
+    /// The exit code is the same as for 'check'.
+    Uki {
+        #[command(flatten)]
+        revocations: check::RevocationArgs,
+
+        /// CSV file with the SBAT data passed to ukify's '--sbat'
+        /// option. By default ukify's 'uki' entry is used.
+        #[arg(long)]
+        sbat: Option<PathBuf>,
+
+        input: PathBuf,
+    },
 }
 
-fn read_pe_section(input: &Path, section_name: &str) -> Result<Vec<u8>> {
-    let data = fs::read(input)?;
-    let file = object::File::parse(&*data)?;
+/// Get the data of the section named `section_name` in the PE (or TE)
+/// image `data`.
+fn pe_section<'a>(data: &'a [u8], section_name: &str) -> Result<&'a [u8]> {
+    // TE images are only found in firmware volumes, and are not
+    // supported by the object crate.
+    if data.starts_with(b"VZ") {
+        return fv::te_section(data, section_name);
+    }
+    let file = object::File::parse(data)?;
     let section = file
         .section_by_name(section_name)
         .ok_or(anyhow!("missing '{}' section", section_name))?;
-    Ok(section.data()?.to_vec())
+    Ok(section.data()?)
+}
+
+fn read_pe_section(input: &Path, section_name: &str) -> Result<Vec<u8>> {
+    let data = fs::read(input)?;
+    Ok(pe_section(&data, section_name)?.to_vec())
 }
 
 fn ignore_broken_pipe(result: io::Result<()>) -> io::Result<()> {
@@ -77,6 +178,30 @@ fn dump_section(input: &Path, section_name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Format a parse error. If the error has a position, the line of
+/// `data` that contains the error is included, with a marker under the
+/// column where the error occurred.
+fn parse_error_to_string(data: &[u8], err: &ParseError) -> String {
+    let mut output = err.to_string();
+
+    let Some(position) = err.position() else {
+        return output;
+    };
+    let Some(line) = data.split(|b| *b == b'\n').nth(position.line - 1) else {
+        return output;
+    };
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line_num = position.line.to_string();
+    let indent = " ".repeat(line_num.len());
+    output.push_str(&format!(
+        "\n{line_num} | {}\n{indent} | {}^",
+        line.escape_ascii(),
+        " ".repeat(position.column - 1),
+    ));
+
+    output
+}
+
 fn image_sbat_to_table_string(image_sbat: &ImageSbat) -> String {
     let mut builder = tabled::builder::Builder::default();
     builder.push_record([
@@ -137,7 +262,7 @@ fn sbat_level_section_to_table_string(
     builder.build().to_string()
 }
 
-fn validate_sbat(inputs: &Vec<PathBuf>) -> Result<()> {
+fn validate_sbat(inputs: &Vec<PathBuf>, require_header: bool) -> Result<()> {
     let mut stdout = io::stdout();
 
     let mut first = true;
@@ -150,7 +275,13 @@ fn validate_sbat(inputs: &Vec<PathBuf>) -> Result<()> {
         ignore_broken_pipe(writeln!(stdout, "{}:", input.display()))?;
 
         let data = read_pe_section(input, SBAT_SECTION_NAME)?;
-        let image_sbat = ImageSbat::parse(&data)?;
+        let image_sbat = ImageSbat::parse(&data)
+            .map_err(|err| anyhow!(parse_error_to_string(&data, &err)))?;
+        if require_header {
+            image_sbat
+                .check_header()
+                .map_err(|err| anyhow!(parse_error_to_string(&data, &err)))?;
+        }
 
         let table = image_sbat_to_table_string(image_sbat);
         ignore_broken_pipe(writeln!(stdout, "{table}"))?;
@@ -159,6 +290,19 @@ fn validate_sbat(inputs: &Vec<PathBuf>) -> Result<()> {
     Ok(())
 }
 
+/// Parse a '.sbatlevel' section and format it as a table.
+fn revocation_section_to_table_string(data: &[u8]) -> Result<String> {
+    let sbat_level_section = RevocationSection::parse(data)?;
+    let parse_revocations = |data| {
+        RevocationSbat::parse(data)
+            .map_err(|err| anyhow!(parse_error_to_string(data, &err)))
+    };
+    let previous = parse_revocations(sbat_level_section.previous())?;
+    let latest = parse_revocations(sbat_level_section.latest())?;
+
+    Ok(sbat_level_section_to_table_string(previous, latest))
+}
+
 fn validate_revocations(inputs: &Vec<PathBuf>) -> Result<()> {
     let mut stdout = io::stdout();
 
@@ -172,27 +316,77 @@ fn validate_revocations(inputs: &Vec<PathBuf>) -> Result<()> {
         ignore_broken_pipe(writeln!(stdout, "{}:", input.display()))?;
 
         let data = read_pe_section(input, REVOCATION_SECTION_NAME)?;
-
-        let sbat_level_section = RevocationSection::parse(&data)?;
-        let previous = RevocationSbat::parse(sbat_level_section.previous())?;
-        let latest = RevocationSbat::parse(sbat_level_section.latest())?;
-
-        let table = sbat_level_section_to_table_string(previous, latest);
+        let table = revocation_section_to_table_string(&data)?;
         ignore_broken_pipe(writeln!(stdout, "{table}"))?;
     }
 
     Ok(())
 }
 
-fn run_action(args: &Args) -> Result<()> {
+fn add_sbat_section(
+    sbat: &Path,
+    strip_signature: bool,
+    input: &Path,
+    output: &Path,
+) -> Result<()> {
+    let sbat_data = fs::read(sbat)?;
+    ImageSbat::parse(&sbat_data)
+        .map_err(|err| anyhow!(parse_error_to_string(&sbat_data, &err)))?;
+
+    let data = fs::read(input)?;
+    let data = add_section::add_section(
+        &data,
+        SBAT_SECTION_NAME,
+        &sbat_data,
+        strip_signature,
+    )
+    .map_err(|err| anyhow!("{}: {err}", input.display()))?;
+    fs::write(output, data)?;
+
+    Ok(())
+}
+
+fn run_action(args: &Args) -> Result<ExitCode> {
     match &args.action {
         Action::Dump { input, section } => dump_section(input, section),
-        Action::Validate { input } => validate_sbat(input),
+        Action::Validate {
+            require_header,
+            input,
+        } => validate_sbat(input, *require_header),
         Action::ValidateRevocations { input } => validate_revocations(input),
-    }
+        Action::AddSection {
+            sbat,
+            strip_signature,
+            input,
+            output,
+        } => add_sbat_section(sbat, *strip_signature, input, output),
+        Action::Check { revocations, input } => {
+            let status =
+                check::check_images(revocations, input, &mut io::stdout())?;
+            return Ok(status.exit_code());
+        }
+        Action::Scan { revocations, input } => {
+            let status = scan::scan(revocations, input, &mut io::stdout())?;
+            return Ok(status.exit_code());
+        }
+        Action::Uki {
+            revocations,
+            sbat,
+            input,
+        } => {
+            let status = uki::inspect_uki(
+                revocations,
+                sbat.as_deref(),
+                input,
+                &mut io::stdout(),
+            )?;
+            return Ok(status.exit_code());
+        }
+    }?;
+    Ok(ExitCode::SUCCESS)
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     run_action(&Args::parse())
 }
 
@@ -213,7 +407,22 @@ mod tests {
 +-----------+-----+----------+---------+---------+------------------------------+
 | pizza     | 2   | SomeCorp | pizza   | 1.2.3   | https://example.com/somecorp |
 +-----------+-----+----------+---------+---------+------------------------------+";
-        assert_eq!(image_sbat_to_table_string(&image_sbat), expected.trim());
+        assert_eq!(image_sbat_to_table_string(image_sbat), expected.trim());
+    }
+
+    #[test]
+    fn test_parse_error_to_string() {
+        let data = b"sbat,1\r\nshim,x,UEFI shim\r\n";
+        let err = ImageSbat::parse(data).unwrap_err();
+        let expected = r#"
+line 2, column 6: invalid generation "x", must be a positive integer
+2 | shim,x,UEFI shim
+  |      ^"#;
+        assert_eq!(parse_error_to_string(data, &err), expected.trim());
+
+        // Errors without a position just use the error message.
+        let err = sbat::Generation::new(0).unwrap_err();
+        assert_eq!(parse_error_to_string(data, &err), err.to_string());
     }
 
     #[test]
@@ -234,6 +443,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_validate_require_header() {
+        use crate::test_util::make_sbat_pe;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("test.efi");
+        let pe = make_sbat_pe(b"shim,1\nsbat,1\n");
+        fs::write(&path, pe).unwrap();
+        let inputs = vec![path];
+
+        validate_sbat(&inputs, false).unwrap();
+        let err = validate_sbat(&inputs, true).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("line 2, column 1: sbat header is not the first"),
+            "{err}"
+        );
+    }
+
     /// Test that a bad input path doesn't cause a panic.
     #[test]
     fn test_invalid_path() {
@@ -250,6 +478,7 @@ mod tests {
         assert!(
             run_action(&Args {
                 action: Action::Validate {
+                    require_header: false,
                     input: vec!["/bad/path".into()],
                 }
             })
@@ -264,5 +493,18 @@ mod tests {
             })
             .is_err()
         );
+
+        assert!(
+            run_action(&Args {
+                action: Action::Check {
+                    revocations: check::RevocationArgs {
+                        revocations: Some("/bad/path".into()),
+                        ..Default::default()
+                    },
+                    input: vec!["/bad/path".into()],
+                }
+            })
+            .is_err()
+        );
     }
 }
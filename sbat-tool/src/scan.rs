@@ -0,0 +1,165 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Scan directories for PE executables.
+
+use crate::check::{CheckStatus, RevocationArgs, check_found};
+use crate::input::find_pe_files;
+use anyhow::Result;
+use sbat::RevocationIndex;
+use std::io::Write;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+
+/// Recursively find all PE executables under each of `paths`, and
+/// check their `.sbat` sections against the revocation data. Files are
+/// identified by their contents, not their extension. PE executables
+/// inside FAT filesystem images, GPT disk images, ISO9660 images
+/// (including their El Torito EFI boot images), and UEFI firmware
+/// volumes are also found.
+///
+/// Each executable's status and SBAT entries are written to `out`.
+/// Errors reading a file or directory, or parsing an executable, are
+/// written to `out` and don't stop the scan.
+pub fn scan(
+    revocation_args: &RevocationArgs,
+    paths: &[PathBuf],
+    out: &mut impl Write,
+) -> Result<CheckStatus> {
+    let revocations = revocation_args.load()?;
+    let index = RevocationIndex::new(&revocations);
+
+    let mut status = CheckStatus::Allowed;
+    let mut num_images: usize = 0;
+    for path in paths {
+        for entry in WalkDir::new(path).sort_by_file_name() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    let path = err.path().unwrap_or(path);
+                    writeln!(out, "{}: Error: {err}\n", path.display())?;
+                    status = CheckStatus::Error;
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            find_pe_files(entry.path(), false, &mut |name, data| {
+                if data.is_ok() {
+                    num_images = num_images.saturating_add(1);
+                }
+                status =
+                    status.max(check_found(&index, name, data, true, out)?);
+                writeln!(out)?;
+                Ok(())
+            })?;
+        }
+    }
+
+    writeln!(out, "Found {num_images} PE executable(s)")?;
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        TestSection, csv_revocation_args, make_pe, make_sbat_pe,
+    };
+    use fs_err as fs;
+    use sbat::{RevocationSbat, RevocationSection};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_scan() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("EFI/BOOT")).unwrap();
+        fs::create_dir_all(root.join("EFI/vendor")).unwrap();
+        let files: &[(&str, Vec<u8>)] = &[
+            ("EFI/BOOT/BOOTX64.EFI", make_sbat_pe(b"shim,2,S,s,1,u\n")),
+            ("EFI/vendor/grubx64.efi", make_sbat_pe(b"grub,1,G,g,1,u\n")),
+            // No extension.
+            ("EFI/vendor/kernel", make_pe(&[], true)),
+            ("EFI/vendor/bad.efi", make_sbat_pe(b"grub,x\n")),
+            ("EFI/vendor/truncated.efi", b"MZ".to_vec()),
+            ("EFI/vendor/grub.cfg", b"set timeout=5\n".to_vec()),
+            ("EFI/vendor/empty", Vec::new()),
+        ];
+        for (name, data) in files {
+            fs::write(root.join(name), data).unwrap();
+        }
+        let args = csv_revocation_args(root, b"sbat,1,2023012900\ngrub,2\n");
+
+        let mut out = Vec::new();
+        let status = scan(&args, &[root.join("EFI")], &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(status, CheckStatus::Error);
+
+        let status_lines: Vec<_> = out
+            .lines()
+            .filter(|line| line.contains(": "))
+            .filter_map(|line| line.strip_prefix(root.to_str().unwrap()))
+            .collect();
+        assert_eq!(
+            status_lines,
+            [
+                "/EFI/BOOT/BOOTX64.EFI: Allowed",
+                "/EFI/vendor/bad.efi: Error: line 1, column 6: invalid \
+                 generation \"x\", must be a positive integer",
+                "/EFI/vendor/grubx64.efi: Revoked",
+                "/EFI/vendor/kernel: Error: missing '.sbat' section",
+                "/EFI/vendor/truncated.efi: Error: Could not read file \
+                 magic",
+            ]
+        );
+        assert!(out.contains("| shim      | 2   | S      | s"), "{out}");
+        assert!(out.ends_with("Found 5 PE executable(s)\n"), "{out}");
+    }
+
+    #[test]
+    fn test_scan_revocation_section() {
+        let dir = TempDir::new().unwrap();
+        let previous = RevocationSbat::parse(b"sbat,1,2022111500\n").unwrap();
+        let latest =
+            RevocationSbat::parse(b"sbat,1,2023012900\ngrub,3\n").unwrap();
+        let section = RevocationSection::new(previous, latest).to_bytes();
+        let shim = make_pe(
+            &[
+                TestSection::new(".sbat", b"shim,2,S,s,1,u\n"),
+                TestSection::new(".sbatlevel", &section),
+            ],
+            true,
+        );
+        fs::write(dir.path().join("shimx64.efi"), shim).unwrap();
+        let args = csv_revocation_args(dir.path(), b"sbat,1\n");
+
+        let mut out = Vec::new();
+        let status = scan(&args, &[dir.path().into()], &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(status, CheckStatus::Allowed);
+        assert!(out.contains("| previous name | previous gen |"), "{out}");
+        assert!(
+            out.contains("|               |              | grub "),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn test_scan_missing_dir() {
+        let dir = TempDir::new().unwrap();
+        let args = csv_revocation_args(dir.path(), b"sbat,1\n");
+
+        let mut out = Vec::new();
+        let missing = dir.path().join("missing");
+        let status = scan(&args, &[missing], &mut out).unwrap();
+        assert_eq!(status, CheckStatus::Error);
+    }
+}
@@ -0,0 +1,443 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Helpers for creating test inputs.
+
+use crate::check::RevocationArgs;
+use fs_err as fs;
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+/// Section to add in [`make_pe`].
+pub struct TestSection<'a> {
+    pub name: &'a str,
+    pub data: &'a [u8],
+}
+
+impl<'a> TestSection<'a> {
+    pub fn new(name: &'a str, data: &'a [u8]) -> Self {
+        Self { name, data }
+    }
+}
+
+const FILE_ALIGNMENT: usize = 0x200;
+const SECTION_ALIGNMENT: usize = 0x1000;
+
+fn align(val: usize, alignment: usize) -> usize {
+    val.div_ceil(alignment) * alignment
+}
+
+fn put_u16(data: &mut [u8], offset: usize, val: u16) {
+    data[offset..offset + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+fn put_u32(data: &mut [u8], offset: usize, val: usize) {
+    let val = u32::try_from(val).unwrap();
+    data[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+/// Create a PE32+ executable with only a `.sbat` section containing
+/// `sbat`.
+pub fn make_sbat_pe(sbat: &[u8]) -> Vec<u8> {
+    make_pe(&[TestSection::new(".sbat", sbat)], true)
+}
+
+/// Create a minimal x86_64 PE executable containing `sections`.
+///
+/// The headers are just big enough to hold the section table. Section
+/// names longer than eight bytes are stored in a string table, as done
+/// by the GNU toolchain for sections such as `.sbatlevel`.
+pub fn make_pe(sections: &[TestSection], pe32_plus: bool) -> Vec<u8> {
+    let pe_offset = 0x40;
+    let optional_header_size = if pe32_plus { 240 } else { 224 };
+    let optional_header_offset = pe_offset + 24;
+    let section_table_offset = optional_header_offset + optional_header_size;
+    let headers_size =
+        align(section_table_offset + 40 * sections.len(), FILE_ALIGNMENT);
+
+    let mut data = vec![0; headers_size];
+    data[..2].copy_from_slice(b"MZ");
+    put_u32(&mut data, 0x3c, pe_offset);
+    data[pe_offset..pe_offset + 4].copy_from_slice(b"PE\0\0");
+    let coff = pe_offset + 4;
+    put_u16(&mut data, coff, 0x8664);
+    put_u16(&mut data, coff + 2, u16::try_from(sections.len()).unwrap());
+    put_u16(
+        &mut data,
+        coff + 16,
+        u16::try_from(optional_header_size).unwrap(),
+    );
+    let opt = optional_header_offset;
+    put_u16(&mut data, opt, if pe32_plus { 0x20b } else { 0x10b });
+    put_u32(&mut data, opt + 32, SECTION_ALIGNMENT);
+    put_u32(&mut data, opt + 36, FILE_ALIGNMENT);
+    put_u32(&mut data, opt + 60, headers_size);
+    // EFI application subsystem.
+    put_u16(&mut data, opt + 68, 10);
+    // Number of data directories.
+    put_u32(&mut data, opt + if pe32_plus { 108 } else { 92 }, 16);
+
+    let mut strings = vec![0; 4];
+    let mut virtual_address = SECTION_ALIGNMENT;
+    for (i, section) in sections.iter().enumerate() {
+        let header = section_table_offset + i * 40;
+        if section.name.len() > 8 {
+            let name = format!("/{}", strings.len());
+            data[header..header + name.len()].copy_from_slice(name.as_bytes());
+            strings.extend(section.name.as_bytes());
+            strings.push(0);
+        } else {
+            data[header..header + section.name.len()]
+                .copy_from_slice(section.name.as_bytes());
+        }
+
+        let raw_size = align(section.data.len(), FILE_ALIGNMENT);
+        let raw_offset = data.len();
+        put_u32(&mut data, header + 8, section.data.len());
+        put_u32(&mut data, header + 12, virtual_address);
+        put_u32(&mut data, header + 16, raw_size);
+        put_u32(&mut data, header + 20, raw_offset);
+        put_u32(&mut data, header + 36, 0x4000_0040);
+
+        data.extend(section.data);
+        data.resize(raw_offset + raw_size, 0);
+        virtual_address += align(section.data.len().max(1), SECTION_ALIGNMENT);
+    }
+    put_u32(&mut data, opt + 56, virtual_address);
+
+    if strings.len() > 4 {
+        let symbol_table_offset = data.len();
+        put_u32(&mut data, coff + 8, symbol_table_offset);
+        let len = strings.len();
+        put_u32(&mut strings, 0, len);
+        data.extend(strings);
+    }
+
+    data
+}
+
+/// Create a minimal x86_64 TE image containing `sections`.
+///
+/// The stripped size is set as if the TE header replaced PE headers of
+/// [`FILE_ALIGNMENT`] bytes, so section file offsets must be adjusted
+/// to find the data.
+pub fn make_te(sections: &[TestSection]) -> Vec<u8> {
+    const TE_HEADER_SIZE: usize = 40;
+
+    let headers_size = TE_HEADER_SIZE + 40 * sections.len();
+    let stripped_size = FILE_ALIGNMENT;
+    let mut data = vec![0; headers_size];
+    data[..2].copy_from_slice(b"VZ");
+    put_u16(&mut data, 2, 0x8664);
+    data[4] = u8::try_from(sections.len()).unwrap();
+    // EFI boot service driver subsystem.
+    data[5] = 11;
+    put_u16(&mut data, 6, u16::try_from(stripped_size).unwrap());
+
+    for (i, section) in sections.iter().enumerate() {
+        let header = TE_HEADER_SIZE + i * 40;
+        data[header..header + section.name.len()]
+            .copy_from_slice(section.name.as_bytes());
+        let raw_offset = data.len() + stripped_size - TE_HEADER_SIZE;
+        put_u32(&mut data, header + 8, section.data.len());
+        put_u32(&mut data, header + 16, section.data.len());
+        put_u32(&mut data, header + 20, raw_offset);
+        data.extend(section.data);
+    }
+    data
+}
+
+/// Create an FFS section of `section_type` containing `body`.
+pub fn make_ffs_section(section_type: u8, body: &[u8]) -> Vec<u8> {
+    let size = u32::try_from(body.len() + 4).unwrap();
+    assert!(size < 0xff_ffff);
+    let mut section = size.to_le_bytes().to_vec();
+    section[3] = section_type;
+    section.extend(body);
+    section
+}
+
+/// FFS file to add in [`make_fv`]: name GUID, file type, and sections.
+pub type TestFfsFile<'a> = ([u8; 16], u8, &'a [Vec<u8>]);
+
+/// Create an FFS2 firmware volume with erase polarity 1 containing
+/// `files`.
+pub fn make_fv(files: &[TestFfsFile]) -> Vec<u8> {
+    // EFI_FIRMWARE_FILE_SYSTEM2_GUID.
+    const FFS2_GUID: [u8; 16] = [
+        0x78, 0xe5, 0x8c, 0x8c, 0x3d, 0x8a, 0x1c, 0x4f, 0x99, 0x35, 0x89, 0x61,
+        0x85, 0xc3, 0x2d, 0xd3,
+    ];
+    const HEADER_SIZE: usize = 72;
+
+    let mut fv = vec![0; HEADER_SIZE];
+    fv[16..32].copy_from_slice(&FFS2_GUID);
+    fv[40..44].copy_from_slice(b"_FVH");
+    // Erase polarity.
+    put_u32(&mut fv, 44, 0x800);
+    put_u16(&mut fv, 48, u16::try_from(HEADER_SIZE).unwrap());
+    for (name, file_type, sections) in files {
+        let mut body = Vec::new();
+        for section in *sections {
+            body.resize(body.len().next_multiple_of(4), 0);
+            body.extend(section);
+        }
+
+        fv.resize(fv.len().next_multiple_of(8), 0xff);
+        let mut header = vec![0; 24];
+        header[..16].copy_from_slice(name);
+        header[18] = *file_type;
+        put_u32(&mut header, 20, body.len() + 24);
+        // Header and data valid (inverted for erase polarity 1).
+        header[23] = 0xf8;
+        fv.extend(header);
+        fv.extend(body);
+    }
+    // Free space.
+    fv.resize(fv.len().next_multiple_of(8) + 64, 0xff);
+    let len = u64::try_from(fv.len()).unwrap();
+    fv[32..40].copy_from_slice(&len.to_le_bytes());
+    fv
+}
+
+/// Create a FAT filesystem image of `fat_type` containing `files`.
+pub fn make_fat(fat_type: fatfs::FatType, files: &[(&str, &[u8])]) -> Vec<u8> {
+    let size: usize = match fat_type {
+        fatfs::FatType::Fat12 => 1024 * 1024,
+        fatfs::FatType::Fat16 => 8 * 1024 * 1024,
+        fatfs::FatType::Fat32 => 40 * 1024 * 1024,
+    };
+    let mut disk = Cursor::new(vec![0; size]);
+    fatfs::format_volume(
+        &mut disk,
+        fatfs::FormatVolumeOptions::new()
+            .fat_type(fat_type)
+            .bytes_per_cluster(512),
+    )
+    .unwrap();
+    disk.set_position(0);
+
+    {
+        let fs =
+            fatfs::FileSystem::new(&mut disk, fatfs::FsOptions::new()).unwrap();
+        assert_eq!(fs.fat_type(), fat_type);
+        let root = fs.root_dir();
+        for (path, data) in files {
+            if let Some((dir, _)) = path.rsplit_once('/') {
+                let mut dir_path = String::new();
+                for component in dir.split('/') {
+                    dir_path.push_str(component);
+                    let _ = root.create_dir(&dir_path);
+                    dir_path.push('/');
+                }
+            }
+            root.create_file(path).unwrap().write_all(data).unwrap();
+        }
+    }
+    disk.into_inner()
+}
+
+/// Create a GPT disk image with 512-byte sectors containing
+/// `partitions`, each with a type GUID and contents.
+pub fn make_gpt(partitions: &[([u8; 16], &[u8])]) -> Vec<u8> {
+    let mut disk = vec![0; 34 * 512];
+    // Protective MBR.
+    disk[510..512].copy_from_slice(&[0x55, 0xaa]);
+    disk[446 + 4] = 0xee;
+
+    let header = 512;
+    disk[header..header + 8].copy_from_slice(b"EFI PART");
+    disk[header + 72..header + 80].copy_from_slice(&2u64.to_le_bytes());
+    disk[header + 80..header + 84].copy_from_slice(&128u32.to_le_bytes());
+    disk[header + 84..header + 88].copy_from_slice(&128u32.to_le_bytes());
+
+    for (i, (type_guid, data)) in partitions.iter().enumerate() {
+        let first_lba = disk.len() / 512;
+        disk.extend(*data);
+        disk.resize(disk.len().next_multiple_of(512), 0);
+        let last_lba = disk.len() / 512 - 1;
+
+        let entry = 1024 + i * 128;
+        disk[entry..entry + 16].copy_from_slice(type_guid);
+        disk[entry + 32..entry + 40]
+            .copy_from_slice(&u64::try_from(first_lba).unwrap().to_le_bytes());
+        disk[entry + 40..entry + 48]
+            .copy_from_slice(&u64::try_from(last_lba).unwrap().to_le_bytes());
+    }
+    disk
+}
+
+fn iso_dir_record(
+    name: &[u8],
+    sector: usize,
+    size: usize,
+    is_dir: bool,
+) -> Vec<u8> {
+    let len = (33 + name.len()).next_multiple_of(2);
+    let mut record = vec![0; len];
+    record[0] = u8::try_from(len).unwrap();
+    let sector = u32::try_from(sector).unwrap();
+    let size = u32::try_from(size).unwrap();
+    record[2..6].copy_from_slice(&sector.to_le_bytes());
+    record[6..10].copy_from_slice(&sector.to_be_bytes());
+    record[10..14].copy_from_slice(&size.to_le_bytes());
+    record[14..18].copy_from_slice(&size.to_be_bytes());
+    if is_dir {
+        record[25] = 0x02;
+    }
+    record[28..30].copy_from_slice(&1u16.to_le_bytes());
+    record[30..32].copy_from_slice(&1u16.to_be_bytes());
+    record[32] = u8::try_from(name.len()).unwrap();
+    record[33..33 + name.len()].copy_from_slice(name);
+    record
+}
+
+/// Create an ISO9660 image containing `files`, with just a primary
+/// volume descriptor. If `efi_boot_image` is set, an El Torito boot
+/// catalog is added with that image for the EFI platform.
+///
+/// Paths should use ISO9660 names (uppercase, at most one dot).
+pub fn make_iso(
+    files: &[(&str, &[u8])],
+    efi_boot_image: Option<&[u8]>,
+) -> Vec<u8> {
+    use std::collections::BTreeMap;
+
+    const SECTOR: usize = 2048;
+
+    // Map from directory path to its entries: name, and whether the
+    // entry is a directory.
+    let mut dirs: BTreeMap<String, Vec<(String, bool)>> = BTreeMap::new();
+    dirs.insert(String::new(), Vec::new());
+    for (path, _) in files {
+        let mut parent = String::new();
+        let components: Vec<_> = path.split('/').collect();
+        for (i, component) in components.iter().enumerate() {
+            let is_dir = i + 1 < components.len();
+            let entry = (component.to_string(), is_dir);
+            let entries = dirs.get_mut(&parent).unwrap();
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+            if is_dir {
+                parent = format!("{parent}/{component}");
+                dirs.entry(parent.clone()).or_default();
+            }
+        }
+    }
+
+    // Lay out the sectors: volume descriptors, then the boot catalog
+    // and image, then directories, then files.
+    let num_descriptors = if efi_boot_image.is_some() { 3 } else { 2 };
+    let mut next_sector = 16 + num_descriptors;
+    let mut boot_sectors = None;
+    if let Some(image) = efi_boot_image {
+        boot_sectors = Some((next_sector, next_sector + 1));
+        next_sector += 1 + image.len().div_ceil(SECTOR);
+    }
+    let mut dir_sectors = BTreeMap::new();
+    for dir in dirs.keys() {
+        dir_sectors.insert(dir.clone(), next_sector);
+        next_sector += 1;
+    }
+    let mut file_sectors = BTreeMap::new();
+    for (path, data) in files {
+        file_sectors.insert(format!("/{path}"), (next_sector, data.len()));
+        next_sector += data.len().div_ceil(SECTOR).max(1);
+    }
+
+    let mut iso = vec![0; next_sector * SECTOR];
+    let mut descriptor = |index: usize, kind: u8| {
+        let start = (16 + index) * SECTOR;
+        iso[start] = kind;
+        iso[start + 1..start + 6].copy_from_slice(b"CD001");
+        iso[start + 6] = 1;
+        start
+    };
+    let pvd = descriptor(0, 1);
+    let boot_record = efi_boot_image.is_some().then(|| descriptor(1, 0));
+    descriptor(num_descriptors - 1, 255);
+
+    let root = iso_dir_record(&[0], dir_sectors[""], SECTOR, true);
+    iso[pvd + 156..pvd + 156 + root.len()].copy_from_slice(&root);
+
+    if let (Some(boot_record), Some((catalog, image_sector))) =
+        (boot_record, boot_sectors)
+    {
+        let id = b"EL TORITO SPECIFICATION";
+        iso[boot_record + 7..boot_record + 7 + id.len()].copy_from_slice(id);
+        put_u32(&mut iso, boot_record + 0x47, catalog);
+
+        // Validation entry and default entry for x86 BIOS, then a
+        // section for EFI.
+        let catalog = catalog * SECTOR;
+        iso[catalog] = 1;
+        iso[catalog + 30..catalog + 32].copy_from_slice(&[0x55, 0xaa]);
+        iso[catalog + 32] = 0x88;
+        put_u32(&mut iso, catalog + 40, image_sector);
+        iso[catalog + 64] = 0x91;
+        iso[catalog + 65] = 0xef;
+        put_u16(&mut iso, catalog + 66, 1);
+        iso[catalog + 96] = 0x88;
+        put_u32(&mut iso, catalog + 104, image_sector);
+
+        let image = efi_boot_image.unwrap();
+        let start = image_sector * SECTOR;
+        iso[start..start + image.len()].copy_from_slice(image);
+    }
+
+    for (dir, entries) in &dirs {
+        let mut records = iso_dir_record(&[0], dir_sectors[dir], SECTOR, true);
+        let parent = dir.rsplit_once('/').map_or("", |(parent, _)| parent);
+        records.extend(iso_dir_record(&[1], dir_sectors[parent], SECTOR, true));
+
+        let mut entries = entries.clone();
+        entries.sort();
+        for (name, is_dir) in entries {
+            let path = format!("{dir}/{name}");
+            let record = if is_dir {
+                iso_dir_record(
+                    name.as_bytes(),
+                    dir_sectors[&path],
+                    SECTOR,
+                    true,
+                )
+            } else {
+                let (sector, size) = file_sectors[&path];
+                iso_dir_record(
+                    format!("{name};1").as_bytes(),
+                    sector,
+                    size,
+                    false,
+                )
+            };
+            records.extend(record);
+        }
+        assert!(records.len() <= SECTOR);
+        let start = dir_sectors[dir] * SECTOR;
+        iso[start..start + records.len()].copy_from_slice(&records);
+    }
+
+    for (path, data) in files {
+        let start = file_sectors[&format!("/{path}")].0 * SECTOR;
+        iso[start..start + data.len()].copy_from_slice(data);
+    }
+
+    iso
+}
+
+/// Write `revocations` to `revocations.csv` in `dir`, and return
+/// [`RevocationArgs`] that read it.
+pub fn csv_revocation_args(dir: &Path, revocations: &[u8]) -> RevocationArgs {
+    let csv = dir.join("revocations.csv");
+    fs::write(&csv, revocations).unwrap();
+    RevocationArgs {
+        revocations: Some(csv),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Inspect Unified Kernel Images.
+
+use crate::check::{CheckStatus, RevocationArgs, check_image_sbat};
+use crate::{image_sbat_to_table_string, parse_error_to_string};
+use anyhow::{Result, anyhow};
+use fs_err as fs;
+use sbat::uki::{UKIFY_DEFAULT_SBAT, UNAME_SECTION_NAME, Uki};
+use sbat::{GenerationConflict, ImageSbat, RevocationIndex};
+use std::io::Write;
+use std::path::Path;
+
+/// Check one part of a UKI. `image_sbat` is `None` if the part's SBAT
+/// data could not be read; the error was already written to `out`.
+fn check_part(
+    index: &RevocationIndex,
+    name: &str,
+    image_sbat: Option<&ImageSbat>,
+    out: &mut impl Write,
+) -> Result<CheckStatus> {
+    match image_sbat {
+        Some(image_sbat) => check_image_sbat(index, name, image_sbat, out),
+        None => {
+            writeln!(out, "{name}: Error")?;
+            Ok(CheckStatus::Error)
+        }
+    }
+}
+
+/// Print the SBAT data of the UKI at `input`: the stub's, the embedded
+/// kernel's, and the merged data that ukify would produce from them.
+/// Then check each of them against the revocation data.
+///
+/// `extra_sbat` is a CSV file with the SBAT data passed to ukify with
+/// `--sbat`; if not set, ukify's default `uki` entry is used.
+///
+/// Errors in the SBAT data are written to `out` and reported as
+/// [`CheckStatus::Error`]. An error is returned if the input is not a
+/// UKI.
+pub fn inspect_uki(
+    revocation_args: &RevocationArgs,
+    extra_sbat: Option<&Path>,
+    input: &Path,
+    out: &mut impl Write,
+) -> Result<CheckStatus> {
+    let revocations = revocation_args.load()?;
+    let index = RevocationIndex::new(&revocations);
+
+    let extra_data = match extra_sbat {
+        Some(path) => fs::read(path)?,
+        None => UKIFY_DEFAULT_SBAT.as_bytes().to_vec(),
+    };
+    let extra = ImageSbat::parse(&extra_data).map_err(|err| {
+        anyhow!("--sbat: {}", parse_error_to_string(&extra_data, &err))
+    })?;
+
+    let data = fs::read(input)?;
+    let uki = Uki::parse(&data)
+        .map_err(|err| anyhow!("{}: {err}", input.display()))?;
+
+    if let Some(uname) = uki.section_data(UNAME_SECTION_NAME) {
+        writeln!(out, "Kernel release: {}\n", uname.escape_ascii())?;
+    }
+
+    let stub = uki.stub_sbat();
+    writeln!(out, "Stub SBAT:")?;
+    match stub {
+        Ok(image_sbat) => {
+            writeln!(out, "{}\n", image_sbat_to_table_string(image_sbat))?;
+        }
+        Err(ref err) => writeln!(out, "Error: {err}\n")?,
+    }
+
+    let kernel = uki.kernel_sbat();
+    writeln!(out, "Kernel SBAT:")?;
+    match kernel {
+        Ok(Some(image_sbat)) => {
+            writeln!(out, "{}\n", image_sbat_to_table_string(image_sbat))?;
+        }
+        Ok(None) => writeln!(out, "(none)\n")?,
+        Err(ref err) => writeln!(out, "Error: {err}\n")?,
+    }
+
+    let merged = uki.merged_sbat(Some(extra), GenerationConflict::Error);
+    writeln!(out, "Merged SBAT (as produced by ukify):")?;
+    match merged {
+        Ok(ref image_sbat) => {
+            writeln!(out, "{}\n", image_sbat_to_table_string(image_sbat))?;
+        }
+        Err(ref err) => writeln!(out, "Error: {err}\n")?,
+    }
+
+    let mut status = check_part(&index, "stub", stub.ok(), out)?;
+    if !matches!(kernel, Ok(None)) {
+        let kernel = kernel.ok().flatten();
+        status = status.max(check_part(&index, "kernel", kernel, out)?);
+    }
+    let merged = merged.as_deref().ok();
+    status = status.max(check_part(&index, "merged", merged, out)?);
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{
+        TestSection, csv_revocation_args, make_pe, make_sbat_pe,
+    };
+    use tempfile::TempDir;
+
+    const STUB_SBAT: &[u8] = b"sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md\nsystemd-stub,1,The systemd Developers,systemd,256,https://systemd.io/\n";
+    const KERNEL_SBAT: &[u8] =
+        b"sbat,1\nlinux,1,The Linux Developers,linux,6.10,https://linux.org\n";
+
+    fn make_uki(dir: &TempDir, kernel_sbat: &[u8]) -> std::path::PathBuf {
+        let kernel = make_sbat_pe(kernel_sbat);
+        let uki = make_pe(
+            &[
+                TestSection::new(".sbat", STUB_SBAT),
+                TestSection::new(".uname", b"6.10.0\0"),
+                TestSection::new(".linux", &kernel),
+            ],
+            true,
+        );
+        let path = dir.path().join("linux.efi");
+        fs::write(&path, uki).unwrap();
+        path
+    }
+
+    fn inspect(
+        dir: &TempDir,
+        revocations: &[u8],
+        extra_sbat: Option<&[u8]>,
+        input: &Path,
+    ) -> (CheckStatus, String) {
+        let args = csv_revocation_args(dir.path(), revocations);
+        let extra_path = dir.path().join("extra.csv");
+        if let Some(extra_sbat) = extra_sbat {
+            fs::write(&extra_path, extra_sbat).unwrap();
+        }
+
+        let mut out = Vec::new();
+        let status = inspect_uki(
+            &args,
+            extra_sbat.map(|_| extra_path.as_path()),
+            input,
+            &mut out,
+        )
+        .unwrap();
+        (status, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_inspect_uki() {
+        let dir = TempDir::new().unwrap();
+        let path = make_uki(&dir, KERNEL_SBAT);
+
+        let (status, out) = inspect(&dir, b"sbat,1\n", None, &path);
+        assert_eq!(status, CheckStatus::Allowed);
+        assert!(out.starts_with("Kernel release: 6.10.0\n"), "{out}");
+        assert!(out.contains("| systemd-stub | 1 "), "{out}");
+        assert!(out.contains("| linux     | 1 "), "{out}");
+        assert!(out.contains("| uki          | 1 "), "{out}");
+        assert!(
+            out.ends_with("stub: Allowed\nkernel: Allowed\nmerged: Allowed\n"),
+            "{out}"
+        );
+
+        let (status, out) = inspect(
+            &dir,
+            b"sbat,1\nlinux,2\n",
+            Some(b"sbat,1\nmyuki,1\n"),
+            &path,
+        );
+        assert_eq!(status, CheckStatus::Revoked);
+        assert!(out.contains("| myuki        | 1 "), "{out}");
+        assert!(!out.contains("| uki "), "{out}");
+        assert!(
+            out.ends_with(
+                "stub: Allowed\n\
+                 kernel: Revoked\n  linux: generation 1 is less than 2\n\
+                 merged: Revoked\n  linux: generation 1 is less than 2\n"
+            ),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn test_inspect_uki_errors() {
+        let dir = TempDir::new().unwrap();
+        let path = make_uki(&dir, b"sbat,1\nlinux,x\n");
+        let (status, out) = inspect(&dir, b"sbat,1\n", None, &path);
+        assert_eq!(status, CheckStatus::Error);
+        assert!(
+            out.contains("Kernel SBAT:\nError: kernel: invalid"),
+            "{out}"
+        );
+        assert!(
+            out.ends_with("stub: Allowed\nkernel: Error\nmerged: Error\n"),
+            "{out}"
+        );
+
+        // Not a UKI.
+        let path = dir.path().join("shimx64.efi");
+        fs::write(&path, make_sbat_pe(STUB_SBAT)).unwrap();
+        let args = csv_revocation_args(dir.path(), b"sbat,1\n");
+        let err = inspect_uki(&args, None, &path, &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().ends_with("missing .linux section"), "{err}");
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Compare iterating over entries with `ImageSbat::entries`, which
+//! parses the CSV again on each iteration, against the stored entries
+//! of `ImageSbatIndex` and `ImageSbatIndexArray`.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use sbat::{ImageSbat, ImageSbatIndex, ImageSbatIndexArray};
+use std::fmt::Write;
+use std::hint::black_box;
+
+/// Number of entries in the generated SBAT data.
+const NUM_ENTRIES: usize = 32;
+
+fn make_sbat() -> String {
+    let mut sbat = String::from(
+        "sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md\n",
+    );
+    for i in 1..NUM_ENTRIES {
+        writeln!(
+            sbat,
+            "comp{i},{i},Example Vendor,comp{i},1.2.3,https://example.com/comp{i}"
+        )
+        .unwrap();
+    }
+    sbat
+}
+
+fn sum_generations<'a>(
+    entries: impl Iterator<Item = &'a sbat::Entry<'a>>,
+) -> u32 {
+    entries
+        .map(|entry| entry.component.generation.to_u32())
+        .sum()
+}
+
+fn bench_image_sbat(c: &mut Criterion) {
+    let sbat = make_sbat();
+    let input = sbat.as_bytes();
+
+    let mut group = c.benchmark_group("parse_and_iterate");
+    group.bench_function("csv_iter", |b| {
+        b.iter(|| {
+            let image_sbat = ImageSbat::parse(black_box(input)).unwrap();
+            image_sbat
+                .entries()
+                .map(|entry| entry.component.generation.to_u32())
+                .sum::<u32>()
+        });
+    });
+    group.bench_function("index", |b| {
+        b.iter(|| {
+            let index = ImageSbatIndex::parse(black_box(input)).unwrap();
+            sum_generations(index.entries().iter())
+        });
+    });
+    group.bench_function("index_array", |b| {
+        b.iter(|| {
+            let index =
+                ImageSbatIndexArray::<NUM_ENTRIES>::parse(black_box(input))
+                    .unwrap();
+            sum_generations(index.entries().iter())
+        });
+    });
+    group.finish();
+
+    // Iterate many times over data that was parsed once.
+    let image_sbat = ImageSbat::parse(input).unwrap();
+    let index = ImageSbatIndex::parse(input).unwrap();
+    let mut group = c.benchmark_group("iterate");
+    group.bench_function("csv_iter", |b| {
+        b.iter(|| {
+            black_box(image_sbat)
+                .entries()
+                .map(|entry| entry.component.generation.to_u32())
+                .sum::<u32>()
+        });
+    });
+    group.bench_function("index", |b| {
+        b.iter(|| sum_generations(black_box(&index).entries().iter()));
+    });
+    group.bench_function("random_access", |b| {
+        b.iter(|| black_box(&index).entry(NUM_ENTRIES / 2));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_image_sbat);
+criterion_main!(benches);
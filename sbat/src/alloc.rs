@@ -6,9 +6,18 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use crate::{ImageSbat, ParseError, RevocationSbat};
-use ascii::AsciiString;
+use crate::csv::{parse_lenient, write_record};
+use crate::image::{NUM_ENTRY_FIELDS, parse_entries};
+use crate::revocations::MAX_HEADER_FIELDS;
+use crate::{
+    BuildError, Component, Entry, Generation, ImageSbat, ImageSbatWriter,
+    ParseError, ParseWarning, RevocationDate, RevocationSbat,
+};
+use ascii::{AsciiStr, AsciiString};
+use core::fmt::{self, Display, Formatter};
 use core::ops::Deref;
+use rust_alloc::collections::BTreeSet;
+use rust_alloc::vec::Vec;
 
 /// Owned image SBAT metadata.
 ///
@@ -33,6 +42,154 @@ impl ImageSbatOwned {
         let sbat = ImageSbat::parse(input)?;
         Ok(Self(sbat.as_csv().to_ascii_string()))
     }
+
+    /// Parse SBAT metadata from raw CSV, passing any warnings to
+    /// `on_warning`.
+    ///
+    /// See [`ImageSbat::parse_with_warnings`] for details.
+    pub fn parse_with_warnings(
+        input: &[u8],
+        on_warning: impl FnMut(ParseWarning),
+    ) -> Result<Self, ParseError> {
+        let sbat = ImageSbat::parse_with_warnings(input, on_warning)?;
+        Ok(Self(sbat.as_csv().to_ascii_string()))
+    }
+
+    /// Parse SBAT metadata from raw CSV, skipping invalid records.
+    ///
+    /// Unlike [`parse`], this does not stop at the first error. Each
+    /// record that fails to parse (due to non-ASCII data, a special
+    /// character, an invalid generation, or too few fields) is skipped
+    /// and its error is added to the returned list. The returned
+    /// `ImageSbatOwned` contains all the valid records. The error
+    /// positions refer to `input`.
+    ///
+    /// [`parse`]: Self::parse
+    #[must_use]
+    pub fn parse_lenient(input: &[u8]) -> (Self, Vec<ParseError>) {
+        let (csv, errors) =
+            parse_lenient::<NUM_ENTRY_FIELDS>(input, |record| {
+                Component::from_record(record).map(|_| ())
+            });
+        (Self(csv), errors)
+    }
+}
+
+/// How [`ImageSbatOwned::merge`] handles a component name that has
+/// different generations in the inputs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GenerationConflict {
+    /// Fail with [`MergeError::ConflictingGenerations`].
+    Error,
+
+    /// Keep the entry with the highest generation.
+    Max,
+}
+
+/// Error returned by [`ImageSbatOwned::merge`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MergeError<'a> {
+    /// The same component name has different generations in the
+    /// inputs, and [`GenerationConflict::Error`] was requested.
+    ConflictingGenerations {
+        /// Component name.
+        name: &'a AsciiStr,
+
+        /// Generation of the first entry with this name.
+        first: Generation,
+
+        /// Conflicting generation of a later entry.
+        second: Generation,
+    },
+
+    /// The merged entries could not be written, for example because an
+    /// entry has an empty component name.
+    Build(BuildError),
+}
+
+impl Display for MergeError<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConflictingGenerations {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "component {name} has conflicting generations {first} and \
+                 {second}"
+            ),
+            Self::Build(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl core::error::Error for MergeError<'_> {}
+
+impl ImageSbatOwned {
+    /// Merge several image SBAT metadata sets into one. This is used to
+    /// combine the SBAT data of several components (for example the
+    /// stub, kernel and addons of a UKI) into one `.sbat` section.
+    ///
+    /// Entries are kept in the order they first appear, except that the
+    /// `sbat` header entry is moved to the front. Entries with the same
+    /// component name and generation are only included once; the first
+    /// one is kept, including its vendor data. If a component name has
+    /// different generations, `on_conflict` decides whether to return an
+    /// error or to keep the entry with the highest generation (in the
+    /// position of the first entry with that name).
+    ///
+    /// An error is also returned if an entry has an empty component
+    /// name, which parsing allows but writing does not.
+    pub fn merge<'a>(
+        images: &[&'a ImageSbat],
+        on_conflict: GenerationConflict,
+    ) -> Result<Self, MergeError<'a>> {
+        let mut entries: Vec<Entry<'a>> = Vec::new();
+        for entry in images.iter().flat_map(|image| image.entries()) {
+            let name = entry.component.name;
+            let Some(existing) =
+                entries.iter_mut().find(|e| e.component.name == name)
+            else {
+                entries.push(entry);
+                continue;
+            };
+
+            let first = existing.component.generation;
+            let second = entry.component.generation;
+            if first == second {
+                continue;
+            }
+            match on_conflict {
+                GenerationConflict::Error => {
+                    return Err(MergeError::ConflictingGenerations {
+                        name,
+                        first,
+                        second,
+                    });
+                }
+                GenerationConflict::Max => {
+                    if second > first {
+                        *existing = entry;
+                    }
+                }
+            }
+        }
+
+        // The header must come first.
+        if let Some(index) =
+            entries.iter().position(|e| e.component.name == "sbat")
+        {
+            let header = entries.remove(index);
+            entries.insert(0, header);
+        }
+
+        let mut builder = ImageSbatBuilder::new();
+        for entry in entries {
+            builder.push(entry);
+        }
+        builder.build().map_err(MergeError::Build)
+    }
 }
 
 impl Deref for ImageSbatOwned {
@@ -55,6 +212,125 @@ impl PartialEq<ImageSbatOwned> for &ImageSbat {
     }
 }
 
+/// Builder for [`ImageSbatOwned`].
+///
+/// Entries are written out as CSV in the order they were added. Each
+/// field is validated when [`build`] is called, and the result is
+/// guaranteed to be valid image SBAT metadata.
+///
+/// See [`ImageSbatWriter`] for a variant that does not require
+/// allocation.
+///
+/// [`build`]: Self::build
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ImageSbatBuilder<'a> {
+    entries: Vec<Entry<'a>>,
+}
+
+impl<'a> ImageSbatBuilder<'a> {
+    /// Create an empty `ImageSbatBuilder`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an [`Entry`].
+    pub fn push(&mut self, entry: Entry<'a>) -> &mut Self {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Write all entries as CSV and create an [`ImageSbatOwned`].
+    ///
+    /// An error is returned if any component name is empty, or if any
+    /// field contains a character that is not allowed (see
+    /// [`ALLOWED_SPECIAL_CHARS`]).
+    ///
+    /// [`ALLOWED_SPECIAL_CHARS`]: crate::ALLOWED_SPECIAL_CHARS
+    pub fn build(&self) -> Result<ImageSbatOwned, BuildError> {
+        let mut writer = ImageSbatWriter::new(AsciiString::new());
+        for entry in &self.entries {
+            writer.write_entry(entry)?;
+        }
+        Ok(ImageSbatOwned(writer.into_inner()))
+    }
+}
+
+/// Image SBAT metadata with a vector of parsed entries.
+///
+/// The entries are stored as they are validated, so unlike
+/// [`ImageSbat::entries`], accessing them does not parse the CSV again.
+/// This also allows random access with [`entry`].
+///
+/// See [`ImageSbatIndexArray`] for a variant that does not require
+/// allocation.
+///
+/// [`ImageSbatIndexArray`]: crate::ImageSbatIndexArray
+/// [`entry`]: Self::entry
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ImageSbatIndex<'a> {
+    image_sbat: &'a ImageSbat,
+    entries: Vec<Entry<'a>>,
+}
+
+impl<'a> ImageSbatIndex<'a> {
+    /// Parse SBAT metadata from raw CSV.
+    ///
+    /// The input is validated in the same way as [`ImageSbat::parse`].
+    pub fn parse(input: &'a [u8]) -> Result<Self, ParseError> {
+        Self::parse_with_warnings(input, ParseWarning::log)
+    }
+
+    /// Parse SBAT metadata from raw CSV, passing any warnings to
+    /// `on_warning`.
+    ///
+    /// See [`ImageSbat::parse_with_warnings`] for the list of warnings.
+    pub fn parse_with_warnings(
+        input: &'a [u8],
+        on_warning: impl FnMut(ParseWarning),
+    ) -> Result<Self, ParseError> {
+        let mut entries = Vec::new();
+        let image_sbat = parse_entries(input, on_warning, |entry| {
+            entries.push(entry);
+            Ok(())
+        })?;
+        Ok(Self {
+            image_sbat,
+            entries,
+        })
+    }
+
+    /// Get the underlying [`ImageSbat`].
+    #[must_use]
+    pub fn image_sbat(&self) -> &'a ImageSbat {
+        self.image_sbat
+    }
+
+    /// Get the entries.
+    #[must_use]
+    pub fn entries(&self) -> &[Entry<'a>] {
+        &self.entries
+    }
+
+    /// Get the entry at `index`, or `None` if out of range.
+    #[must_use]
+    pub fn entry(&self, index: usize) -> Option<Entry<'a>> {
+        self.entries.get(index).copied()
+    }
+
+    /// Number of entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
 /// Owned revocation SBAT data.
 ///
 /// Typically this data comes from a UEFI variable such as `SbatLevel`.
@@ -78,6 +354,30 @@ impl RevocationSbatOwned {
         let sbat = RevocationSbat::parse(input)?;
         Ok(Self(sbat.as_csv().to_ascii_string()))
     }
+
+    /// Parse SBAT data from raw CSV, passing any warnings to
+    /// `on_warning`.
+    ///
+    /// See [`RevocationSbat::parse_with_warnings`] for details.
+    pub fn parse_with_warnings(
+        input: &[u8],
+        on_warning: impl FnMut(ParseWarning),
+    ) -> Result<Self, ParseError> {
+        let sbat = RevocationSbat::parse_with_warnings(input, on_warning)?;
+        Ok(Self(sbat.as_csv().to_ascii_string()))
+    }
+
+    /// Parse SBAT data from raw CSV, skipping invalid records.
+    ///
+    /// See [`ImageSbatOwned::parse_lenient`] for details.
+    #[must_use]
+    pub fn parse_lenient(input: &[u8]) -> (Self, Vec<ParseError>) {
+        let (csv, errors) =
+            parse_lenient::<MAX_HEADER_FIELDS>(input, |record| {
+                Component::from_record(record).map(|_| ())
+            });
+        (Self(csv), errors)
+    }
 }
 
 impl Deref for RevocationSbatOwned {
@@ -100,9 +400,78 @@ impl PartialEq<RevocationSbatOwned> for &RevocationSbat {
     }
 }
 
+/// Builder for [`RevocationSbatOwned`].
+///
+/// The output starts with an `sbat,<generation>,<date>` header record,
+/// followed by the revoked components in the order they were added.
+/// The result is guaranteed to be valid revocation SBAT data.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RevocationSbatBuilder<'a> {
+    generation: Generation,
+    date: &'a AsciiStr,
+    components: Vec<Component<'a>>,
+}
+
+impl<'a> RevocationSbatBuilder<'a> {
+    /// Create a `RevocationSbatBuilder`. The `generation` is the SBAT
+    /// format generation written in the header record, and `date` is
+    /// the revocation date in `YYYYMMDD` or `YYYYMMDDNN` form.
+    #[must_use]
+    pub fn new(generation: Generation, date: &'a AsciiStr) -> Self {
+        Self {
+            generation,
+            date,
+            components: Vec::new(),
+        }
+    }
+
+    /// Add a revoked [`Component`].
+    pub fn push(&mut self, component: Component<'a>) -> &mut Self {
+        self.components.push(component);
+        self
+    }
+
+    /// Write the header and all components as CSV and create a
+    /// [`RevocationSbatOwned`].
+    ///
+    /// An error is returned if the date is invalid, if any component
+    /// name is empty or is used more than once (including the `sbat`
+    /// name used by the header), or if any component name contains a
+    /// character that is not allowed (see [`ALLOWED_SPECIAL_CHARS`]).
+    ///
+    /// [`ALLOWED_SPECIAL_CHARS`]: crate::ALLOWED_SPECIAL_CHARS
+    #[allow(clippy::missing_panics_doc)]
+    pub fn build(&self) -> Result<RevocationSbatOwned, BuildError> {
+        if RevocationDate::from_ascii(self.date).is_err() {
+            return Err(BuildError::InvalidDate);
+        }
+
+        // OK to unwrap: the string is ASCII.
+        let header = Component::new(
+            AsciiStr::from_ascii("sbat").unwrap(),
+            self.generation,
+        );
+
+        let mut csv = AsciiString::new();
+        write_record(&mut csv, &header, &[Some(self.date)])?;
+
+        let mut names = BTreeSet::from([header.name]);
+        for component in &self.components {
+            if !names.insert(component.name) {
+                return Err(BuildError::DuplicateComponent);
+            }
+            write_record(&mut csv, component, &[])?;
+        }
+
+        Ok(RevocationSbatOwned(csv))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{ParseErrorKind, Vendor};
+    use ascii::AsciiStr;
 
     const CSV: &[u8] = b"compA,1\ncompB,2\ncompC,3";
 
@@ -114,6 +483,262 @@ mod tests {
         assert_eq!(r2, r1);
     }
 
+    #[test]
+    fn test_image_sbat_builder() {
+        let ascii = |s| AsciiStr::from_ascii(s).unwrap();
+
+        let shim = Entry::new(
+            Component::new(ascii("shim"), Generation::new(4).unwrap()),
+            Vendor {
+                name: Some(ascii("UEFI shim")),
+                package_name: Some(ascii("shim")),
+                version: Some(ascii("1")),
+                url: Some(ascii("https://github.com/rhboot/shim")),
+            },
+        );
+        let partial = Entry::new(
+            Component::new(ascii("compA"), Generation::new(2).unwrap()),
+            Vendor {
+                version: Some(ascii("1.2.3")),
+                ..Default::default()
+            },
+        );
+
+        let header = Entry::new(
+            Component::new(ascii("sbat"), Generation::default()),
+            Vendor::default(),
+        );
+        let image_sbat = ImageSbatBuilder::new()
+            .push(header)
+            .push(shim)
+            .push(partial)
+            .build()
+            .unwrap();
+        assert_eq!(
+            image_sbat.as_csv(),
+            "\
+sbat,1
+shim,4,UEFI shim,shim,1,https://github.com/rhboot/shim
+compA,2,,,1.2.3
+"
+        );
+
+        // The output must be accepted by the parser.
+        assert_eq!(
+            ImageSbat::parse(image_sbat.as_csv().as_bytes()),
+            Ok(&*image_sbat)
+        );
+        assert_eq!(image_sbat.entries().nth(1), Some(shim));
+
+        // A component without a name is rejected.
+        assert_eq!(
+            ImageSbatBuilder::new().push(Entry::default()).build(),
+            Err(BuildError::EmptyComponentName)
+        );
+    }
+
+    #[test]
+    fn test_image_sbat_merge() {
+        let stub = ImageSbat::parse(
+            b"sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md
+systemd-stub,1,The systemd Developers,systemd,256,https://systemd.io/
+",
+        )
+        .unwrap();
+        let kernel = ImageSbat::parse(
+            b"linux,1,The Linux Developers,linux,6.10,https://linux.org
+sbat,1,Kernel SBAT header
+systemd-stub,1,Duplicate
+",
+        )
+        .unwrap();
+        let addon =
+            ImageSbat::parse(b"sbat,1\nlinux,2,Addon\naddon,1\n").unwrap();
+
+        let merged = ImageSbatOwned::merge(
+            &[stub, kernel, addon],
+            GenerationConflict::Max,
+        )
+        .unwrap();
+        assert_eq!(
+            merged.as_csv(),
+            "\
+sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md
+systemd-stub,1,The systemd Developers,systemd,256,https://systemd.io/
+linux,2,Addon
+addon,1
+"
+        );
+
+        // Without a conflict, the result is the same with either
+        // option.
+        assert_eq!(
+            ImageSbatOwned::merge(&[stub, kernel], GenerationConflict::Error),
+            ImageSbatOwned::merge(&[stub, kernel], GenerationConflict::Max)
+        );
+
+        let err = ImageSbatOwned::merge(
+            &[stub, kernel, addon],
+            GenerationConflict::Error,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            MergeError::ConflictingGenerations {
+                name: AsciiStr::from_ascii("linux").unwrap(),
+                first: Generation::new(1).unwrap(),
+                second: Generation::new(2).unwrap(),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "component linux has conflicting generations 1 and 2"
+        );
+
+        let empty = ImageSbatOwned::merge(&[], GenerationConflict::Error);
+        assert_eq!(empty.unwrap().as_csv(), "");
+
+        let unnamed = ImageSbat::parse(b",1\n").unwrap();
+        assert_eq!(
+            ImageSbatOwned::merge(&[unnamed], GenerationConflict::Max),
+            Err(MergeError::Build(BuildError::EmptyComponentName))
+        );
+    }
+
+    #[test]
+    fn test_image_sbat_builder_error() {
+        let entry = Entry::new(
+            Component::new(
+                AsciiStr::from_ascii("a\nb").unwrap(),
+                Generation::default(),
+            ),
+            Vendor::default(),
+        );
+        assert_eq!(
+            ImageSbatBuilder::new().push(entry).build(),
+            Err(BuildError::SpecialChar(ascii::AsciiChar::LineFeed))
+        );
+    }
+
+    #[test]
+    fn test_image_sbat_parse_lenient() {
+        let input =
+            b"sbat,1\nshim,x,UEFI shim\nfoo\ngrub,\xff\ngrub.a\"b,1\ngrub,3";
+        let (image_sbat, errors) = ImageSbatOwned::parse_lenient(input);
+        assert_eq!(image_sbat.as_csv(), "sbat,1\ngrub,3\n");
+        assert_eq!(
+            errors.iter().map(ParseError::kind).collect::<Vec<_>>(),
+            [
+                &ParseErrorKind::invalid_generation("x"),
+                &ParseErrorKind::TooFewFields,
+                &ParseErrorKind::InvalidAscii,
+                &ParseErrorKind::SpecialChar(ascii::AsciiChar::Quotation),
+            ]
+        );
+        assert_eq!(
+            errors
+                .iter()
+                .map(|err| err.position().unwrap().line)
+                .collect::<Vec<_>>(),
+            [2, 3, 4, 5]
+        );
+
+        // The result is valid.
+        assert_eq!(
+            ImageSbat::parse(image_sbat.as_csv().as_bytes()),
+            Ok(&*image_sbat)
+        );
+
+        // Valid input gives the same result as the strict parser.
+        let (image_sbat, errors) = ImageSbatOwned::parse_lenient(CSV);
+        assert!(errors.is_empty());
+        assert_eq!(
+            image_sbat.entries().collect::<Vec<_>>(),
+            ImageSbat::parse(CSV).unwrap().entries().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_revocation_sbat_parse_lenient() {
+        let (revocations, errors) = RevocationSbatOwned::parse_lenient(
+            b"sbat,1,2023012900\nshim,0\ngrub,3",
+        );
+        assert_eq!(revocations.as_csv(), "sbat,1,2023012900\ngrub,3\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), &ParseErrorKind::invalid_generation("0"));
+    }
+
+    #[test]
+    fn test_revocation_sbat_builder() {
+        let ascii = |s| AsciiStr::from_ascii(s).unwrap();
+        let component = |name, generation| {
+            Component::new(ascii(name), Generation::new(generation).unwrap())
+        };
+
+        let revocations = RevocationSbatBuilder::new(
+            Generation::default(),
+            ascii("2023012900"),
+        )
+        .push(component("shim", 2))
+        .push(component("grub", 3))
+        .push(component("grub.debian", 4))
+        .build()
+        .unwrap();
+        assert_eq!(
+            revocations.as_csv(),
+            "sbat,1,2023012900\nshim,2\ngrub,3\ngrub.debian,4\n"
+        );
+
+        // The output must be accepted by the parser.
+        assert_eq!(
+            RevocationSbat::parse(revocations.as_csv().as_bytes()),
+            Ok(&*revocations)
+        );
+        assert_eq!(revocations.date(), Some(ascii("2023012900")));
+        assert_eq!(
+            revocations.revoked_components().collect::<Vec<_>>(),
+            [
+                component("sbat", 1),
+                component("shim", 2),
+                component("grub", 3),
+                component("grub.debian", 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_revocation_sbat_builder_errors() {
+        let ascii = |s| AsciiStr::from_ascii(s).unwrap();
+        let component =
+            |name| Component::new(ascii(name), Generation::default());
+        let date = ascii("20230129");
+
+        assert_eq!(
+            RevocationSbatBuilder::new(Generation::default(), ascii("2023-01"))
+                .build(),
+            Err(BuildError::InvalidDate)
+        );
+        assert_eq!(
+            RevocationSbatBuilder::new(Generation::default(), date)
+                .push(component("shim"))
+                .push(component("shim"))
+                .build(),
+            Err(BuildError::DuplicateComponent)
+        );
+        assert_eq!(
+            RevocationSbatBuilder::new(Generation::default(), date)
+                .push(component("sbat"))
+                .build(),
+            Err(BuildError::DuplicateComponent)
+        );
+        assert_eq!(
+            RevocationSbatBuilder::new(Generation::default(), date)
+                .push(component("a,b"))
+                .build(),
+            Err(BuildError::SpecialChar(ascii::AsciiChar::Comma))
+        );
+    }
+
     #[test]
     fn test_revocation_sbat_owned() {
         let r1 = RevocationSbat::parse(CSV).unwrap();
@@ -121,4 +746,25 @@ mod tests {
         assert_eq!(r1, r2);
         assert_eq!(r2, r1);
     }
+
+    #[test]
+    fn test_image_sbat_index() {
+        let index = ImageSbatIndex::parse(CSV).unwrap();
+        assert_eq!(index.len(), 3);
+        assert!(!index.is_empty());
+        assert!(
+            index
+                .entries()
+                .iter()
+                .copied()
+                .eq(ImageSbat::parse(CSV).unwrap().entries())
+        );
+        assert_eq!(index.entry(2).unwrap().component.name, "compC");
+        assert_eq!(index.entry(3), None);
+
+        assert_eq!(
+            ImageSbatIndex::parse(b"a,1\nb").unwrap_err().kind(),
+            &ParseErrorKind::TooFewFields
+        );
+    }
 }
@@ -7,8 +7,92 @@
 // except according to those terms.
 
 use crate::csv::Record;
+use crate::error::ParseErrorKind;
 use crate::{Generation, ParseError};
-use ascii::AsciiStr;
+use ascii::{AsciiChar, AsciiStr};
+use core::fmt::{self, Display, Formatter};
+
+/// Name of an SBAT component, such as `grub` or `grub.fedora`.
+///
+/// By convention, names have the form `product` for the upstream
+/// component, or `product.vendor` for a vendor's build of it. For
+/// example, `shim.redhat` is Red Hat's build of `shim`. Revoking a
+/// vendor's name only affects that vendor, while revoking the product
+/// name affects every build that includes the upstream entry.
+///
+/// A valid name contains only ASCII letters, digits, and
+/// [`ALLOWED_SPECIAL_CHARS`], with a `.` separating the product from
+/// the vendor. Neither part may be empty. The vendor itself may
+/// contain further `.` separators.
+///
+/// [`ALLOWED_SPECIAL_CHARS`]: Self::ALLOWED_SPECIAL_CHARS
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ComponentName<'a>(&'a AsciiStr);
+
+impl<'a> ComponentName<'a> {
+    /// Characters allowed in each part of a name, in addition to
+    /// letters and digits.
+    pub const ALLOWED_SPECIAL_CHARS: &'static [AsciiChar] =
+        &[AsciiChar::Minus, AsciiChar::UnderScore];
+
+    /// Create a `ComponentName`. An error is returned if `name` is not
+    /// valid.
+    pub fn new(name: &'a AsciiStr) -> Result<Self, ParseError> {
+        let is_valid_part = |part: &AsciiStr| {
+            !part.is_empty()
+                && part.chars().all(|chr| {
+                    chr.is_alphanumeric()
+                        || Self::ALLOWED_SPECIAL_CHARS.contains(&chr)
+                })
+        };
+        if !name.split(AsciiChar::Dot).all(is_valid_part) {
+            return Err(ParseErrorKind::InvalidComponentName.into());
+        }
+        Ok(Self(name))
+    }
+
+    /// Get the full name.
+    #[must_use]
+    pub fn as_ascii(&self) -> &'a AsciiStr {
+        self.0
+    }
+
+    /// Get the product: the part of the name before the first `.`, or
+    /// the whole name if there is no vendor.
+    #[must_use]
+    pub fn product(&self) -> &'a AsciiStr {
+        self.split().0
+    }
+
+    /// Get the vendor: the part of the name after the first `.`, if
+    /// any.
+    #[must_use]
+    pub fn vendor(&self) -> Option<&'a AsciiStr> {
+        self.split().1
+    }
+
+    /// Check if this is `product` or a vendor's build of it. For
+    /// example, both `grub` and `grub.fedora` are derived from `grub`,
+    /// but `grub2` is not.
+    #[must_use]
+    pub fn is_derived_from(&self, product: &str) -> bool {
+        self.product() == product
+    }
+
+    fn split(&self) -> (&'a AsciiStr, Option<&'a AsciiStr>) {
+        let name = self.0;
+        match name.chars().position(|chr| chr == AsciiChar::Dot) {
+            Some(dot) => (&name[..dot], Some(&name[dot.saturating_add(1)..])),
+            None => (name, None),
+        }
+    }
+}
+
+impl Display for ComponentName<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 /// SBAT component. This is the machine-readable portion of SBAT that is
 /// actually used for revocation (other fields are human-readable and
@@ -29,15 +113,99 @@ impl<'a> Component<'a> {
         Self { name, generation }
     }
 
+    /// Get the [`name`](Self::name) as a [`ComponentName`]. An error is
+    /// returned if it is not a valid name.
+    pub fn component_name(&self) -> Result<ComponentName<'a>, ParseError> {
+        ComponentName::new(self.name)
+    }
+
+    /// Check if the component is `product` or a vendor's build of it.
+    /// Names that are not a valid [`ComponentName`] are not derived
+    /// from any product.
+    #[must_use]
+    pub fn is_derived_from(&self, product: &str) -> bool {
+        self.component_name()
+            .is_ok_and(|name| name.is_derived_from(product))
+    }
+
     /// Parse a `Component` from a `Record`.
     pub(crate) fn from_record<const N: usize>(
         record: &Record<'a, N>,
     ) -> Result<Self, ParseError> {
+        let too_few_fields = || {
+            ParseError::new(
+                ParseErrorKind::TooFewFields,
+                Some(record.position()),
+            )
+        };
+
         Ok(Self {
-            name: record.get_field(0).ok_or(ParseError::TooFewFields)?,
+            name: record.get_field(0).ok_or_else(too_few_fields)?,
             generation: record
                 .get_field_as_generation(1)?
-                .ok_or(ParseError::TooFewFields)?,
+                .ok_or_else(too_few_fields)?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(s: &str) -> Result<ComponentName<'_>, ParseError> {
+        ComponentName::new(AsciiStr::from_ascii(s).unwrap())
+    }
+
+    #[test]
+    fn test_component_name() {
+        let grub = name("grub").unwrap();
+        assert_eq!(grub.product(), "grub");
+        assert_eq!(grub.vendor(), None);
+        assert_eq!(grub.to_string(), "grub");
+
+        let fedora = name("grub.fedora").unwrap();
+        assert_eq!(fedora.as_ascii(), "grub.fedora");
+        assert_eq!(fedora.product(), "grub");
+        assert_eq!(fedora.vendor().unwrap(), "fedora");
+
+        let nested = name("systemd-boot.vendor.os_1").unwrap();
+        assert_eq!(nested.product(), "systemd-boot");
+        assert_eq!(nested.vendor().unwrap(), "vendor.os_1");
+
+        assert!(grub.is_derived_from("grub"));
+        assert!(fedora.is_derived_from("grub"));
+        assert!(!fedora.is_derived_from("grub.fedora"));
+        assert!(!name("grub2").unwrap().is_derived_from("grub"));
+    }
+
+    #[test]
+    fn test_component_name_invalid() {
+        for s in ["", ".", "grub.", ".fedora", "grub..fedora", "a b", "a/b"] {
+            assert_eq!(
+                name(s),
+                Err(ParseErrorKind::InvalidComponentName.into()),
+                "{s:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_derived_from() {
+        let component = |s| {
+            Component::new(
+                AsciiStr::from_ascii(s).unwrap(),
+                Generation::default(),
+            )
+        };
+        assert!(component("shim.redhat").is_derived_from("shim"));
+        assert!(
+            component("shim.redhat")
+                .component_name()
+                .unwrap()
+                .vendor()
+                .is_some()
+        );
+        assert!(!component("shim redhat").is_derived_from("shim"));
+        assert!(component("shim redhat").component_name().is_err());
+    }
+}
@@ -6,7 +6,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! Simple CSV parser.
+//! Simple CSV parser and writer.
 //!
 //! This parser is restricted in various ways because it is only used
 //! for SBAT data, which allows the implementation to be much
@@ -20,15 +20,22 @@
 //!
 //! * The parser is parameterized over the maximum number of fields in a
 //!   record. If a record has more than that number of fields, a warning
-//!   is logged but the extra data is ignored. SBAT treats all but the
+//!   is reported but the extra data is ignored. SBAT treats all but the
 //!   first two fields in each line as human-readable comments, so
 //!   dropping the data is OK.
+//!
+//! The writer applies the same character restrictions, so anything it
+//! writes can be read back by the parser.
 
+use crate::error::ParseErrorKind;
 use crate::lines::LineIter;
-use crate::{Generation, ParseError};
+use crate::{
+    BuildError, Component, Generation, HeaderProblem, ParseError, ParseWarning,
+    ParseWarningKind, Position,
+};
 use arrayvec::ArrayVec;
 use ascii::{AsciiChar, AsciiStr};
-use log::warn;
+use core::fmt::Write;
 
 /// ASCII characters that this library allows in SBAT fields (in
 /// addition to alphanumeric characters).
@@ -65,18 +72,78 @@ fn is_char_allowed_in_field(chr: AsciiChar) -> bool {
     chr.is_alphanumeric() || ALLOWED_SPECIAL_CHARS.contains(&chr)
 }
 
+/// Check that all characters in `field` are allowed.
+fn check_field(field: &AsciiStr) -> Result<(), BuildError> {
+    if let Some(special_char) =
+        field.chars().find(|chr| !is_char_allowed_in_field(*chr))
+    {
+        Err(BuildError::SpecialChar(special_char))
+    } else {
+        Ok(())
+    }
+}
+
+/// Write a record to `writer`, terminated by a newline.
+///
+/// The first two fields are the `component` name and generation,
+/// followed by `extra_fields`. Trailing `None` fields are omitted,
+/// other `None` fields are written as empty fields.
+///
+/// All fields are checked before anything is written, so nothing is
+/// written if an error is returned for an invalid field. The component
+/// name must not be empty.
+pub(crate) fn write_record<W: Write>(
+    writer: &mut W,
+    component: &Component,
+    extra_fields: &[Option<&AsciiStr>],
+) -> Result<(), BuildError> {
+    let num_extra_fields = extra_fields
+        .iter()
+        .rposition(Option::is_some)
+        .map_or(0, |index| index.checked_add(1).unwrap());
+    let extra_fields = &extra_fields[..num_extra_fields];
+
+    if component.name.is_empty() {
+        return Err(BuildError::EmptyComponentName);
+    }
+    check_field(component.name)?;
+    for field in extra_fields.iter().flatten() {
+        check_field(field)?;
+    }
+
+    write!(writer, "{},{}", component.name, component.generation)?;
+    for field in extra_fields {
+        write!(writer, ",{}", field.map_or("", AsciiStr::as_str))?;
+    }
+    writer.write_char('\n')?;
+
+    Ok(())
+}
+
+/// Truncate the input at the first null byte. If no null byte is
+/// present, the entire input is returned.
+fn trim_at_null(input: &[u8]) -> &[u8] {
+    if let Some(null_index) = input.iter().position(|elem| *elem == 0) {
+        &input[..null_index]
+    } else {
+        input
+    }
+}
+
 /// Take raw bytes and convert to ASCII, stopping at the first null
 /// byte. If no null byte is present, the entire input will be
 /// converted.
 pub(crate) fn trim_ascii_at_null(
-    mut input: &[u8],
+    input: &[u8],
 ) -> Result<&AsciiStr, ParseError> {
-    // Truncate the input at the first null byte.
-    if let Some(null_index) = input.iter().position(|elem| *elem == 0) {
-        input = &input[..null_index];
-    }
-
-    AsciiStr::from_ascii(input).map_err(|_| ParseError::InvalidAscii)
+    let input = trim_at_null(input);
+
+    AsciiStr::from_ascii(input).map_err(|err| {
+        ParseError::new(
+            ParseErrorKind::InvalidAscii,
+            Some(Position::at_end_of(&input[..err.valid_up_to()])),
+        )
+    })
 }
 
 /// CSV iterator.
@@ -85,6 +152,7 @@ pub(crate) fn trim_ascii_at_null(
 /// fixed maximum length of `NUM_FIELDS`.
 pub(crate) struct CsvIter<'a, const NUM_FIELDS: usize> {
     line_iter: Option<LineIter<'a>>,
+    record_index: usize,
 }
 
 impl<'a, const NUM_FIELDS: usize> CsvIter<'a, NUM_FIELDS> {
@@ -92,6 +160,7 @@ impl<'a, const NUM_FIELDS: usize> CsvIter<'a, NUM_FIELDS> {
     pub(crate) fn new(input: &'a AsciiStr) -> Self {
         Self {
             line_iter: Some(LineIter::new(input)),
+            record_index: 0,
         }
     }
 }
@@ -106,37 +175,291 @@ impl<'a, const NUM_FIELDS: usize> Iterator for CsvIter<'a, NUM_FIELDS> {
         // Skip empty lines.
         loop {
             line = line_iter.next()?;
-            if !line.is_empty() {
+            if !line.text.is_empty() {
                 break;
             }
         }
 
-        let mut record = Record::default();
-        for field in line.split(AsciiChar::Comma) {
-            // Reject all special characters.
-            if let Some(special_char) =
-                field.chars().find(|chr| !is_char_allowed_in_field(*chr))
-            {
-                self.line_iter = None;
-                return Some(Err(ParseError::SpecialChar(special_char)));
-            }
+        let position = Position {
+            record: self.record_index,
+            line: line.number,
+            column: 1,
+            offset: line.offset,
+        };
+        self.record_index = self.record_index.saturating_add(1);
+
+        let record = Record::parse(line.text, position);
+        if record.is_err() {
+            self.line_iter = None;
+        }
+        Some(record)
+    }
+}
+
+/// Name of the header record that starts both image and revocation
+/// data.
+const HEADER_NAME: &str = "sbat";
 
-            record.add_field(field);
+/// Tracks the `sbat` header record while iterating over records.
+#[derive(Default)]
+struct HeaderCheck {
+    seen_record: bool,
+    seen_header: bool,
+}
+
+impl HeaderCheck {
+    /// Check the next record. A problem is returned if the record is a
+    /// header that is not the first record, or is not the first header.
+    fn next<const NUM_FIELDS: usize>(
+        &mut self,
+        record: &Record<'_, NUM_FIELDS>,
+    ) -> Option<HeaderProblem> {
+        let is_header = record.get_field(0).is_some_and(|f| f == HEADER_NAME);
+        let problem = if !is_header {
+            None
+        } else if self.seen_header {
+            Some(HeaderProblem::Duplicate)
+        } else if self.seen_record {
+            Some(HeaderProblem::Misplaced)
+        } else {
+            None
+        };
+        self.seen_record = true;
+        self.seen_header |= is_header;
+        problem
+    }
+
+    /// Check for a missing header after all records have been checked.
+    fn finish(&self) -> Option<HeaderProblem> {
+        (!self.seen_header).then_some(HeaderProblem::Missing)
+    }
+}
+
+/// Check that the `sbat` header of CSV `input`, which must already have
+/// been validated, is present, first, and not repeated.
+#[allow(clippy::missing_panics_doc)]
+pub(crate) fn check_header<const NUM_FIELDS: usize>(
+    input: &AsciiStr,
+) -> Result<(), ParseError> {
+    let mut header = HeaderCheck::default();
+    for record in CsvIter::<NUM_FIELDS>::new(input) {
+        // OK to unwrap: the input has already been validated.
+        let record = record.unwrap();
+        if let Some(problem) = header.next(&record) {
+            return Err(ParseError::new(
+                ParseErrorKind::Header(problem),
+                Some(record.position()),
+            ));
+        }
+    }
+    // A missing header is reported at the end of the input, since no
+    // record is at fault.
+    match header.finish() {
+        Some(problem) => Err(ParseError::new(
+            ParseErrorKind::Header(problem),
+            Some(Position::at_end_of(input.as_bytes())),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Validate CSV `input`, returning it as ASCII.
+///
+/// Data past the first null byte is ignored. Each record is parsed and
+/// passed to `check`; the first error from parsing or `check` is
+/// returned. Warnings about data that is accepted but questionable,
+/// including problems with the `sbat` header, are passed to
+/// `on_warning`.
+pub(crate) fn validate<'a, const NUM_FIELDS: usize>(
+    input: &'a [u8],
+    mut check: impl FnMut(&Record<'a, NUM_FIELDS>) -> Result<(), ParseError>,
+    mut on_warning: impl FnMut(ParseWarning),
+) -> Result<&'a AsciiStr, ParseError> {
+    let ascii = trim_ascii_at_null(input)?;
+
+    // Zero padding after the null is common (e.g. in a PE section), so
+    // only warn about non-zero data.
+    if input[ascii.len()..].iter().any(|b| *b != 0) {
+        on_warning(ParseWarning::new(
+            ParseWarningKind::DataAfterNull,
+            Position::at_end_of(ascii.as_bytes()),
+        ));
+    }
+
+    let mut header = HeaderCheck::default();
+    let mut record_index: usize = 0;
+    for line in LineIter::new(ascii) {
+        let position = Position {
+            record: record_index,
+            line: line.number,
+            column: 1,
+            offset: line.offset,
+        };
+
+        if line.crlf {
+            on_warning(ParseWarning::new(
+                ParseWarningKind::CrLf,
+                position.advance(line.text.len()),
+            ));
+        }
+        if line.text.is_empty() {
+            on_warning(ParseWarning::new(
+                ParseWarningKind::EmptyLine,
+                position,
+            ));
+            continue;
+        }
+        record_index = record_index.saturating_add(1);
+
+        let record = Record::parse(line.text, position)?;
+        if let Some(extra_field_position) = record.extra_field_position {
+            on_warning(ParseWarning::new(
+                ParseWarningKind::TooManyFields,
+                extra_field_position,
+            ));
+        }
+        check(&record)?;
+        if let Some(problem) = header.next(&record) {
+            on_warning(ParseWarning::new(
+                ParseWarningKind::Header(problem),
+                record.position(),
+            ));
+        }
+    }
+
+    if let Some(problem) = header.finish() {
+        on_warning(ParseWarning::new(
+            ParseWarningKind::Header(problem),
+            Position::at_end_of(ascii.as_bytes()),
+        ));
+    }
+
+    Ok(ascii)
+}
+
+/// Parse `input` as CSV records, skipping invalid records instead of
+/// stopping at the first error.
+///
+/// In addition to the checks done by `CsvIter`, each record is passed
+/// to `check`. The lines of all valid records are concatenated (with
+/// `\n` line endings) into the returned string, and an error is
+/// returned for each invalid record. Unlike `CsvIter`, non-ASCII data
+/// only invalidates the record it is in rather than the whole input.
+#[cfg(feature = "alloc")]
+pub(crate) fn parse_lenient<const NUM_FIELDS: usize>(
+    input: &[u8],
+    check: impl Fn(&Record<'_, NUM_FIELDS>) -> Result<(), ParseError>,
+) -> (ascii::AsciiString, rust_alloc::vec::Vec<ParseError>) {
+    let mut valid = ascii::AsciiString::new();
+    let mut errors = rust_alloc::vec::Vec::new();
+
+    let mut position = Position {
+        record: 0,
+        line: 1,
+        column: 1,
+        offset: 0,
+    };
+    let mut lines = trim_at_null(input).split(|b| *b == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        let line_len = line.len();
+
+        // Match `LineIter`: a carriage return is only part of the line
+        // ending if followed by a line feed.
+        let line = if lines.peek().is_some() {
+            line.strip_suffix(b"\r").unwrap_or(line)
+        } else {
+            line
+        };
+
+        if !line.is_empty() {
+            let result = AsciiStr::from_ascii(line)
+                .map_err(|err| {
+                    ParseError::new(
+                        ParseErrorKind::InvalidAscii,
+                        Some(position.advance(err.valid_up_to())),
+                    )
+                })
+                .and_then(|line| {
+                    check(&Record::parse(line, position)?)?;
+                    Ok(line)
+                });
+            match result {
+                Ok(line) => {
+                    valid.push_str(line);
+                    valid.push(AsciiChar::LineFeed);
+                }
+                Err(err) => errors.push(err),
+            }
+            position.record = position.record.saturating_add(1);
         }
 
-        Some(Ok(record))
+        position.line = position.line.saturating_add(1);
+        position.offset =
+            position.offset.saturating_add(line_len.saturating_add(1));
     }
+
+    (valid, errors)
 }
 
 /// CSV record. This represents a line of comma-separated fields.
-#[derive(Clone, Default)]
-pub(crate) struct Record<'a, const NUM_FIELDS: usize>(
-    ArrayVec<&'a AsciiStr, NUM_FIELDS>,
-);
+#[derive(Clone)]
+pub(crate) struct Record<'a, const NUM_FIELDS: usize> {
+    fields: ArrayVec<&'a AsciiStr, NUM_FIELDS>,
+    position: Position,
+
+    /// Position of the first field that didn't fit in `fields`, if any.
+    extra_field_position: Option<Position>,
+}
 
 impl<'a, const NUM_FIELDS: usize> Record<'a, NUM_FIELDS> {
+    /// Parse a non-empty line into a record. The `position` is the
+    /// position of the start of the line.
+    fn parse(
+        line: &'a AsciiStr,
+        position: Position,
+    ) -> Result<Self, ParseError> {
+        let mut record = Record {
+            fields: ArrayVec::new(),
+            position,
+            extra_field_position: None,
+        };
+        let mut field_position = position;
+        for field in line.split(AsciiChar::Comma) {
+            // Reject all special characters.
+            if let Some(index) =
+                field.chars().position(|chr| !is_char_allowed_in_field(chr))
+            {
+                return Err(ParseError::new(
+                    ParseErrorKind::SpecialChar(field[index]),
+                    Some(field_position.advance(index)),
+                ));
+            }
+
+            record.add_field(field, field_position);
+            field_position =
+                field_position.advance(field.len().saturating_add(1));
+        }
+
+        Ok(record)
+    }
+
     pub(crate) fn get_field(&self, index: usize) -> Option<&'a AsciiStr> {
-        self.0.get(index).copied()
+        self.fields.get(index).copied()
+    }
+
+    /// Position of the start of the record.
+    pub(crate) fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Position of the start of the field at `index`.
+    fn field_position(&self, index: usize) -> Position {
+        self.fields
+            .iter()
+            .take(index)
+            .fold(self.position, |pos, field| {
+                pos.advance(field.len().saturating_add(1))
+            })
     }
 
     /// Get the contents of the record's field at `index` as a
@@ -146,19 +469,22 @@ impl<'a, const NUM_FIELDS: usize> Record<'a, NUM_FIELDS> {
         index: usize,
     ) -> Result<Option<Generation>, ParseError> {
         if let Some(ascii) = self.get_field(index) {
-            Ok(Some(Generation::from_ascii(ascii)?))
+            Ok(Some(Generation::from_ascii(ascii).map_err(|err| {
+                err.or_position(self.field_position(index))
+            })?))
         } else {
             Ok(None)
         }
     }
 
     /// Add a field to the record if possible. If there is no more room,
-    /// the error is logged but otherwise ignored. This behavior is used
-    /// because SBAT only really cares about the first two fields per
-    /// record, the other fields act as human-readable comments.
-    fn add_field(&mut self, field: &'a AsciiStr) {
-        if self.0.try_push(field).is_err() {
-            warn!("maximum fields per record exceeded");
+    /// the field's position is recorded but the field is otherwise
+    /// ignored. This behavior is used because SBAT only really cares
+    /// about the first two fields per record, the other fields act as
+    /// human-readable comments.
+    fn add_field(&mut self, field: &'a AsciiStr, position: Position) {
+        if self.fields.try_push(field).is_err() {
+            self.extra_field_position.get_or_insert(position);
         }
     }
 }
@@ -167,6 +493,51 @@ impl<'a, const NUM_FIELDS: usize> Record<'a, NUM_FIELDS> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_trim_ascii_at_null_invalid_ascii() {
+        assert_eq!(
+            trim_ascii_at_null(b"a,b\nc,\xff"),
+            Err(ParseError::new(
+                ParseErrorKind::InvalidAscii,
+                Some(Position {
+                    record: 1,
+                    line: 2,
+                    column: 3,
+                    offset: 6,
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn test_record_positions() {
+        let s = AsciiStr::from_ascii("\na,b,c\nd,x").unwrap();
+        let records: Vec<_> =
+            CsvIter::<3>::new(s).map(Result::unwrap).collect();
+
+        assert_eq!(
+            records[0].field_position(2),
+            Position {
+                record: 0,
+                line: 2,
+                column: 5,
+                offset: 5,
+            }
+        );
+        assert_eq!(
+            records[1].get_field_as_generation(1),
+            Err(ParseError::new(
+                ParseErrorKind::invalid_generation("x"),
+                Some(Position {
+                    record: 1,
+                    line: 3,
+                    column: 3,
+                    offset: 9,
+                })
+            ))
+        );
+    }
+
     #[test]
     fn test_trim_ascii_at_null() {
         // Everything after null byte is removed.
@@ -179,12 +550,30 @@ mod tests {
         assert_eq!(trim_ascii_at_null(b"a,b,c").unwrap().as_bytes(), b"a,b,c");
     }
 
+    fn special_char(
+        chr: AsciiChar,
+        record: usize,
+        line: usize,
+        column: usize,
+        offset: usize,
+    ) -> ParseError {
+        ParseError::new(
+            ParseErrorKind::SpecialChar(chr),
+            Some(Position {
+                record,
+                line,
+                column,
+                offset,
+            }),
+        )
+    }
+
     fn parse_simple(s: &str) -> Vec<Result<Vec<&str>, ParseError>> {
         let s = AsciiStr::from_ascii(s).unwrap();
         CsvIter::<3>::new(s)
             .map(|record| -> Result<Vec<&str>, ParseError> {
                 let record = record?;
-                Ok(record.0.iter().map(|field| field.as_str()).collect())
+                Ok(record.fields.iter().map(|field| field.as_str()).collect())
             })
             .collect()
     }
@@ -236,11 +625,226 @@ mod tests {
     fn test_special_char() {
         assert_eq!(
             parse_simple("\\"),
-            [Err(ParseError::SpecialChar(AsciiChar::BackSlash))]
+            [Err(special_char(AsciiChar::BackSlash, 0, 1, 1, 0))]
+        );
+        assert_eq!(
+            parse_simple("ab,c\""),
+            [Err(special_char(AsciiChar::Quotation, 0, 1, 5, 4))]
+        );
+    }
+
+    fn write_simple(
+        name: &str,
+        extra_fields: &[Option<&str>],
+    ) -> Result<String, BuildError> {
+        let ascii = |s| AsciiStr::from_ascii(s).unwrap();
+        let extra_fields: Vec<_> =
+            extra_fields.iter().map(|field| field.map(ascii)).collect();
+        let mut output = String::new();
+        write_record(
+            &mut output,
+            &Component::new(ascii(name), Generation::new(2).unwrap()),
+            &extra_fields,
+        )?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_write_record() {
+        assert_eq!(write_simple("a", &[]).unwrap(), "a,2\n");
+        assert_eq!(write_simple("a", &[Some("b")]).unwrap(), "a,2,b\n");
+        assert_eq!(
+            write_simple("a", &[None, Some("c"), None]).unwrap(),
+            "a,2,,c\n"
+        );
+        assert_eq!(write_simple("a", &[None, None]).unwrap(), "a,2\n");
+        assert_eq!(
+            write_simple("", &[Some("b")]),
+            Err(BuildError::EmptyComponentName)
+        );
+    }
+
+    #[test]
+    fn test_write_record_special_char() {
+        assert_eq!(
+            write_simple("a,b", &[]),
+            Err(BuildError::SpecialChar(AsciiChar::Comma))
+        );
+        assert_eq!(
+            write_simple("a", &[None, Some("b\nc")]),
+            Err(BuildError::SpecialChar(AsciiChar::LineFeed))
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_parse_lenient() {
+        let pos = |record, line, column, offset| {
+            Some(Position {
+                record,
+                line,
+                column,
+                offset,
+            })
+        };
+
+        let (valid, errors) =
+            parse_lenient::<3>(b"a,1\r\nb\xff\n\nc,\"\nd,2\r\ne\r\0f", |_| {
+                Ok(())
+            });
+        assert_eq!(valid, "a,1\nd,2\n");
+        assert_eq!(
+            errors,
+            [
+                ParseError::new(ParseErrorKind::InvalidAscii, pos(1, 2, 2, 6)),
+                ParseError::new(
+                    ParseErrorKind::SpecialChar(AsciiChar::Quotation),
+                    pos(2, 4, 3, 11)
+                ),
+                ParseError::new(
+                    ParseErrorKind::SpecialChar(AsciiChar::CarriageReturn),
+                    pos(4, 6, 2, 19)
+                ),
+            ]
+        );
+
+        // Errors from the check function are also collected.
+        let (valid, errors) = parse_lenient::<3>(b"a\nb,1", |record| {
+            Component::from_record(record).map(|_| ())
+        });
+        assert_eq!(valid, "b,1\n");
+        assert_eq!(
+            errors,
+            [ParseError::new(
+                ParseErrorKind::TooFewFields,
+                pos(0, 1, 1, 0)
+            )]
+        );
+    }
+
+    #[test]
+    fn test_header() {
+        let check = |input: &str| {
+            let mut warnings = Vec::new();
+            let ascii = validate::<3>(
+                input.as_bytes(),
+                |_| Ok(()),
+                |w| {
+                    warnings.push((w.kind(), w.position().record));
+                },
+            )
+            .unwrap();
+            let result = check_header::<3>(ascii)
+                .map_err(|err| (err.kind().clone(), err.position()));
+            (warnings, result)
+        };
+        let header =
+            |problem, record| (ParseWarningKind::Header(problem), record);
+        let error = |problem, record, line, column, offset| {
+            let position = Position {
+                record,
+                line,
+                column,
+                offset,
+            };
+            Err((ParseErrorKind::Header(problem), Some(position)))
+        };
+
+        assert_eq!(check("sbat,1\na,1\n"), (vec![], Ok(())));
+        assert_eq!(check("\nsbat,1\na,1\n").1, Ok(()));
+
+        assert_eq!(
+            check(""),
+            (
+                vec![header(HeaderProblem::Missing, 0)],
+                error(HeaderProblem::Missing, 0, 1, 1, 0)
+            )
+        );
+        assert_eq!(
+            check("a,1\nb,2\n"),
+            (
+                vec![header(HeaderProblem::Missing, 2)],
+                error(HeaderProblem::Missing, 2, 3, 1, 8)
+            )
+        );
+        assert_eq!(
+            check("a,1\nsbat,1\n"),
+            (
+                vec![header(HeaderProblem::Misplaced, 1)],
+                error(HeaderProblem::Misplaced, 1, 2, 1, 4)
+            )
+        );
+        assert_eq!(
+            check("sbat,1\na,1\nsbat,2\n"),
+            (
+                vec![header(HeaderProblem::Duplicate, 2)],
+                error(HeaderProblem::Duplicate, 2, 3, 1, 11)
+            )
+        );
+        assert_eq!(
+            check("a,1\nsbat,1\nsbat,1\n").0,
+            [
+                header(HeaderProblem::Misplaced, 1),
+                header(HeaderProblem::Duplicate, 2)
+            ]
         );
+    }
+
+    #[test]
+    fn test_validate_warnings() {
+        let warning = |kind, record, line, column, offset| {
+            ParseWarning::new(
+                kind,
+                Position {
+                    record,
+                    line,
+                    column,
+                    offset,
+                },
+            )
+        };
+
+        let mut warnings = Vec::new();
+        let ascii = validate::<3>(
+            b"a,b\r\n\nc,d,e,f,g\n\0\0x",
+            |_| Ok(()),
+            |w| warnings.push(w),
+        )
+        .unwrap();
+        assert_eq!(ascii, "a,b\r\n\nc,d,e,f,g\n");
+        assert_eq!(
+            warnings,
+            [
+                warning(ParseWarningKind::DataAfterNull, 2, 4, 1, 16),
+                warning(ParseWarningKind::CrLf, 0, 1, 4, 3),
+                warning(ParseWarningKind::EmptyLine, 1, 2, 1, 5),
+                warning(ParseWarningKind::TooManyFields, 1, 3, 7, 12),
+                warning(
+                    ParseWarningKind::Header(HeaderProblem::Missing),
+                    2,
+                    4,
+                    1,
+                    16
+                ),
+            ]
+        );
+
+        // Zero padding is not a warning.
+        let mut warnings = Vec::new();
+        validate::<3>(b"sbat,1\0\0\0", |_| Ok(()), |w| warnings.push(w))
+            .unwrap();
+        assert!(warnings.is_empty());
+
+        // Errors from `check` are returned.
         assert_eq!(
-            parse_simple("\""),
-            [Err(ParseError::SpecialChar(AsciiChar::Quotation))]
+            validate::<3>(
+                b"a",
+                |record| Component::from_record(record).map(|_| ()),
+                |_| {}
+            )
+            .unwrap_err()
+            .kind(),
+            &ParseErrorKind::TooFewFields
         );
     }
 
@@ -256,7 +860,7 @@ ij
             ),
             [
                 Ok(vec!["ab", "cd"]),
-                Err(ParseError::SpecialChar(AsciiChar::Quotation))
+                Err(special_char(AsciiChar::Quotation, 1, 3, 4, 10))
             ]
         );
     }
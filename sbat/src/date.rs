@@ -0,0 +1,184 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::ParseError;
+use crate::error::ParseErrorKind;
+use ascii::AsciiStr;
+use core::fmt::{self, Display, Formatter};
+
+/// Date of revocation data.
+///
+/// Shim stores this in the `sbat` header of revocation data, in the
+/// form `YYYYMMDD` or `YYYYMMDDNN`. The optional `NN` revision
+/// distinguishes multiple revocations made on the same day.
+///
+/// Dates are ordered by year, month, day, and then revision. A date
+/// without a revision is older than the same date with any revision,
+/// which matches the string comparison shim uses.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct RevocationDate {
+    year: u16,
+    month: u8,
+    day: u8,
+    revision: Option<u8>,
+}
+
+impl RevocationDate {
+    /// Create a `RevocationDate`.
+    ///
+    /// An error is returned if the year is greater than 9999, the
+    /// month or day is not valid for the calendar, or the revision is
+    /// greater than 99.
+    pub fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        revision: Option<u8>,
+    ) -> Result<Self, ParseError> {
+        let is_leap_year =
+            year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days_in_month = match month {
+            2 if is_leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+        if year > 9999
+            || !(1..=12).contains(&month)
+            || !(1..=days_in_month).contains(&day)
+            || revision.is_some_and(|revision| revision > 99)
+        {
+            return Err(ParseErrorKind::InvalidDate.into());
+        }
+        Ok(Self {
+            year,
+            month,
+            day,
+            revision,
+        })
+    }
+
+    /// Parse an ASCII string in the `YYYYMMDD` or `YYYYMMDDNN` form.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn from_ascii(s: &AsciiStr) -> Result<Self, ParseError> {
+        let s = s.as_str();
+        if !matches!(s.len(), 8 | 10) || !s.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(ParseErrorKind::InvalidDate.into());
+        }
+
+        // OK to unwrap: all characters are digits, and there are too
+        // few of them to overflow.
+        let year = s[..4].parse().unwrap();
+        let month = s[4..6].parse().unwrap();
+        let day = s[6..8].parse().unwrap();
+        let revision = s.get(8..).filter(|r| !r.is_empty());
+        let revision = revision.map(|r| r.parse().unwrap());
+        Self::new(year, month, day, revision)
+    }
+
+    /// Year, from 0 to 9999.
+    #[must_use]
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    /// Month, from 1 to 12.
+    #[must_use]
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    /// Day of the month, starting at 1.
+    #[must_use]
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    /// Revision within the day, from 0 to 99, if present.
+    #[must_use]
+    pub fn revision(&self) -> Option<u8> {
+        self.revision
+    }
+}
+
+impl Display for RevocationDate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}{:02}{:02}", self.year, self.month, self.day)?;
+        if let Some(revision) = self.revision {
+            write!(f, "{revision:02}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<RevocationDate, ParseError> {
+        RevocationDate::from_ascii(AsciiStr::from_ascii(s).unwrap())
+    }
+
+    #[test]
+    fn test_parse() {
+        let date = parse("2021030218").unwrap();
+        assert_eq!(
+            (date.year(), date.month(), date.day(), date.revision()),
+            (2021, 3, 2, Some(18))
+        );
+        assert_eq!(date.to_string(), "2021030218");
+
+        let date = parse("20240229").unwrap();
+        assert_eq!(date, RevocationDate::new(2024, 2, 29, None).unwrap());
+        assert_eq!(date.to_string(), "20240229");
+
+        assert!(parse("2023123100").is_ok());
+        assert!(parse("2000022900").is_ok());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let err = Err(ParseErrorKind::InvalidDate.into());
+        for s in [
+            "",
+            "2021030",
+            "202103021",
+            "20210302180",
+            "2021-03-02",
+            "+021030218",
+            "20211302",
+            "20210001",
+            "20210132",
+            "20210100",
+            "20210431",
+            "20230229",
+            "19000229",
+        ] {
+            assert_eq!(parse(s), err, "{s}");
+        }
+
+        assert_eq!(RevocationDate::new(10000, 1, 1, None), err);
+        assert_eq!(RevocationDate::new(2021, 1, 1, Some(100)), err);
+    }
+
+    #[test]
+    fn test_order() {
+        let dates = [
+            "20201231",
+            "2020123100",
+            "2020123101",
+            "2021010100",
+            "2021010200",
+            "2021020100",
+            "2022010100",
+        ]
+        .map(|s| parse(s).unwrap());
+        assert!(dates.windows(2).all(|w| w[0] < w[1]));
+    }
+}
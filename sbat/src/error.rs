@@ -6,12 +6,109 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use arrayvec::ArrayString;
 use ascii::AsciiChar;
 use core::fmt::{self, Display, Formatter};
 
-/// SBAT parse error.
+/// Maximum number of bytes of invalid generation text stored in
+/// [`ParseErrorKind::InvalidGeneration`]. Longer text is truncated.
+pub const MAX_INVALID_GENERATION_LEN: usize = 32;
+
+/// Location in the input data where a [`ParseError`] occurred.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Position {
+    /// Zero-based index of the CSV record. Empty lines are not counted
+    /// as records.
+    pub record: usize,
+
+    /// One-based line number.
+    pub line: usize,
+
+    /// One-based column number within the line.
+    pub column: usize,
+
+    /// Zero-based byte offset from the start of the input.
+    pub offset: usize,
+}
+
+impl Position {
+    /// Get the position of the end of `input`.
+    pub(crate) fn at_end_of(input: &[u8]) -> Self {
+        let mut position = Self {
+            record: 0,
+            line: 1,
+            column: 1,
+            offset: input.len(),
+        };
+        let mut line_len: usize = 0;
+        let mut last_was_cr = false;
+        for byte in input {
+            if *byte == b'\n' {
+                // Matches the line handling in `LineIter`: a line
+                // containing only a carriage return before the line
+                // feed is empty.
+                if line_len > 1 || (line_len == 1 && !last_was_cr) {
+                    position.record = position.record.saturating_add(1);
+                }
+                position.line = position.line.saturating_add(1);
+                position.column = 1;
+                line_len = 0;
+            } else {
+                position.column = position.column.saturating_add(1);
+                line_len = line_len.saturating_add(1);
+            }
+            last_was_cr = *byte == b'\r';
+        }
+        position
+    }
+
+    /// Get the position `len` bytes further along the same line.
+    pub(crate) fn advance(mut self, len: usize) -> Self {
+        self.column = self.column.saturating_add(len);
+        self.offset = self.offset.saturating_add(len);
+        self
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Problem with the `sbat` header record.
+///
+/// Both image and revocation data should start with a single `sbat`
+/// record, whose generation is the SBAT format version.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HeaderProblem {
+    /// There is no `sbat` record. This is reported at the end of the
+    /// input.
+    Missing,
+
+    /// The `sbat` record is not the first record.
+    Misplaced,
+
+    /// There is more than one `sbat` record.
+    Duplicate,
+}
+
+impl Display for HeaderProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "missing sbat header"),
+            Self::Misplaced => {
+                write!(f, "sbat header is not the first record")
+            }
+            Self::Duplicate => write!(f, "duplicate sbat header"),
+        }
+    }
+}
+
+/// Kind of [`ParseError`].
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub enum ParseError {
+#[non_exhaustive]
+pub enum ParseErrorKind {
     /// CSV field is not ASCII. According to the SBAT spec, all fields
     /// must be ASCII.
     InvalidAscii,
@@ -24,16 +121,53 @@ pub enum ParseError {
     /// [`ALLOWED_SPECIAL_CHARS`]: crate::ALLOWED_SPECIAL_CHARS
     SpecialChar(AsciiChar),
 
-    /// CSV field is not a valid [`Generation`] number.
+    /// CSV field is not a valid [`Generation`] number. The invalid text
+    /// is included, truncated to [`MAX_INVALID_GENERATION_LEN`] bytes.
     ///
     /// [`Generation`]: crate::Generation
-    InvalidGeneration,
+    InvalidGeneration(ArrayString<MAX_INVALID_GENERATION_LEN>),
+
+    /// Component name is not a valid [`ComponentName`].
+    ///
+    /// [`ComponentName`]: crate::ComponentName
+    InvalidComponentName,
+
+    /// Revocation date is not in the `YYYYMMDD` or `YYYYMMDDNN` form,
+    /// or is not a valid calendar date. See [`RevocationDate`].
+    ///
+    /// [`RevocationDate`]: crate::RevocationDate
+    InvalidDate,
 
     /// CSV record has too few fields.
     TooFewFields,
+
+    /// The `sbat` header is missing, misplaced, or duplicated. See for
+    /// example [`ImageSbat::check_header`].
+    ///
+    /// [`ImageSbat::check_header`]: crate::ImageSbat::check_header
+    Header(HeaderProblem),
+
+    /// There are more records than fit in a fixed-capacity container.
+    TooManyRecords,
 }
 
-impl Display for ParseError {
+impl ParseErrorKind {
+    /// Create an [`InvalidGeneration`] error, truncating `text` if
+    /// needed.
+    ///
+    /// [`InvalidGeneration`]: Self::InvalidGeneration
+    pub(crate) fn invalid_generation(text: &str) -> Self {
+        let mut truncated = ArrayString::new();
+        for chr in text.chars() {
+            if truncated.try_push(chr).is_err() {
+                break;
+            }
+        }
+        Self::InvalidGeneration(truncated)
+    }
+}
+
+impl Display for ParseErrorKind {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Self::InvalidAscii => write!(f, "CSV field is not ASCII"),
@@ -44,18 +178,224 @@ impl Display for ParseError {
                     c.as_byte()
                 )
             }
-            Self::InvalidGeneration => {
-                write!(f, "invalid generation, must be a positive integer")
+            Self::InvalidGeneration(text) => {
+                write!(
+                    f,
+                    "invalid generation {text:?}, must be a positive integer"
+                )
+            }
+            Self::InvalidComponentName => write!(
+                f,
+                "invalid component name, must be product or product.vendor"
+            ),
+            Self::InvalidDate => {
+                write!(f, "invalid date, must be YYYYMMDD or YYYYMMDDNN")
             }
             Self::TooFewFields => {
                 write!(f, "a CSV record does not have enough fields")
             }
+            Self::Header(problem) => write!(f, "{problem}"),
+            Self::TooManyRecords => write!(f, "too many CSV records"),
         }
     }
 }
 
+/// SBAT parse error.
+///
+/// The error has a [`ParseErrorKind`], and if the error occurred while
+/// parsing CSV data, a [`Position`] in the input.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    position: Option<Position>,
+}
+
+impl ParseError {
+    /// Create a `ParseError`.
+    #[must_use]
+    pub fn new(kind: ParseErrorKind, position: Option<Position>) -> Self {
+        Self { kind, position }
+    }
+
+    /// Get the kind of error.
+    #[must_use]
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+
+    /// Get the location in the input data where the error occurred, if
+    /// known.
+    #[must_use]
+    pub fn position(&self) -> Option<Position> {
+        self.position
+    }
+
+    /// Set the position if not already set.
+    pub(crate) fn or_position(mut self, position: Position) -> Self {
+        self.position.get_or_insert(position);
+        self
+    }
+}
+
+impl From<ParseErrorKind> for ParseError {
+    fn from(kind: ParseErrorKind) -> Self {
+        Self::new(kind, None)
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(position) = self.position {
+            write!(f, "{position}: ")?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
 impl core::error::Error for ParseError {}
 
+/// Kind of [`ParseWarning`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ParseWarningKind {
+    /// CSV record has more fields than are used for this type of SBAT
+    /// data. The extra fields are ignored.
+    TooManyFields,
+
+    /// Empty line. Empty lines are skipped.
+    EmptyLine,
+
+    /// Line ends with a carriage return and line feed rather than just
+    /// a line feed. The carriage return is ignored.
+    CrLf,
+
+    /// Input contains non-null data after the first null byte. All data
+    /// after the first null byte is ignored.
+    DataAfterNull,
+
+    /// The `sbat` header is missing, misplaced, or duplicated.
+    Header(HeaderProblem),
+}
+
+impl Display for ParseWarningKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManyFields => {
+                write!(f, "maximum fields per record exceeded")
+            }
+            Self::EmptyLine => write!(f, "empty line"),
+            Self::CrLf => write!(f, "CRLF line ending"),
+            Self::DataAfterNull => write!(f, "data after null byte"),
+            Self::Header(problem) => write!(f, "{problem}"),
+        }
+    }
+}
+
+/// SBAT parse warning.
+///
+/// Warnings describe input that was accepted by the parser, but that
+/// might indicate a problem with the data. See for example
+/// [`ImageSbat::parse_with_warnings`].
+///
+/// [`ImageSbat::parse_with_warnings`]: crate::ImageSbat::parse_with_warnings
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseWarning {
+    kind: ParseWarningKind,
+    position: Position,
+}
+
+impl ParseWarning {
+    /// Create a `ParseWarning`.
+    #[must_use]
+    pub fn new(kind: ParseWarningKind, position: Position) -> Self {
+        Self { kind, position }
+    }
+
+    /// Get the kind of warning.
+    #[must_use]
+    pub fn kind(&self) -> ParseWarningKind {
+        self.kind
+    }
+
+    /// Get the location in the input data that the warning refers to.
+    #[must_use]
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Default warning handler used by the `parse` methods. Only
+    /// [`ParseWarningKind::TooManyFields`] is logged; the other
+    /// warnings are common in real data.
+    pub(crate) fn log(self) {
+        if self.kind == ParseWarningKind::TooManyFields {
+            log::warn!("{self}");
+        }
+    }
+}
+
+impl Display for ParseWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.kind)
+    }
+}
+
+/// SBAT build error.
+///
+/// Returned when writing SBAT data with one of the writer or builder
+/// types, such as [`ImageSbatWriter`].
+///
+/// [`ImageSbatWriter`]: crate::ImageSbatWriter
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// Field contains a special character. The same rules as
+    /// [`ParseErrorKind::SpecialChar`] apply, so in particular a field
+    /// cannot contain a comma or a newline.
+    SpecialChar(AsciiChar),
+
+    /// Component name is empty. A component without a name could never
+    /// be revoked.
+    EmptyComponentName,
+
+    /// The same component name was added more than once.
+    DuplicateComponent,
+
+    /// Revocation date is not in the `YYYYMMDD` or `YYYYMMDDNN` form
+    /// used by shim.
+    InvalidDate,
+
+    /// The underlying [`fmt::Write`] returned an error.
+    Write,
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SpecialChar(c) => {
+                write!(
+                    f,
+                    "field contains special character: {:#04x}",
+                    c.as_byte()
+                )
+            }
+            Self::EmptyComponentName => write!(f, "empty component name"),
+            Self::DuplicateComponent => {
+                write!(f, "duplicate component name")
+            }
+            Self::InvalidDate => write!(f, "invalid revocation date"),
+            Self::Write => write!(f, "failed to write SBAT data"),
+        }
+    }
+}
+
+impl From<fmt::Error> for BuildError {
+    fn from(_: fmt::Error) -> Self {
+        Self::Write
+    }
+}
+
+impl core::error::Error for BuildError {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -63,14 +403,104 @@ mod tests {
     #[test]
     fn test_error_display() {
         assert_eq!(
-            format!("{}", ParseError::SpecialChar(AsciiChar::Null)),
+            format!("{}", ParseErrorKind::SpecialChar(AsciiChar::Null)),
             "CSV field contains special character: 0x00"
         );
+        assert_eq!(
+            format!("{}", ParseErrorKind::invalid_generation("x")),
+            r#"invalid generation "x", must be a positive integer"#
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                ParseError::new(
+                    ParseErrorKind::TooFewFields,
+                    Some(Position {
+                        record: 1,
+                        line: 3,
+                        column: 1,
+                        offset: 20,
+                    })
+                )
+            ),
+            "line 3, column 1: a CSV record does not have enough fields"
+        );
 
         // For the rest, don't bother testing the specific error
         // messages, just ensure nothing panics.
-        let _ = format!("{}", ParseError::InvalidAscii);
-        let _ = format!("{}", ParseError::InvalidGeneration);
-        let _ = format!("{}", ParseError::TooFewFields);
+        let _ = format!("{}", ParseErrorKind::InvalidAscii);
+        let _ = format!("{}", ParseError::from(ParseErrorKind::TooFewFields));
+        let _ = format!("{}", ParseErrorKind::InvalidDate);
+        let _ = format!("{}", ParseErrorKind::InvalidComponentName);
+        let _ = format!("{}", ParseErrorKind::Header(HeaderProblem::Missing));
+    }
+
+    #[test]
+    fn test_warning_display() {
+        assert_eq!(
+            format!(
+                "{}",
+                ParseWarning::new(
+                    ParseWarningKind::EmptyLine,
+                    Position {
+                        record: 1,
+                        line: 2,
+                        column: 1,
+                        offset: 7,
+                    }
+                )
+            ),
+            "line 2, column 1: empty line"
+        );
+
+        let _ = format!("{}", ParseWarningKind::TooManyFields);
+        let _ = format!("{}", ParseWarningKind::CrLf);
+        let _ = format!("{}", ParseWarningKind::DataAfterNull);
+        assert_eq!(
+            format!("{}", ParseWarningKind::Header(HeaderProblem::Misplaced)),
+            "sbat header is not the first record"
+        );
+        let _ = format!("{}", HeaderProblem::Missing);
+        let _ = format!("{}", HeaderProblem::Duplicate);
+    }
+
+    #[test]
+    fn test_invalid_generation_truncated() {
+        let text = "1".repeat(100);
+        let ParseErrorKind::InvalidGeneration(truncated) =
+            ParseErrorKind::invalid_generation(&text)
+        else {
+            panic!("wrong kind");
+        };
+        assert_eq!(truncated.as_str(), &text[..MAX_INVALID_GENERATION_LEN]);
+    }
+
+    #[test]
+    fn test_position_at_end_of() {
+        let pos = |record, line, column, offset| Position {
+            record,
+            line,
+            column,
+            offset,
+        };
+
+        assert_eq!(Position::at_end_of(b""), pos(0, 1, 1, 0));
+        assert_eq!(Position::at_end_of(b"ab"), pos(0, 1, 3, 2));
+        assert_eq!(Position::at_end_of(b"ab\n"), pos(1, 2, 1, 3));
+        assert_eq!(Position::at_end_of(b"ab\n\nc"), pos(1, 3, 2, 5));
+        assert_eq!(Position::at_end_of(b"\r\nab\r\nc"), pos(1, 3, 2, 7));
+        assert_eq!(Position::at_end_of(b"\r\r\nc"), pos(1, 2, 2, 4));
+    }
+
+    #[test]
+    fn test_build_error_display() {
+        assert_eq!(
+            format!("{}", BuildError::SpecialChar(AsciiChar::Comma)),
+            "field contains special character: 0x2c"
+        );
+        let _ = format!("{}", BuildError::DuplicateComponent);
+        let _ = format!("{}", BuildError::InvalidDate);
+        let _ = format!("{}", BuildError::EmptyComponentName);
+        let _ = format!("{}", BuildError::Write);
     }
 }
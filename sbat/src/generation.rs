@@ -7,6 +7,7 @@
 // except according to those terms.
 
 use crate::ParseError;
+use crate::error::ParseErrorKind;
 use ascii::AsciiStr;
 use core::fmt::{self, Display, Formatter};
 use core::str::FromStr;
@@ -29,17 +30,25 @@ impl Generation {
     /// the input is zero.
     pub fn new(val: u32) -> Result<Self, ParseError> {
         if val == 0 {
-            Err(ParseError::InvalidGeneration)
+            Err(ParseErrorKind::invalid_generation("0").into())
         } else {
             Ok(Self(val))
         }
     }
 
     /// Parse an ASCII string as a `Generation`.
+    ///
+    /// Only decimal digits are allowed. In particular a leading `+` is
+    /// rejected, since shim would parse it as zero.
     pub fn from_ascii(s: &AsciiStr) -> Result<Self, ParseError> {
-        let val = u32::from_str(s.as_str())
-            .map_err(|_| ParseError::InvalidGeneration)?;
-        Self::new(val)
+        let invalid_generation =
+            || ParseErrorKind::invalid_generation(s.as_str()).into();
+        if !s.as_bytes().iter().all(u8::is_ascii_digit) {
+            return Err(invalid_generation());
+        }
+        let val =
+            u32::from_str(s.as_str()).map_err(|_| invalid_generation())?;
+        Self::new(val).map_err(|_| invalid_generation())
     }
 
     /// Get the generation value as a [`u32`].
@@ -62,7 +71,10 @@ mod tests {
     #[test]
     fn test_generation() {
         assert_eq!(Generation::default(), Generation::new(1).unwrap());
-        assert_eq!(Generation::new(0), Err(ParseError::InvalidGeneration));
+        assert_eq!(
+            Generation::new(0),
+            Err(ParseErrorKind::invalid_generation("0").into())
+        );
         assert_eq!(
             Generation::from_ascii(AsciiStr::from_ascii("123").unwrap())
                 .unwrap()
@@ -71,7 +83,15 @@ mod tests {
         );
         assert_eq!(
             Generation::from_ascii(AsciiStr::from_ascii("123a").unwrap()),
-            Err(ParseError::InvalidGeneration)
+            Err(ParseErrorKind::invalid_generation("123a").into())
+        );
+        assert_eq!(
+            Generation::from_ascii(AsciiStr::from_ascii("00").unwrap()),
+            Err(ParseErrorKind::invalid_generation("00").into())
+        );
+        assert_eq!(
+            Generation::from_ascii(AsciiStr::from_ascii("+1").unwrap()),
+            Err(ParseErrorKind::invalid_generation("+1").into())
         );
         assert_eq!(Generation::default().to_string(), "1");
     }
@@ -12,9 +12,12 @@
 //! executable. See the crate documentation for details of how it is
 //! used.
 
-use crate::csv::{CsvIter, Record, trim_ascii_at_null};
-use crate::{Component, ParseError};
+use crate::csv::{CsvIter, Record, check_header, validate, write_record};
+use crate::error::ParseErrorKind;
+use crate::{BuildError, Component, Generation, ParseError, ParseWarning};
+use arrayvec::ArrayVec;
 use ascii::AsciiStr;
+use core::fmt::Write;
 use core::ptr;
 
 /// Standard PE section name for SBAT metadata.
@@ -50,7 +53,7 @@ pub struct Entry<'a> {
     pub vendor: Vendor<'a>,
 }
 
-const NUM_ENTRY_FIELDS: usize = 6;
+pub(crate) const NUM_ENTRY_FIELDS: usize = 6;
 
 impl<'a> Entry<'a> {
     /// Make a new `Entry`.
@@ -75,6 +78,54 @@ impl<'a> Entry<'a> {
     }
 }
 
+/// Writer for image SBAT metadata.
+///
+/// This writes [`Entry`] values as CSV to any [`core::fmt::Write`]
+/// implementation, which makes it usable without allocation (for
+/// example with an [`arrayvec::ArrayString`]). Each field is validated
+/// before being written, and the output is guaranteed to be accepted
+/// by [`ImageSbat::parse`].
+///
+/// If the `alloc` feature is enabled, [`ImageSbatBuilder`] can be used
+/// instead to directly create an [`ImageSbatOwned`].
+///
+/// [`arrayvec::ArrayString`]: https://docs.rs/arrayvec/latest/arrayvec/struct.ArrayString.html
+/// [`ImageSbatBuilder`]: crate::ImageSbatBuilder
+/// [`ImageSbatOwned`]: crate::ImageSbatOwned
+#[derive(Debug)]
+pub struct ImageSbatWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ImageSbatWriter<W> {
+    /// Create a new `ImageSbatWriter`.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Write an [`Entry`] as a CSV record.
+    ///
+    /// An error is returned if the component name is empty, or if any
+    /// field contains a character that is not allowed (see
+    /// [`ALLOWED_SPECIAL_CHARS`]). In that case nothing is written for
+    /// the entry.
+    ///
+    /// [`ALLOWED_SPECIAL_CHARS`]: crate::ALLOWED_SPECIAL_CHARS
+    pub fn write_entry(&mut self, entry: &Entry) -> Result<(), BuildError> {
+        let vendor = entry.vendor;
+        write_record(
+            &mut self.writer,
+            &entry.component,
+            &[vendor.name, vendor.package_name, vendor.version, vendor.url],
+        )
+    }
+
+    /// Consume the `ImageSbatWriter`, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
 /// Iterator over entries in [`ImageSbat`].
 ///
 /// See [`ImageSbat::entries`].
@@ -109,16 +160,36 @@ impl ImageSbat {
     /// Any data past the first null in `input` is ignored. A null byte
     /// is not required to be present.
     pub fn parse(input: &[u8]) -> Result<&Self, ParseError> {
-        let input = trim_ascii_at_null(input)?;
-
-        // Ensure that all entries are valid.
-        let iter = CsvIter::<NUM_ENTRY_FIELDS>::new(input);
-        for record in iter {
-            let record = record?;
-            // Check that the first two fields are valid. The other
-            // fields are optional.
-            Component::from_record(&record)?;
-        }
+        Self::parse_with_warnings(input, ParseWarning::log)
+    }
+
+    /// Parse SBAT metadata from raw CSV, passing any warnings to
+    /// `on_warning`.
+    ///
+    /// This is the same as [`parse`], except that instead of logging,
+    /// a [`ParseWarning`] is reported for each questionable but valid
+    /// part of the input: records with too many fields, empty lines,
+    /// CRLF line endings, data after the first null byte, and an `sbat`
+    /// header that is missing, not the first record, or repeated. The
+    /// caller can decide whether to treat these as errors.
+    ///
+    /// To reject only header problems, use [`check_header`] after
+    /// parsing.
+    ///
+    /// [`check_header`]: Self::check_header
+    ///
+    /// [`parse`]: Self::parse
+    pub fn parse_with_warnings(
+        input: &[u8],
+        on_warning: impl FnMut(ParseWarning),
+    ) -> Result<&Self, ParseError> {
+        // Ensure that all entries are valid. Only the first two fields
+        // are checked, the other fields are optional.
+        let input = validate::<NUM_ENTRY_FIELDS>(
+            input,
+            |record| Component::from_record(record).map(|_| ()),
+            on_warning,
+        )?;
 
         Ok(Self::from_ascii_str_unchecked(input))
     }
@@ -147,12 +218,150 @@ impl ImageSbat {
     pub fn entries(&self) -> Entries<'_> {
         Entries(CsvIter::new(&self.0))
     }
+
+    /// Get an iterator over the entries for `product` and vendor builds
+    /// of it. For example, with `grub` this finds `grub`,
+    /// `grub.fedora`, and so on. See [`ComponentName`].
+    ///
+    /// [`ComponentName`]: crate::ComponentName
+    pub fn entries_derived_from<'s>(
+        &'s self,
+        product: &'s str,
+    ) -> impl Iterator<Item = Entry<'s>> {
+        self.entries()
+            .filter(move |entry| entry.component.is_derived_from(product))
+    }
+
+    /// SBAT format version: the generation of the `sbat` header entry.
+    ///
+    /// Returns `None` if the first entry is not the header. Use
+    /// [`check_header`] to also reject a repeated header.
+    ///
+    /// [`check_header`]: Self::check_header
+    #[must_use]
+    pub fn sbat_version(&self) -> Option<Generation> {
+        let entry = self.entries().next()?;
+        (entry.component.name == "sbat").then_some(entry.component.generation)
+    }
+
+    /// Check that the `sbat` header entry is present, is the first
+    /// entry, and is not repeated. If so, the SBAT format version is
+    /// returned.
+    ///
+    /// Parsing accepts data with any of these problems (and reports a
+    /// [`ParseWarningKind::Header`] warning), since shim does not check
+    /// the header of images. Call this after parsing to reject it.
+    ///
+    /// [`ParseWarningKind::Header`]: crate::ParseWarningKind::Header
+    #[allow(clippy::missing_panics_doc)]
+    pub fn check_header(&self) -> Result<Generation, ParseError> {
+        check_header::<NUM_ENTRY_FIELDS>(&self.0)?;
+        // OK to unwrap: the header is the first entry.
+        Ok(self.sbat_version().unwrap())
+    }
+}
+
+/// Parse `input` as image SBAT metadata, passing each [`Entry`] to
+/// `push` as it is parsed. Errors returned by `push` are given the
+/// position of the entry's record.
+pub(crate) fn parse_entries<'a>(
+    input: &'a [u8],
+    on_warning: impl FnMut(ParseWarning),
+    mut push: impl FnMut(Entry<'a>) -> Result<(), ParseErrorKind>,
+) -> Result<&'a ImageSbat, ParseError> {
+    let input = validate::<NUM_ENTRY_FIELDS>(
+        input,
+        |record| {
+            push(Entry::from_record(record)?)
+                .map_err(|kind| ParseError::new(kind, Some(record.position())))
+        },
+        on_warning,
+    )?;
+
+    Ok(ImageSbat::from_ascii_str_unchecked(input))
+}
+
+/// Image SBAT metadata with a fixed-capacity array of parsed entries.
+///
+/// The entries are stored as they are validated, so unlike
+/// [`ImageSbat::entries`], accessing them does not parse the CSV again.
+/// This also allows random access with [`entry`].
+///
+/// See [`ImageSbatIndex`] for a variant without a fixed capacity.
+///
+/// [`ImageSbatIndex`]: crate::ImageSbatIndex
+/// [`entry`]: Self::entry
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ImageSbatIndexArray<'a, const N: usize> {
+    image_sbat: &'a ImageSbat,
+    entries: ArrayVec<Entry<'a>, N>,
+}
+
+impl<'a, const N: usize> ImageSbatIndexArray<'a, N> {
+    /// Parse SBAT metadata from raw CSV, storing up to `N` entries.
+    ///
+    /// The input is validated in the same way as [`ImageSbat::parse`].
+    /// If there are more than `N` entries,
+    /// [`ParseErrorKind::TooManyRecords`] is returned.
+    pub fn parse(input: &'a [u8]) -> Result<Self, ParseError> {
+        Self::parse_with_warnings(input, ParseWarning::log)
+    }
+
+    /// Parse SBAT metadata from raw CSV, passing any warnings to
+    /// `on_warning`.
+    ///
+    /// See [`ImageSbat::parse_with_warnings`] for the list of warnings.
+    pub fn parse_with_warnings(
+        input: &'a [u8],
+        on_warning: impl FnMut(ParseWarning),
+    ) -> Result<Self, ParseError> {
+        let mut entries = ArrayVec::new();
+        let image_sbat = parse_entries(input, on_warning, |entry| {
+            entries
+                .try_push(entry)
+                .map_err(|_| ParseErrorKind::TooManyRecords)
+        })?;
+        Ok(Self {
+            image_sbat,
+            entries,
+        })
+    }
+
+    /// Get the underlying [`ImageSbat`].
+    #[must_use]
+    pub fn image_sbat(&self) -> &'a ImageSbat {
+        self.image_sbat
+    }
+
+    /// Get the entries.
+    #[must_use]
+    pub fn entries(&self) -> &[Entry<'a>] {
+        &self.entries
+    }
+
+    /// Get the entry at `index`, or `None` if out of range.
+    #[must_use]
+    pub fn entry(&self, index: usize) -> Option<Entry<'a>> {
+        self.entries.get(index).copied()
+    }
+
+    /// Number of entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Generation;
+    use crate::{Generation, HeaderProblem, ParseWarningKind, Position};
 
     #[cfg(feature = "alloc")]
     use crate::ImageSbatOwned;
@@ -209,12 +418,163 @@ shim,1,UEFI shim,shim,1,https://github.com/rhboot/shim";
 
     #[test]
     fn invalid_record_array() {
-        assert_eq!(ImageSbat::parse(b"a"), Err(ParseError::TooFewFields));
+        assert_eq!(
+            ImageSbat::parse(b"sbat,1\n\na").unwrap_err(),
+            ParseError::new(
+                ParseErrorKind::TooFewFields,
+                Some(Position {
+                    record: 1,
+                    line: 3,
+                    column: 1,
+                    offset: 8,
+                })
+            )
+        );
+        assert_eq!(
+            ImageSbat::parse(b"a,-1").unwrap_err().kind(),
+            &ParseErrorKind::invalid_generation("-1")
+        );
+    }
+
+    #[test]
+    fn parse_with_warnings() {
+        let mut warnings = Vec::new();
+        let image_sbat = ImageSbat::parse_with_warnings(
+            b"sbat,1\r\n\nshim,1,a,b,c,d,e\0x",
+            |warning| warnings.push(warning.kind()),
+        )
+        .unwrap();
+        assert_eq!(image_sbat.entries().count(), 2);
+        assert_eq!(
+            warnings,
+            [
+                ParseWarningKind::DataAfterNull,
+                ParseWarningKind::CrLf,
+                ParseWarningKind::EmptyLine,
+                ParseWarningKind::TooManyFields,
+            ]
+        );
+    }
+
+    #[test]
+    fn entries_derived_from() {
+        let image_sbat = ImageSbat::parse(
+            b"sbat,1\ngrub,3\ngrub.fedora,2\ngrub2,1\nshim.fedora,1\n",
+        )
+        .unwrap();
+        let names = |product| {
+            image_sbat
+                .entries_derived_from(product)
+                .map(|entry| entry.component.name.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("grub"), ["grub", "grub.fedora"]);
+        assert_eq!(names("shim"), ["shim.fedora"]);
+        assert!(names("fedora").is_empty());
+    }
+
+    #[test]
+    fn sbat_version() {
+        let version = Generation::new(2).unwrap();
+        let image_sbat = ImageSbat::parse(b"sbat,2\nshim,1\n").unwrap();
+        assert_eq!(image_sbat.sbat_version(), Some(version));
+        assert_eq!(image_sbat.check_header(), Ok(version));
+
+        let image_sbat = ImageSbat::parse(b"shim,1\nsbat,2\n").unwrap();
+        assert_eq!(image_sbat.sbat_version(), None);
+        assert_eq!(
+            image_sbat.check_header().unwrap_err().kind(),
+            &ParseErrorKind::Header(HeaderProblem::Misplaced)
+        );
+
+        let image_sbat = ImageSbat::parse(b"sbat,2\nsbat,2\n").unwrap();
+        assert_eq!(image_sbat.sbat_version(), Some(version));
+        assert_eq!(
+            image_sbat.check_header().unwrap_err().kind(),
+            &ParseErrorKind::Header(HeaderProblem::Duplicate)
+        );
+    }
+
+    #[test]
+    fn index_array() {
+        let index = ImageSbatIndexArray::<2>::parse(VALID_SBAT).unwrap();
+        assert_eq!(index.len(), 2);
+        assert!(!index.is_empty());
+        assert_eq!(index.image_sbat(), ImageSbat::parse(VALID_SBAT).unwrap());
+        assert!(
+            index
+                .entries()
+                .iter()
+                .copied()
+                .eq(index.image_sbat().entries())
+        );
+        assert_eq!(index.entry(1).unwrap().component.name, "shim");
+        assert_eq!(index.entry(2), None);
+
+        let empty = ImageSbatIndexArray::<0>::parse(b"").unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn index_array_errors() {
+        assert_eq!(
+            ImageSbatIndexArray::<1>::parse(VALID_SBAT).unwrap_err(),
+            ParseError::new(
+                ParseErrorKind::TooManyRecords,
+                Some(Position {
+                    record: 1,
+                    line: 2,
+                    column: 1,
+                    offset: 76,
+                })
+            )
+        );
+        assert_eq!(
+            ImageSbatIndexArray::<2>::parse(b"a,1\nb,x")
+                .unwrap_err()
+                .kind(),
+            &ParseErrorKind::invalid_generation("x")
+        );
+    }
+
+    #[test]
+    fn write_entries() {
+        let mut writer =
+            ImageSbatWriter::new(arrayvec::ArrayString::<256>::new());
+        for entry in ImageSbat::parse(VALID_SBAT).unwrap().entries() {
+            writer.write_entry(&entry).unwrap();
+        }
+        let output = writer.into_inner();
+        assert_eq!(output.as_bytes(), [VALID_SBAT, b"\n"].concat());
+
+        parse_success_helper(ImageSbat::parse(output.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn write_entry_special_char() {
+        let ascii = |s| AsciiStr::from_ascii(s).unwrap();
+
+        let mut writer = ImageSbatWriter::new(String::new());
+        let entry = Entry::new(
+            Component::new(ascii("shim"), Generation::default()),
+            Vendor {
+                url: Some(ascii("\"example\"")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            writer.write_entry(&entry),
+            Err(BuildError::SpecialChar(ascii::AsciiChar::Quotation))
+        );
+        assert!(writer.into_inner().is_empty());
     }
 
     #[cfg(feature = "alloc")]
     #[test]
     fn invalid_record_vec() {
-        assert_eq!(ImageSbatOwned::parse(b"a"), Err(ParseError::TooFewFields));
+        assert_eq!(
+            ImageSbatOwned::parse(b"a").unwrap_err().kind(),
+            &ParseErrorKind::TooFewFields
+        );
     }
 }
@@ -34,15 +34,42 @@
 //! This `no_std` library handles parsing both sources of SBAT data
 //! ([`ImageSbat`] and [`RevocationSbat`] data), as well as performing
 //! the revocation comparison. The parsing starts with raw bytes
-//! containing the CSV; the library doesn't handle directly reading PE
-//! binaries or UEFI variables. Consider using the [`object`] crate to
-//! extract the `.sbat` section from a PE binary.
+//! containing the CSV. With the `alloc` feature, the `variables`
+//! module can read and write shim's UEFI variables through a
+//! `VariableStore`, such as efivarfs (with the `std` feature), and
+//! predict how shim will update `SbatLevel` at boot. If the `pe`
+//! feature is enabled, the `pe` module can extract the `.sbat` and
+//! `.sbatlevel` sections from a PE binary without allocating.
+//! Alternatively, consider using the [`object`] crate. The `uki`
+//! module, also enabled by the `pe` feature, reads the SBAT data of a
+//! Unified Kernel Image and its embedded kernel.
 //!
 //! If the `alloc` feature is enabled, the [`ImageSbatOwned`] and
 //! [`RevocationSbatOwned`] types can be be used. These types own the
 //! CSV string data rather than taking a reference to it. They deref to
 //! [`ImageSbat`] and [`RevocationSbat`] respectively.
 //!
+//! SBAT data can also be written. [`ImageSbatWriter`] writes image
+//! SBAT CSV to any [`core::fmt::Write`] implementation, and with the
+//! `alloc` feature [`ImageSbatBuilder`] creates an [`ImageSbatOwned`].
+//! Similarly, [`RevocationSbatBuilder`] creates a
+//! [`RevocationSbatOwned`]. [`ImageSbatOwned::merge`] combines the
+//! image SBAT data of several components into one.
+//!
+//! To access the entries of image SBAT data repeatedly or by index,
+//! parse it as an [`ImageSbatIndex`] (or the fixed-capacity
+//! [`ImageSbatIndexArray`]), which stores the entries while validating
+//! rather than parsing the CSV again on each iteration.
+//!
+//! When validating many images against the same revocations, build a
+//! [`RevocationIndex`] (or the fixed-capacity [`RevocationIndexArray`])
+//! once and validate with it, to avoid re-parsing the revocation data
+//! for every lookup.
+//!
+//! The parsers above are stricter than shim. To check exactly what shim
+//! will accept, and how it compares generations, use the [`shim`]
+//! module.
+//!
 //! # Examples
 //!
 //! ```
@@ -70,26 +97,55 @@ extern crate alloc as rust_alloc;
 
 mod component;
 mod csv;
+mod date;
 mod error;
 mod generation;
 mod image;
 mod lines;
+mod revocation_index;
 mod revocation_section;
 mod revocations;
+pub mod shim;
+
+#[cfg(feature = "pe")]
+pub mod pe;
+
+#[cfg(feature = "pe")]
+pub mod uki;
 
 #[cfg(feature = "alloc")]
 mod alloc;
 
+#[cfg(feature = "alloc")]
+pub mod variables;
+
 pub use ValidationResult::{Allowed, Revoked};
-pub use component::Component;
+pub use component::{Component, ComponentName};
 pub use csv::ALLOWED_SPECIAL_CHARS;
-pub use error::ParseError;
+pub use date::RevocationDate;
+pub use error::{
+    BuildError, HeaderProblem, MAX_INVALID_GENERATION_LEN, ParseError,
+    ParseErrorKind, ParseWarning, ParseWarningKind, Position,
+};
 pub use generation::Generation;
-pub use image::{Entries, Entry, ImageSbat, SBAT_SECTION_NAME, Vendor};
+pub use image::{
+    Entries, Entry, ImageSbat, ImageSbatIndexArray, ImageSbatWriter,
+    SBAT_SECTION_NAME, Vendor,
+};
+pub use revocation_index::{RevocationIndexArray, RevocationIndexError};
 pub use revocation_section::{
     REVOCATION_SECTION_NAME, RevocationSection, RevocationSectionError,
 };
-pub use revocations::{RevocationSbat, RevokedComponents, ValidationResult};
+pub use revocations::{
+    RevocationSbat, RevokedComponents, RevokedEntries, RevokedEntry,
+    ValidationReport, ValidationResult,
+};
+
+#[cfg(feature = "alloc")]
+pub use alloc::{
+    GenerationConflict, ImageSbatBuilder, ImageSbatIndex, ImageSbatOwned,
+    MergeError, RevocationSbatBuilder, RevocationSbatOwned,
+};
 
 #[cfg(feature = "alloc")]
-pub use alloc::{ImageSbatOwned, RevocationSbatOwned};
+pub use revocation_index::RevocationIndex;
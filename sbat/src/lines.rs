@@ -5,18 +5,38 @@ use ascii::{AsciiChar, AsciiStr};
 // https://github.com/tomprogrammer/rust-ascii/issues/101
 pub(crate) struct LineIter<'a> {
     string: &'a AsciiStr,
+    number: usize,
+    offset: usize,
+}
+
+/// Line yielded by `LineIter`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Line<'a> {
+    /// Contents of the line, not including the line ending.
+    pub(crate) text: &'a AsciiStr,
+
+    /// One-based line number.
+    pub(crate) number: usize,
+
+    /// Byte offset of the start of the line within the input.
+    pub(crate) offset: usize,
+
+    /// Whether the line ended with `\r\n` rather than just `\n`.
+    pub(crate) crlf: bool,
 }
 
 impl<'a> LineIter<'a> {
     pub(crate) fn new(string: &'a AsciiStr) -> Self {
-        Self { string }
+        Self {
+            string,
+            number: 0,
+            offset: 0,
+        }
     }
-}
-
-impl<'a> Iterator for LineIter<'a> {
-    type Item = &'a AsciiStr;
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Get the text of the next line, without the line ending, and
+    /// whether the line ending was CRLF.
+    fn next_text(&mut self) -> Option<(&'a AsciiStr, bool)> {
         if self.string.is_empty() {
             return None;
         }
@@ -33,24 +53,49 @@ impl<'a> Iterator for LineIter<'a> {
             self.string = &self.string[line_end.checked_add(1).unwrap()..];
             if line.last() == Some(AsciiChar::CarriageReturn) {
                 // OK to unwrap: we know the line has at least one character.
-                Some(&line[..line.len().checked_sub(1).unwrap()])
+                Some((&line[..line.len().checked_sub(1).unwrap()], true))
             } else {
-                Some(line)
+                Some((line, false))
             }
         } else {
             let line = self.string;
             self.string = &self.string[0..0];
-            Some(line)
+            Some((line, false))
         }
     }
 }
 
+impl<'a> Iterator for LineIter<'a> {
+    type Item = Line<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start_len = self.string.len();
+        let (text, crlf) = self.next_text()?;
+
+        let line = Line {
+            text,
+            number: self.number.saturating_add(1),
+            offset: self.offset,
+            crlf,
+        };
+        self.number = line.number;
+        // OK to unwrap: the remaining string can't be longer than it
+        // was before.
+        self.offset = self
+            .offset
+            .saturating_add(start_len.checked_sub(self.string.len()).unwrap());
+        Some(line)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     fn lines(s: &str) -> Vec<&AsciiStr> {
-        LineIter::new(AsciiStr::from_ascii(s).unwrap()).collect::<Vec<_>>()
+        LineIter::new(AsciiStr::from_ascii(s).unwrap())
+            .map(|line| line.text)
+            .collect::<Vec<_>>()
     }
 
     #[test]
@@ -72,4 +117,19 @@ mod tests {
         assert_eq!(lines("ab\ncd\n"), ["ab", "cd"]);
         assert_eq!(lines("ab\ncd\n\n"), ["ab", "cd", ""]);
     }
+
+    #[test]
+    fn test_line_positions() {
+        let positions = |s| {
+            LineIter::new(AsciiStr::from_ascii(s).unwrap())
+                .map(|line| (line.number, line.offset, line.crlf))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(positions("ab\ncd"), [(1, 0, false), (2, 3, false)]);
+        assert_eq!(
+            positions("ab\r\n\ncd\r"),
+            [(1, 0, true), (2, 4, false), (3, 5, false)]
+        );
+    }
 }
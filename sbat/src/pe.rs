@@ -0,0 +1,1037 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Minimal PE/COFF reader.
+//!
+//! This module can find sections in PE32 and PE32+ executables without
+//! allocating, which is enough to read the [`SBAT_SECTION_NAME`] and
+//! [`REVOCATION_SECTION_NAME`] sections. It is not a general purpose
+//! PE parser; consider using the [`object`] crate for that.
+//!
+//! [`object`]: https://crates.io/crates/object
+
+use crate::{
+    ImageSbat, ParseError, REVOCATION_SECTION_NAME, RevocationSection,
+    RevocationSectionError, SBAT_SECTION_NAME,
+};
+use core::ffi::c_void;
+use core::fmt::{self, Display, Formatter};
+
+/// Signature at the start of the DOS header.
+const DOS_SIGNATURE: &[u8; 2] = b"MZ";
+
+/// Offset within the DOS header of the PE header offset.
+const PE_OFFSET_OFFSET: usize = 0x3c;
+
+/// Signature at the start of the PE header.
+const PE_SIGNATURE: &[u8; 4] = b"PE\0\0";
+
+/// Size of the COFF file header that follows the PE signature.
+const COFF_HEADER_SIZE: usize = 20;
+
+/// Size of a section header.
+const SECTION_HEADER_SIZE: usize = 40;
+
+/// Size of a COFF symbol table entry.
+const SYMBOL_SIZE: usize = 18;
+
+/// Optional header magic for PE32.
+const PE32_MAGIC: u16 = 0x10b;
+
+/// Optional header magic for PE32+.
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+
+/// Error returned by [`PeFile`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PeError {
+    /// The loaded image pointer is null, or its size does not fit in a
+    /// [`usize`].
+    InvalidImage,
+
+    /// The data is too short for a DOS header, or the header does not
+    /// start with `MZ`.
+    InvalidDosHeader,
+
+    /// The PE header offset is out of bounds, or the PE header does
+    /// not start with the `PE\0\0` signature.
+    InvalidPeSignature,
+
+    /// The COFF file header is truncated.
+    InvalidCoffHeader,
+
+    /// The optional header is truncated.
+    InvalidOptionalHeader,
+
+    /// The optional header magic is not PE32 or PE32+.
+    InvalidOptionalHeaderMagic(u16),
+
+    /// The section table extends past the end of the data.
+    InvalidSectionTable,
+
+    /// A section name refers to an invalid string table entry.
+    InvalidSectionName,
+
+    /// A section's data extends past the end of the data.
+    InvalidSectionData,
+
+    /// The requested section does not exist.
+    MissingSection,
+
+    /// The [`SBAT_SECTION_NAME`] section is not valid [`ImageSbat`].
+    InvalidImageSbat(ParseError),
+
+    /// The [`REVOCATION_SECTION_NAME`] section is not a valid
+    /// [`RevocationSection`].
+    InvalidRevocationSection(RevocationSectionError),
+}
+
+impl Display for PeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidImage => write!(f, "invalid loaded image"),
+            Self::InvalidDosHeader => write!(f, "invalid DOS header"),
+            Self::InvalidPeSignature => write!(f, "invalid PE signature"),
+            Self::InvalidCoffHeader => write!(f, "invalid COFF header"),
+            Self::InvalidOptionalHeader => {
+                write!(f, "invalid optional header")
+            }
+            Self::InvalidOptionalHeaderMagic(magic) => {
+                write!(f, "invalid optional header magic: {magic:#06x}")
+            }
+            Self::InvalidSectionTable => write!(f, "invalid section table"),
+            Self::InvalidSectionName => write!(f, "invalid section name"),
+            Self::InvalidSectionData => write!(f, "invalid section data"),
+            Self::MissingSection => write!(f, "missing section"),
+            Self::InvalidImageSbat(err) => {
+                write!(f, "invalid {SBAT_SECTION_NAME} section: {err}")
+            }
+            Self::InvalidRevocationSection(err) => {
+                write!(f, "invalid {REVOCATION_SECTION_NAME} section: {err}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for PeError {}
+
+/// Read `N` bytes from `data` at `offset`.
+fn read_array<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+/// Read a little-endian [`u16`] from `data` at `offset`.
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    read_array(data, offset).map(u16::from_le_bytes)
+}
+
+/// Read a little-endian [`u32`] from `data` at `offset`.
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    read_array(data, offset).map(u32::from_le_bytes)
+}
+
+/// Convert a [`u32`] from a header to a [`usize`].
+fn to_usize(val: u32) -> Option<usize> {
+    usize::try_from(val).ok()
+}
+
+/// Get the bytes of a name up to the first null.
+fn trim_name(name: &[u8]) -> &[u8] {
+    name.split(|b| *b == 0).next().unwrap_or_default()
+}
+
+/// Memory layout of a [`PeFile`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PeLayout {
+    /// The executable as stored on disk. Section data is located with
+    /// [`PeSection::pointer_to_raw_data`].
+    File,
+
+    /// The executable as loaded into memory by firmware, for example
+    /// the image described by `EFI_LOADED_IMAGE_PROTOCOL`. Each section
+    /// is located at its [`PeSection::virtual_address`] relative to the
+    /// image base.
+    Loaded,
+}
+
+/// How long section names are resolved.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum LongNames<'a> {
+    /// Look up names in the string table, if there is one. Names that
+    /// can't be resolved are an error.
+    StringTable(Option<&'a [u8]>),
+
+    /// The string table is not available (it is not loaded into
+    /// memory), so names are left unresolved.
+    Unresolved,
+}
+
+/// Section of a [`PeFile`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PeSection<'a> {
+    name: &'a [u8],
+    virtual_size: u32,
+    virtual_address: u32,
+    size_of_raw_data: u32,
+    pointer_to_raw_data: u32,
+    characteristics: u32,
+}
+
+impl<'a> PeSection<'a> {
+    /// Parse a section header. Long names of the form `/<offset>` are
+    /// resolved according to `long_names`.
+    fn parse(
+        header: &'a [u8],
+        long_names: LongNames<'a>,
+    ) -> Result<Self, PeError> {
+        let field = |offset| {
+            read_u32(header, offset).ok_or(PeError::InvalidSectionTable)
+        };
+
+        let raw_name =
+            trim_name(header.get(..8).ok_or(PeError::InvalidSectionTable)?);
+        let name = if let (Some(offset), LongNames::StringTable(string_table)) =
+            (raw_name.strip_prefix(b"/"), long_names)
+        {
+            let offset = core::str::from_utf8(offset)
+                .ok()
+                .and_then(|offset| offset.parse::<usize>().ok())
+                .ok_or(PeError::InvalidSectionName)?;
+            match string_table {
+                Some(strings) => trim_name(
+                    strings.get(offset..).ok_or(PeError::InvalidSectionName)?,
+                ),
+                // Without a string table the name can't be resolved.
+                // Leave it in its raw form, as in the loaded layout, so
+                // that it doesn't match any lookup.
+                None => raw_name,
+            }
+        } else {
+            raw_name
+        };
+
+        Ok(Self {
+            name,
+            virtual_size: field(8)?,
+            virtual_address: field(12)?,
+            size_of_raw_data: field(16)?,
+            pointer_to_raw_data: field(20)?,
+            characteristics: field(36)?,
+        })
+    }
+
+    /// Section name. Long names stored in the string table are
+    /// resolved, except in the [`PeLayout::Loaded`] layout or when the
+    /// file has no string table. In those cases the string table is not
+    /// available and long names are returned in their raw `/<offset>`
+    /// form.
+    #[must_use]
+    pub fn name(&self) -> &'a [u8] {
+        self.name
+    }
+
+    /// Size of the section when loaded into memory.
+    #[must_use]
+    pub fn virtual_size(&self) -> u32 {
+        self.virtual_size
+    }
+
+    /// Address of the section relative to the image base when loaded
+    /// into memory.
+    #[must_use]
+    pub fn virtual_address(&self) -> u32 {
+        self.virtual_address
+    }
+
+    /// Size of the section's data in the file. This is rounded up to
+    /// the file alignment, so it may be larger than
+    /// [`virtual_size`](Self::virtual_size).
+    #[must_use]
+    pub fn size_of_raw_data(&self) -> u32 {
+        self.size_of_raw_data
+    }
+
+    /// File offset of the section's data.
+    #[must_use]
+    pub fn pointer_to_raw_data(&self) -> u32 {
+        self.pointer_to_raw_data
+    }
+
+    /// Section flags.
+    #[must_use]
+    pub fn characteristics(&self) -> u32 {
+        self.characteristics
+    }
+
+    /// Size of the section's data in the file, excluding file
+    /// alignment padding.
+    ///
+    /// This is the smaller of [`virtual_size`] and [`size_of_raw_data`].
+    /// If [`virtual_size`] is larger, the rest of the section is
+    /// zero-filled when loaded. If [`virtual_size`] is zero (as in COFF
+    /// object files), [`size_of_raw_data`] is used.
+    ///
+    /// [`virtual_size`]: Self::virtual_size
+    /// [`size_of_raw_data`]: Self::size_of_raw_data
+    #[must_use]
+    pub fn file_data_size(&self) -> u32 {
+        if self.virtual_size == 0 {
+            self.size_of_raw_data
+        } else {
+            self.virtual_size.min(self.size_of_raw_data)
+        }
+    }
+}
+
+/// Iterator over the sections of a [`PeFile`].
+///
+/// See [`PeFile::sections`].
+pub struct PeSections<'a> {
+    headers: core::slice::ChunksExact<'a, u8>,
+    long_names: LongNames<'a>,
+}
+
+impl<'a> Iterator for PeSections<'a> {
+    type Item = PeSection<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.headers.next()?;
+        // The section headers were all checked in `PeFile::parse`, so
+        // this always succeeds.
+        PeSection::parse(header, self.long_names).ok()
+    }
+}
+
+/// PE32 or PE32+ executable.
+///
+/// The executable can be in either the file layout (see [`parse`]) or
+/// the loaded layout (see [`parse_loaded`]).
+///
+/// [`parse`]: Self::parse
+/// [`parse_loaded`]: Self::parse_loaded
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PeFile<'a> {
+    data: &'a [u8],
+    layout: PeLayout,
+    section_table: &'a [u8],
+    long_names: LongNames<'a>,
+    is_pe32_plus: bool,
+}
+
+impl<'a> PeFile<'a> {
+    /// Parse the headers and section table of a PE executable in the
+    /// [`PeLayout::File`] layout.
+    pub fn parse(data: &'a [u8]) -> Result<Self, PeError> {
+        Self::parse_with_layout(data, PeLayout::File)
+    }
+
+    /// Parse the headers and section table of a PE executable in the
+    /// [`PeLayout::Loaded`] layout. `data` starts at the image base and
+    /// covers the whole image.
+    ///
+    /// Long section names can't be resolved in this layout, since the
+    /// string table is not loaded. Depending on the linker, the
+    /// [`REVOCATION_SECTION_NAME`] section is then named either
+    /// `.sbatlev` (truncated to eight bytes) or `/<offset>`.
+    /// [`revocation_section`] handles both by checking which of the
+    /// sections with such a name contains valid revocation data.
+    ///
+    /// [`revocation_section`]: Self::revocation_section
+    pub fn parse_loaded(data: &'a [u8]) -> Result<Self, PeError> {
+        Self::parse_with_layout(data, PeLayout::Loaded)
+    }
+
+    /// Parse an executable loaded into memory by firmware. `base` and
+    /// `size` are the `ImageBase` and `ImageSize` fields of
+    /// `EFI_LOADED_IMAGE_PROTOCOL`.
+    ///
+    /// See [`parse_loaded`] for details.
+    ///
+    /// # Safety
+    ///
+    /// If `base` is not null, it must point to `size` bytes that are
+    /// readable and not modified for the lifetime `'a`.
+    ///
+    /// [`parse_loaded`]: Self::parse_loaded
+    #[allow(unsafe_code)]
+    pub unsafe fn from_loaded_image(
+        base: *const c_void,
+        size: u64,
+    ) -> Result<Self, PeError> {
+        let size = usize::try_from(size).map_err(|_| PeError::InvalidImage)?;
+        if base.is_null() {
+            return Err(PeError::InvalidImage);
+        }
+        // SAFETY: the pointer is not null, and the caller guarantees
+        // that it is valid for reads of `size` bytes for `'a`. The
+        // pointer is for `u8` data, so there are no alignment
+        // requirements.
+        let data = unsafe { core::slice::from_raw_parts(base.cast(), size) };
+        Self::parse_loaded(data)
+    }
+
+    #[allow(clippy::missing_panics_doc)]
+    fn parse_with_layout(
+        data: &'a [u8],
+        layout: PeLayout,
+    ) -> Result<Self, PeError> {
+        if data.get(..2) != Some(DOS_SIGNATURE) {
+            return Err(PeError::InvalidDosHeader);
+        }
+        let pe_offset = read_u32(data, PE_OFFSET_OFFSET)
+            .and_then(to_usize)
+            .ok_or(PeError::InvalidDosHeader)?;
+        if read_array(data, pe_offset).as_ref() != Some(PE_SIGNATURE) {
+            return Err(PeError::InvalidPeSignature);
+        }
+
+        // OK to unwrap: the signature was read from this offset.
+        let coff_offset = pe_offset.checked_add(PE_SIGNATURE.len()).unwrap();
+        let coff_header = coff_offset
+            .checked_add(COFF_HEADER_SIZE)
+            .and_then(|end| data.get(coff_offset..end))
+            .ok_or(PeError::InvalidCoffHeader)?;
+        // OK to unwrap: the header is long enough.
+        let num_sections = usize::from(read_u16(coff_header, 2).unwrap());
+        let symbol_table_offset = read_u32(coff_header, 8).unwrap();
+        let num_symbols = read_u32(coff_header, 12).unwrap();
+        let optional_header_size =
+            usize::from(read_u16(coff_header, 16).unwrap());
+
+        // OK to unwrap: the COFF header was read from this offset.
+        let optional_header_offset =
+            coff_offset.checked_add(COFF_HEADER_SIZE).unwrap();
+        let magic = read_u16(data, optional_header_offset)
+            .ok_or(PeError::InvalidOptionalHeader)?;
+        let is_pe32_plus = match magic {
+            PE32_MAGIC => false,
+            PE32_PLUS_MAGIC => true,
+            _ => return Err(PeError::InvalidOptionalHeaderMagic(magic)),
+        };
+        let section_table_offset = optional_header_offset
+            .checked_add(optional_header_size)
+            .filter(|offset| *offset <= data.len())
+            .ok_or(PeError::InvalidOptionalHeader)?;
+
+        let section_table = num_sections
+            .checked_mul(SECTION_HEADER_SIZE)
+            .and_then(|size| section_table_offset.checked_add(size))
+            .and_then(|end| data.get(section_table_offset..end))
+            .ok_or(PeError::InvalidSectionTable)?;
+
+        // The string table immediately follows the symbol table. It is
+        // only needed for long section names, so it's not an error if
+        // it is missing. The symbol table is not loaded into memory, so
+        // the string table is only available in the file layout.
+        let long_names = match layout {
+            PeLayout::File if symbol_table_offset != 0 => {
+                LongNames::StringTable(
+                    to_usize(num_symbols)
+                        .and_then(|n| n.checked_mul(SYMBOL_SIZE))
+                        .zip(to_usize(symbol_table_offset))
+                        .and_then(|(size, offset)| offset.checked_add(size))
+                        .and_then(|offset| data.get(offset..)),
+                )
+            }
+            PeLayout::File => LongNames::StringTable(None),
+            PeLayout::Loaded => LongNames::Unresolved,
+        };
+
+        let file = Self {
+            data,
+            layout,
+            section_table,
+            long_names,
+            is_pe32_plus,
+        };
+
+        // Check all section headers up front so that iterating over
+        // them can't fail.
+        for header in section_table.chunks_exact(SECTION_HEADER_SIZE) {
+            PeSection::parse(header, long_names)?;
+        }
+
+        Ok(file)
+    }
+
+    /// Memory layout of the executable.
+    #[must_use]
+    pub fn layout(&self) -> PeLayout {
+        self.layout
+    }
+
+    /// Whether the executable is PE32+ (64-bit) rather than PE32.
+    #[must_use]
+    pub fn is_pe32_plus(&self) -> bool {
+        self.is_pe32_plus
+    }
+
+    /// Get an iterator over the sections.
+    #[must_use]
+    pub fn sections(&self) -> PeSections<'a> {
+        PeSections {
+            headers: self.section_table.chunks_exact(SECTION_HEADER_SIZE),
+            long_names: self.long_names,
+        }
+    }
+
+    /// Get the first section named `name`. The name is compared
+    /// exactly; see [`PeSection::name`] for how long names appear.
+    #[must_use]
+    pub fn section_by_name(&self, name: &str) -> Option<PeSection<'a>> {
+        self.sections()
+            .find(|section| section.name() == name.as_bytes())
+    }
+
+    /// Get the data of `section`.
+    ///
+    /// In the [`PeLayout::File`] layout, the data is read from
+    /// [`PeSection::pointer_to_raw_data`] and trimmed to
+    /// [`PeSection::file_data_size`].
+    ///
+    /// In the [`PeLayout::Loaded`] layout, the data is read from
+    /// [`PeSection::virtual_address`], and is
+    /// [`PeSection::virtual_size`] bytes long (or
+    /// [`PeSection::size_of_raw_data`] if the virtual size is zero).
+    pub fn section_data(
+        &self,
+        section: &PeSection,
+    ) -> Result<&'a [u8], PeError> {
+        let (start, size) = match self.layout {
+            PeLayout::File => {
+                (section.pointer_to_raw_data(), section.file_data_size())
+            }
+            PeLayout::Loaded => (
+                section.virtual_address(),
+                if section.virtual_size() == 0 {
+                    section.size_of_raw_data()
+                } else {
+                    section.virtual_size()
+                },
+            ),
+        };
+        let start = to_usize(start).ok_or(PeError::InvalidSectionData)?;
+        let size = to_usize(size).ok_or(PeError::InvalidSectionData)?;
+        start
+            .checked_add(size)
+            .and_then(|end| self.data.get(start..end))
+            .ok_or(PeError::InvalidSectionData)
+    }
+
+    /// Get the data of the first section named `name`.
+    pub fn section_data_by_name(
+        &self,
+        name: &str,
+    ) -> Result<&'a [u8], PeError> {
+        let section =
+            self.section_by_name(name).ok_or(PeError::MissingSection)?;
+        self.section_data(&section)
+    }
+
+    /// Parse the [`SBAT_SECTION_NAME`] section as [`ImageSbat`].
+    pub fn image_sbat(&self) -> Result<&'a ImageSbat, PeError> {
+        let data = self.section_data_by_name(SBAT_SECTION_NAME)?;
+        ImageSbat::parse(data).map_err(PeError::InvalidImageSbat)
+    }
+
+    /// Parse the [`REVOCATION_SECTION_NAME`] section as a
+    /// [`RevocationSection`].
+    ///
+    /// In the [`PeLayout::Loaded`] layout, the section name can't be
+    /// resolved (see [`parse_loaded`]), so the first section named
+    /// `.sbatlev` or `/<offset>` whose data is a valid
+    /// `RevocationSection` is used. If no such section is valid, the
+    /// error for the first one is returned.
+    ///
+    /// [`parse_loaded`]: Self::parse_loaded
+    pub fn revocation_section(&self) -> Result<RevocationSection<'a>, PeError> {
+        let parse = |section: &PeSection| {
+            RevocationSection::parse(self.section_data(section)?)
+                .map_err(PeError::InvalidRevocationSection)
+        };
+
+        if let Some(section) = self.section_by_name(REVOCATION_SECTION_NAME) {
+            return parse(&section);
+        }
+        if self.layout == PeLayout::File {
+            return Err(PeError::MissingSection);
+        }
+
+        let truncated_name = &REVOCATION_SECTION_NAME.as_bytes()[..8];
+        let mut first_error = None;
+        for section in self.sections().filter(|section| {
+            section.name() == truncated_name || section.name().starts_with(b"/")
+        }) {
+            match parse(&section) {
+                Ok(revocation_section) => return Ok(revocation_section),
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        Err(first_error.unwrap_or(PeError::MissingSection))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::arithmetic_side_effects)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ParseErrorKind;
+
+    /// Section to add in [`make_pe`].
+    pub(crate) struct TestSection<'a> {
+        pub(crate) name: &'a [u8],
+        pub(crate) data: &'a [u8],
+        pub(crate) virtual_size: u32,
+    }
+
+    impl<'a> TestSection<'a> {
+        pub(crate) fn new(name: &'a [u8], data: &'a [u8]) -> Self {
+            Self {
+                name,
+                data,
+                virtual_size: u32::try_from(data.len()).unwrap(),
+            }
+        }
+    }
+
+    const FILE_ALIGNMENT: usize = 0x200;
+    const SECTION_ALIGNMENT: usize = 0x1000;
+
+    fn align(val: usize, alignment: usize) -> usize {
+        val.div_ceil(alignment) * alignment
+    }
+
+    fn put_u16(data: &mut [u8], offset: usize, val: u16) {
+        data[offset..offset + 2].copy_from_slice(&val.to_le_bytes());
+    }
+
+    fn put_u32(data: &mut [u8], offset: usize, val: usize) {
+        let val = u32::try_from(val).unwrap();
+        data[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    /// Create a minimal PE executable in file layout. Names longer than
+    /// eight bytes are stored in a string table.
+    pub(crate) fn make_pe(
+        sections: &[TestSection],
+        pe32_plus: bool,
+    ) -> Vec<u8> {
+        let pe_offset = 0x40;
+        let optional_header_size = if pe32_plus { 240 } else { 224 };
+        let optional_header_offset = pe_offset + 4 + COFF_HEADER_SIZE;
+        let section_table_offset =
+            optional_header_offset + optional_header_size;
+        let headers_size = align(
+            section_table_offset + SECTION_HEADER_SIZE * sections.len(),
+            FILE_ALIGNMENT,
+        );
+
+        let mut data = vec![0; headers_size];
+        data[..2].copy_from_slice(DOS_SIGNATURE);
+        put_u32(&mut data, PE_OFFSET_OFFSET, pe_offset);
+        data[pe_offset..pe_offset + 4].copy_from_slice(PE_SIGNATURE);
+        let coff = pe_offset + 4;
+        put_u16(&mut data, coff, 0x8664);
+        put_u16(&mut data, coff + 2, u16::try_from(sections.len()).unwrap());
+        put_u16(
+            &mut data,
+            coff + 16,
+            u16::try_from(optional_header_size).unwrap(),
+        );
+        put_u16(
+            &mut data,
+            optional_header_offset,
+            if pe32_plus {
+                PE32_PLUS_MAGIC
+            } else {
+                PE32_MAGIC
+            },
+        );
+        put_u32(&mut data, optional_header_offset + 32, SECTION_ALIGNMENT);
+        put_u32(&mut data, optional_header_offset + 36, FILE_ALIGNMENT);
+        put_u32(&mut data, optional_header_offset + 60, headers_size);
+
+        let mut strings = vec![0; 4];
+        let mut virtual_address = SECTION_ALIGNMENT;
+        for (i, section) in sections.iter().enumerate() {
+            let header = section_table_offset + i * SECTION_HEADER_SIZE;
+            if section.name.len() > 8 {
+                let name = format!("/{}", strings.len());
+                data[header..header + name.len()]
+                    .copy_from_slice(name.as_bytes());
+                strings.extend(section.name);
+                strings.push(0);
+            } else {
+                data[header..header + section.name.len()]
+                    .copy_from_slice(section.name);
+            }
+
+            let raw_size = align(section.data.len(), FILE_ALIGNMENT);
+            let raw_offset = data.len();
+            put_u32(
+                &mut data,
+                header + 8,
+                usize::try_from(section.virtual_size).unwrap(),
+            );
+            put_u32(&mut data, header + 12, virtual_address);
+            put_u32(&mut data, header + 16, raw_size);
+            put_u32(&mut data, header + 20, raw_offset);
+            put_u32(&mut data, header + 36, 0x4000_0040);
+
+            data.extend(section.data);
+            data.resize(raw_offset + raw_size, 0);
+            virtual_address += align(
+                usize::try_from(section.virtual_size).unwrap().max(1),
+                SECTION_ALIGNMENT,
+            );
+        }
+        put_u32(&mut data, optional_header_offset + 56, virtual_address);
+
+        if strings.len() > 4 {
+            let symbol_table_offset = data.len();
+            put_u32(&mut data, coff + 8, symbol_table_offset);
+            let len = strings.len();
+            put_u32(&mut strings, 0, len);
+            data.extend(strings);
+        }
+
+        data
+    }
+
+    #[test]
+    fn test_sections() {
+        for pe32_plus in [false, true] {
+            let data = make_pe(
+                &[
+                    TestSection::new(b".text", &[0xc3]),
+                    TestSection::new(b".sbat", b"sbat,1\n"),
+                ],
+                pe32_plus,
+            );
+            let file = PeFile::parse(&data).unwrap();
+            assert_eq!(file.is_pe32_plus(), pe32_plus);
+            let names: Vec<_> = file.sections().map(|s| s.name()).collect();
+            assert_eq!(names, [b".text".as_slice(), b".sbat"]);
+
+            let section = file.section_by_name(".sbat").unwrap();
+            assert_eq!(section.virtual_address(), 0x2000);
+            assert_eq!(section.virtual_size(), 7);
+            assert_eq!(section.size_of_raw_data(), 0x200);
+            assert_eq!(section.characteristics(), 0x4000_0040);
+            assert_eq!(file.section_data(&section).unwrap(), b"sbat,1\n");
+            assert!(file.section_by_name(".data").is_none());
+        }
+    }
+
+    #[test]
+    fn test_image_sbat() {
+        let data = make_pe(&[TestSection::new(b".sbat", b"sbat,1\n")], true);
+        let file = PeFile::parse(&data).unwrap();
+        assert_eq!(
+            file.image_sbat().unwrap(),
+            ImageSbat::parse(b"sbat,1\n").unwrap()
+        );
+        assert_eq!(file.revocation_section(), Err(PeError::MissingSection));
+
+        let data = make_pe(&[TestSection::new(b".sbat", b"sbat,x\n")], true);
+        let file = PeFile::parse(&data).unwrap();
+        assert_eq!(
+            file.image_sbat().unwrap_err(),
+            PeError::InvalidImageSbat(ParseError::new(
+                ParseErrorKind::invalid_generation("x"),
+                Some(crate::Position {
+                    record: 0,
+                    line: 1,
+                    column: 6,
+                    offset: 5,
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn test_revocation_section() {
+        let section_data = include_bytes!("../tests/sbatlevel.section");
+        let data = make_pe(
+            &[TestSection::new(
+                REVOCATION_SECTION_NAME.as_bytes(),
+                section_data,
+            )],
+            true,
+        );
+        let file = PeFile::parse(&data).unwrap();
+        assert_eq!(
+            file.section_by_name(REVOCATION_SECTION_NAME)
+                .unwrap()
+                .name(),
+            b".sbatlevel"
+        );
+        assert_eq!(
+            file.revocation_section().unwrap(),
+            RevocationSection::parse(section_data).unwrap()
+        );
+
+        let data = make_pe(
+            &[TestSection::new(
+                REVOCATION_SECTION_NAME.as_bytes(),
+                b"\x01",
+            )],
+            true,
+        );
+        assert_eq!(
+            PeFile::parse(&data).unwrap().revocation_section(),
+            Err(PeError::InvalidRevocationSection(
+                RevocationSectionError::MissingVersion
+            ))
+        );
+    }
+
+    #[test]
+    fn test_size_trimming() {
+        // Virtual size smaller than raw size: trimmed to virtual size.
+        let data = make_pe(
+            &[TestSection {
+                virtual_size: 3,
+                ..TestSection::new(b".sbat", b"abcdef")
+            }],
+            true,
+        );
+        let file = PeFile::parse(&data).unwrap();
+        let section = file.section_by_name(".sbat").unwrap();
+        assert_eq!(section.file_data_size(), 3);
+        assert_eq!(file.section_data(&section).unwrap(), b"abc");
+
+        // Virtual size larger than raw size: limited to raw size.
+        let data = make_pe(
+            &[TestSection {
+                virtual_size: 0x1000,
+                ..TestSection::new(b".sbat", b"abcdef")
+            }],
+            true,
+        );
+        let file = PeFile::parse(&data).unwrap();
+        let section = file.section_by_name(".sbat").unwrap();
+        assert_eq!(section.file_data_size(), 0x200);
+        assert_eq!(file.section_data(&section).unwrap().len(), 0x200);
+
+        // Virtual size of zero: use the raw size.
+        let data = make_pe(
+            &[TestSection {
+                virtual_size: 0,
+                ..TestSection::new(b".sbat", b"abcdef")
+            }],
+            true,
+        );
+        let file = PeFile::parse(&data).unwrap();
+        let section = file.section_by_name(".sbat").unwrap();
+        assert_eq!(section.file_data_size(), 0x200);
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        let valid = make_pe(&[TestSection::new(b".sbat", b"sbat,1\n")], true);
+
+        assert_eq!(PeFile::parse(b""), Err(PeError::InvalidDosHeader));
+        assert_eq!(PeFile::parse(b"MZ"), Err(PeError::InvalidDosHeader));
+
+        let mut data = valid.clone();
+        data[0] = b'X';
+        assert_eq!(PeFile::parse(&data), Err(PeError::InvalidDosHeader));
+
+        let mut data = valid.clone();
+        data[0x40] = b'X';
+        assert_eq!(PeFile::parse(&data), Err(PeError::InvalidPeSignature));
+
+        let mut data = valid.clone();
+        put_u32(&mut data, PE_OFFSET_OFFSET, 0xffff_fff0);
+        assert_eq!(PeFile::parse(&data), Err(PeError::InvalidPeSignature));
+
+        assert_eq!(
+            PeFile::parse(&valid[..0x50]),
+            Err(PeError::InvalidCoffHeader)
+        );
+        assert_eq!(
+            PeFile::parse(&valid[..0x58]),
+            Err(PeError::InvalidOptionalHeader)
+        );
+
+        let mut data = valid.clone();
+        put_u16(&mut data, 0x58, 0x1234);
+        assert_eq!(
+            PeFile::parse(&data),
+            Err(PeError::InvalidOptionalHeaderMagic(0x1234))
+        );
+
+        // Too many sections for the data.
+        let mut data = valid.clone();
+        put_u16(&mut data, 0x46, 0xffff);
+        assert_eq!(PeFile::parse(&data), Err(PeError::InvalidSectionTable));
+
+        // Optional header size past the end of the data.
+        let mut data = valid.clone();
+        put_u16(&mut data, 0x54, 0xffff);
+        assert_eq!(PeFile::parse(&data), Err(PeError::InvalidOptionalHeader));
+    }
+
+    #[test]
+    fn test_invalid_sections() {
+        let valid = make_pe(&[TestSection::new(b".sbat", b"sbat,1\n")], true);
+        let section_header = 0x58 + 240;
+
+        // Long name with no string table is left unresolved, and
+        // doesn't hide other sections.
+        let mut data = make_pe(
+            &[
+                TestSection::new(b".text", &[0xc3]),
+                TestSection::new(b".sbat", b"sbat,1\n"),
+            ],
+            true,
+        );
+        data[section_header..section_header + 8]
+            .copy_from_slice(b"/4\0\0\0\0\0\0");
+        let file = PeFile::parse(&data).unwrap();
+        assert_eq!(file.sections().next().unwrap().name(), b"/4");
+        assert_eq!(file.image_sbat().unwrap().as_csv(), "sbat,1\n");
+
+        // Long name that isn't a number.
+        let mut data = valid.clone();
+        data[section_header..section_header + 8]
+            .copy_from_slice(b"/x\0\0\0\0\0\0");
+        assert_eq!(PeFile::parse(&data), Err(PeError::InvalidSectionName));
+
+        // Data past the end of the file.
+        let mut data = valid.clone();
+        put_u32(&mut data, section_header + 20, 0xffff_0000);
+        let file = PeFile::parse(&data).unwrap();
+        assert_eq!(file.image_sbat(), Err(PeError::InvalidSectionData));
+    }
+
+    /// Convert a PE executable in file layout to the loaded layout.
+    pub(crate) fn load_pe(file_data: &[u8]) -> Vec<u8> {
+        let file = PeFile::parse(file_data).unwrap();
+        let optional_header = 0x58;
+        let size_of_image =
+            usize::try_from(read_u32(file_data, optional_header + 56).unwrap())
+                .unwrap();
+        let size_of_headers =
+            usize::try_from(read_u32(file_data, optional_header + 60).unwrap())
+                .unwrap();
+
+        let mut image = vec![0; size_of_image];
+        image[..size_of_headers].copy_from_slice(&file_data[..size_of_headers]);
+        for section in file.sections() {
+            let data = file.section_data(&section).unwrap();
+            let start = usize::try_from(section.virtual_address()).unwrap();
+            image[start..start + data.len()].copy_from_slice(data);
+        }
+        image
+    }
+
+    #[test]
+    fn test_loaded() {
+        let sbat_level = include_bytes!("../tests/sbatlevel.section");
+        let expected = RevocationSection::parse(sbat_level).unwrap();
+
+        // `make_pe` stores long names in the string table, so in memory
+        // they are unresolved `/<offset>` names. The first long name
+        // isn't revocation data, so it must be skipped.
+        let data = make_pe(
+            &[
+                TestSection::new(b".text", &[0xc3]),
+                TestSection::new(b".sbat", b"sbat,1\n"),
+                TestSection::new(b".debug_info", b"not revocations"),
+                TestSection::new(
+                    REVOCATION_SECTION_NAME.as_bytes(),
+                    sbat_level,
+                ),
+            ],
+            true,
+        );
+        let image = load_pe(&data);
+        let file = PeFile::parse_loaded(&image).unwrap();
+        assert_eq!(file.layout(), PeLayout::Loaded);
+        assert_eq!(file.sections().nth(2).unwrap().name(), b"/4");
+        assert_eq!(file.revocation_section(), Ok(expected));
+
+        // In memory, the data is the full virtual size.
+        let section = file.section_by_name(".sbat").unwrap();
+        assert_eq!(file.section_data(&section).unwrap(), b"sbat,1\n");
+        assert_eq!(
+            file.image_sbat().unwrap(),
+            ImageSbat::parse(b"sbat,1\n").unwrap()
+        );
+
+        // Sections past the end of the image are an error.
+        assert_eq!(
+            PeFile::parse_loaded(&image[..0x2000]).unwrap().image_sbat(),
+            Err(PeError::InvalidSectionData)
+        );
+
+        // Some linkers truncate the name instead.
+        let data = make_pe(&[TestSection::new(b".sbatlev", sbat_level)], false);
+        let image = load_pe(&data);
+        let file = PeFile::parse_loaded(&image).unwrap();
+        assert_eq!(file.revocation_section(), Ok(expected));
+
+        // A long-named section with invalid data.
+        let data = make_pe(
+            &[TestSection::new(b".debug_info", b"not revocations")],
+            true,
+        );
+        let image = load_pe(&data);
+        let file = PeFile::parse_loaded(&image).unwrap();
+        assert!(matches!(
+            file.revocation_section(),
+            Err(PeError::InvalidRevocationSection(_))
+        ));
+
+        let data = make_pe(&[TestSection::new(b".sbat", b"sbat,1\n")], true);
+        let image = load_pe(&data);
+        let file = PeFile::parse_loaded(&image).unwrap();
+        assert_eq!(file.revocation_section(), Err(PeError::MissingSection));
+    }
+
+    #[test]
+    #[allow(unsafe_code)]
+    fn test_from_loaded_image() {
+        let data = make_pe(&[TestSection::new(b".sbat", b"sbat,1\n")], false);
+        let image = load_pe(&data);
+        let size = u64::try_from(image.len()).unwrap();
+
+        // SAFETY: `image` is valid for `size` bytes and is not modified.
+        let file =
+            unsafe { PeFile::from_loaded_image(image.as_ptr().cast(), size) }
+                .unwrap();
+        assert_eq!(file, PeFile::parse_loaded(&image).unwrap());
+        assert!(file.image_sbat().is_ok());
+
+        // SAFETY: null is allowed.
+        assert_eq!(
+            unsafe { PeFile::from_loaded_image(core::ptr::null(), size) },
+            Err(PeError::InvalidImage)
+        );
+    }
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(
+            format!("{}", PeError::InvalidOptionalHeaderMagic(0x1234)),
+            "invalid optional header magic: 0x1234"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                PeError::InvalidRevocationSection(
+                    RevocationSectionError::MissingVersion
+                )
+            ),
+            "invalid .sbatlevel section: missing version field"
+        );
+    }
+}
@@ -0,0 +1,406 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Indexed revocations.
+//!
+//! Each lookup in [`RevocationSbat`] parses the whole revocation CSV,
+//! which is slow when validating many images. An index parses the
+//! revocation data once and keeps the components sorted by name, so
+//! lookups are a binary search.
+
+use crate::revocations::RevocationSource;
+use crate::{
+    Component, Generation, ImageSbat, RevocationSbat, RevokedEntries,
+    ValidationResult,
+};
+use arrayvec::ArrayVec;
+use ascii::AsciiStr;
+use core::fmt::{self, Display, Formatter};
+
+#[cfg(feature = "alloc")]
+use crate::RevokedEntry;
+#[cfg(feature = "alloc")]
+use rust_alloc::vec::Vec;
+
+/// Get the position at which to insert `component` into `components`
+/// such that the slice stays sorted by name. Components with equal
+/// names stay in their original order.
+pub(crate) fn insert_position(
+    components: &[Component],
+    component: &Component,
+) -> usize {
+    components.partition_point(|c| c.name <= component.name)
+}
+
+/// Get all components in the sorted `components` slice named `name`.
+pub(crate) fn components_named<'c, 'r>(
+    components: &'c [Component<'r>],
+    name: &AsciiStr,
+) -> &'c [Component<'r>] {
+    let start = components.partition_point(|c| c.name < name);
+    let (_, rest) = components.split_at(start);
+    let len = rest.partition_point(|c| c.name == name);
+    rest.split_at(len).0
+}
+
+/// Get the first component in the sorted `components` slice that
+/// revokes `input`. The result is the same as for the unsorted
+/// revocation data, since components with the same name keep their
+/// original order.
+pub(crate) fn revoking_component_sorted<'r>(
+    components: &[Component<'r>],
+    input: &Component,
+) -> Option<Component<'r>> {
+    components_named(components, input.name)
+        .iter()
+        .find(|revocation| input.generation < revocation.generation)
+        .copied()
+}
+
+/// Get the minimum generation required for `name` in the sorted
+/// `components` slice.
+pub(crate) fn minimum_generation_sorted(
+    components: &[Component],
+    name: &AsciiStr,
+) -> Option<Generation> {
+    components_named(components, name)
+        .iter()
+        .map(|c| c.generation)
+        .max()
+}
+
+/// Get an iterator over all entries in `image_sbat` revoked by the
+/// sorted `components` slice.
+fn revoked_entries_sorted<'i, 'r>(
+    components: &'r [Component<'r>],
+    image_sbat: &'i ImageSbat,
+) -> RevokedEntries<'i, 'r> {
+    RevokedEntries::new(RevocationSource::Index(components), image_sbat)
+}
+
+/// Check if any component in `image_sbat` is revoked by the sorted
+/// `components` slice.
+fn validate_image_sorted<'i>(
+    components: &[Component],
+    image_sbat: &'i ImageSbat,
+) -> ValidationResult<'i> {
+    match revoked_entries_sorted(components, image_sbat).next() {
+        Some(revoked_entry) => ValidationResult::Revoked(revoked_entry.entry),
+        None => ValidationResult::Allowed,
+    }
+}
+
+/// Error returned by [`RevocationIndexArray::new`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum RevocationIndexError {
+    /// The revocation data has more components than the index can hold.
+    CapacityExceeded,
+}
+
+impl Display for RevocationIndexError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CapacityExceeded => {
+                write!(f, "too many components for the index capacity")
+            }
+        }
+    }
+}
+
+impl core::error::Error for RevocationIndexError {}
+
+/// Fixed-capacity index of [`RevocationSbat`] components.
+///
+/// Up to `N` revocation components are stored, sorted by name. Lookups
+/// and validation give the same results as the corresponding methods
+/// of [`RevocationSbat`], but without re-parsing the revocation data
+/// each time.
+///
+/// See [`RevocationIndex`] for a variant without a fixed capacity.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RevocationIndexArray<'a, const N: usize> {
+    components: ArrayVec<Component<'a>, N>,
+}
+
+impl<'a, const N: usize> RevocationIndexArray<'a, N> {
+    /// Create an index of the components in `revocations`.
+    ///
+    /// An error is returned if there are more than `N` components.
+    pub fn new(
+        revocations: &'a RevocationSbat,
+    ) -> Result<Self, RevocationIndexError> {
+        let mut components = ArrayVec::new();
+        for component in revocations.revoked_components() {
+            let index = insert_position(&components, &component);
+            components
+                .try_insert(index, component)
+                .map_err(|_| RevocationIndexError::CapacityExceeded)?;
+        }
+        Ok(Self { components })
+    }
+
+    /// Get the revocation components, sorted by name.
+    #[must_use]
+    pub fn components(&self) -> &[Component<'a>] {
+        &self.components
+    }
+
+    /// Get the minimum generation of the component named `name`
+    /// required by the revocation data. If the revocation data contains
+    /// more than one component with that name, the highest generation
+    /// is returned. If there is no such component, `None` is returned.
+    #[must_use]
+    pub fn minimum_generation(&self, name: &AsciiStr) -> Option<Generation> {
+        minimum_generation_sorted(&self.components, name)
+    }
+
+    /// Check if the `input` [`Component`] is revoked.
+    ///
+    /// See [`RevocationSbat::is_component_revoked`].
+    #[must_use]
+    pub fn is_component_revoked(&self, input: &Component) -> bool {
+        revoking_component_sorted(&self.components, input).is_some()
+    }
+
+    /// Get an iterator over all revoked entries in `image_sbat`.
+    ///
+    /// See [`RevocationSbat::revoked_entries`].
+    #[must_use]
+    pub fn revoked_entries<'i, 'r>(
+        &'r self,
+        image_sbat: &'i ImageSbat,
+    ) -> RevokedEntries<'i, 'r> {
+        revoked_entries_sorted(&self.components, image_sbat)
+    }
+
+    /// Check if any component in `image_sbat` is revoked.
+    ///
+    /// See [`RevocationSbat::validate_image`].
+    pub fn validate_image<'i>(
+        &self,
+        image_sbat: &'i ImageSbat,
+    ) -> ValidationResult<'i> {
+        validate_image_sorted(&self.components, image_sbat)
+    }
+}
+
+/// Index of [`RevocationSbat`] components.
+///
+/// The revocation components are stored sorted by name. Lookups and
+/// validation give the same results as the corresponding methods of
+/// [`RevocationSbat`], but without re-parsing the revocation data each
+/// time, which makes this type a good fit for validating many images.
+///
+/// See [`RevocationIndexArray`] for a variant that does not require
+/// allocation.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RevocationIndex<'a> {
+    components: Vec<Component<'a>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> RevocationIndex<'a> {
+    /// Create an index of the components in `revocations`.
+    #[must_use]
+    pub fn new(revocations: &'a RevocationSbat) -> Self {
+        let mut components: Vec<_> = revocations.revoked_components().collect();
+        // Stable sort, so components with the same name keep their
+        // original order.
+        components.sort_by(|a, b| a.name.cmp(b.name));
+        Self { components }
+    }
+
+    /// Get the revocation components, sorted by name.
+    #[must_use]
+    pub fn components(&self) -> &[Component<'a>] {
+        &self.components
+    }
+
+    /// Get the minimum generation of the component named `name`
+    /// required by the revocation data. If the revocation data contains
+    /// more than one component with that name, the highest generation
+    /// is returned. If there is no such component, `None` is returned.
+    #[must_use]
+    pub fn minimum_generation(&self, name: &AsciiStr) -> Option<Generation> {
+        minimum_generation_sorted(&self.components, name)
+    }
+
+    /// Check if the `input` [`Component`] is revoked.
+    ///
+    /// See [`RevocationSbat::is_component_revoked`].
+    #[must_use]
+    pub fn is_component_revoked(&self, input: &Component) -> bool {
+        revoking_component_sorted(&self.components, input).is_some()
+    }
+
+    /// Get an iterator over all revoked entries in `image_sbat`.
+    ///
+    /// See [`RevocationSbat::revoked_entries`].
+    #[must_use]
+    pub fn revoked_entries<'i, 'r>(
+        &'r self,
+        image_sbat: &'i ImageSbat,
+    ) -> RevokedEntries<'i, 'r> {
+        revoked_entries_sorted(&self.components, image_sbat)
+    }
+
+    /// Get all revoked entries in `image_sbat`.
+    ///
+    /// See [`RevocationSbat::validate_image_report`].
+    #[must_use]
+    pub fn validate_image_report<'i, 'r>(
+        &'r self,
+        image_sbat: &'i ImageSbat,
+    ) -> Vec<RevokedEntry<'i, 'r>> {
+        self.revoked_entries(image_sbat).collect()
+    }
+
+    /// Check if any component in `image_sbat` is revoked.
+    ///
+    /// See [`RevocationSbat::validate_image`].
+    pub fn validate_image<'i>(
+        &self,
+        image_sbat: &'i ImageSbat,
+    ) -> ValidationResult<'i> {
+        validate_image_sorted(&self.components, image_sbat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REVOCATIONS: &[u8] = b"sbat,1,2021030218\n\
+                                 grub,3\n\
+                                 compA,1\n\
+                                 grub,2\n\
+                                 grub.acme,1\n";
+
+    const IMAGES: &[&[u8]] = &[
+        b"sbat,1,SBAT Version,sbat,1,https://example.com\n",
+        b"sbat,1,SBAT Version,sbat,1,https://example.com\n\
+          grub,1,Free Software Foundation,grub,2.04,https://example.com\n",
+        b"grub,2,Free Software Foundation,grub,2.04,https://example.com\n\
+          compA,1,A,a,1,https://example.com\n",
+        b"grub,3,Free Software Foundation,grub,2.04,https://example.com\n\
+          grub.acme,1,Acme,grub,2.04,https://example.com\n",
+        b"compB,1,B,b,1,https://example.com\n",
+        b"sbat,2,SBAT Version,sbat,1,https://example.com\n\
+          grub,4,Free Software Foundation,grub,2.04,https://example.com\n",
+    ];
+
+    fn ascii(s: &str) -> &AsciiStr {
+        AsciiStr::from_ascii(s).unwrap()
+    }
+
+    #[test]
+    fn test_index_sorted() {
+        let revocations = RevocationSbat::parse(REVOCATIONS).unwrap();
+        let index = RevocationIndexArray::<8>::new(revocations).unwrap();
+        let names: Vec<_> =
+            index.components().iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["compA", "grub", "grub", "grub.acme", "sbat"]);
+
+        // Components with the same name keep their original order.
+        let generations: Vec<_> = index
+            .components()
+            .iter()
+            .filter(|c| c.name == "grub")
+            .map(|c| c.generation.to_u32())
+            .collect();
+        assert_eq!(generations, [3, 2]);
+    }
+
+    #[test]
+    fn test_minimum_generation() {
+        let revocations = RevocationSbat::parse(REVOCATIONS).unwrap();
+        let index = RevocationIndexArray::<8>::new(revocations).unwrap();
+        let min_gen = |name| index.minimum_generation(ascii(name));
+        assert_eq!(min_gen("grub"), Some(Generation::new(3).unwrap()));
+        assert_eq!(min_gen("compA"), Some(Generation::new(1).unwrap()));
+        assert_eq!(min_gen("sbat"), Some(Generation::new(1).unwrap()));
+        assert_eq!(min_gen("grub.fedora"), None);
+        assert_eq!(min_gen(""), None);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let revocations = RevocationSbat::parse(REVOCATIONS).unwrap();
+        assert!(RevocationIndexArray::<5>::new(revocations).is_ok());
+        assert_eq!(
+            RevocationIndexArray::<4>::new(revocations),
+            Err(RevocationIndexError::CapacityExceeded)
+        );
+
+        let empty = RevocationSbat::parse(b"").unwrap();
+        let index = RevocationIndexArray::<0>::new(empty).unwrap();
+        assert!(index.components().is_empty());
+    }
+
+    #[test]
+    fn test_same_results_as_revocation_sbat() {
+        let revocations = RevocationSbat::parse(REVOCATIONS).unwrap();
+        let index = RevocationIndexArray::<8>::new(revocations).unwrap();
+
+        for image in IMAGES {
+            let image = ImageSbat::parse(image).unwrap();
+            assert_eq!(
+                index.validate_image(image),
+                revocations.validate_image(image)
+            );
+            assert!(
+                index
+                    .revoked_entries(image)
+                    .eq(revocations.revoked_entries(image))
+            );
+            for entry in image.entries() {
+                assert_eq!(
+                    index.is_component_revoked(&entry.component),
+                    revocations.is_component_revoked(&entry.component)
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_revocation_index() {
+        let revocations = RevocationSbat::parse(
+            b"sbat,1
+grub,3
+compA,1
+grub,2
+",
+        )
+        .unwrap();
+        let index = RevocationIndex::new(revocations);
+        let index_array = RevocationIndexArray::<4>::new(revocations).unwrap();
+        assert_eq!(index.components(), index_array.components());
+        assert_eq!(
+            index.minimum_generation(ascii("grub")),
+            Some(Generation::new(3).unwrap())
+        );
+
+        let image = ImageSbat::parse(
+            b"sbat,1,SBAT Version,sbat,1,https://example.com\n\
+              grub,2,Free Software Foundation,grub,2.04,https://example.com\n",
+        )
+        .unwrap();
+        assert_eq!(
+            index.validate_image(image),
+            revocations.validate_image(image)
+        );
+        assert_eq!(
+            index.validate_image_report(image),
+            revocations.validate_image_report(image)
+        );
+        assert_eq!(index.validate_image_report(image).len(), 1);
+    }
+}
@@ -6,14 +6,24 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use crate::RevocationSbat;
 use core::fmt::{self, Display, Formatter};
 use core::mem;
 
+#[cfg(feature = "alloc")]
+use rust_alloc::vec::Vec;
+
 /// Name of the revocation section embedded in shim executables.
 ///
 /// See [`RevocationSection`] for details of this section.
 pub const REVOCATION_SECTION_NAME: &str = ".sbatlevel";
 
+/// Size of the version field.
+const VERSION_SIZE: usize = mem::size_of::<u32>();
+
+/// Size of the previous and latest offset fields.
+const PAYLOAD_HEADER_SIZE: usize = mem::size_of::<u32>() * 2;
+
 /// Error returned by [`RevocationSection::parse`].
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum RevocationSectionError {
@@ -89,7 +99,6 @@ impl core::error::Error for RevocationSectionError {}
 /// that can be parsed with [`RevocationSbat::parse`].
 ///
 /// [pr]: https://github.com/rhboot/shim/pull/483
-/// [`RevocationSbat::parse`]: crate::RevocationSbat::parse
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct RevocationSection<'a> {
     previous: &'a [u8],
@@ -107,19 +116,16 @@ impl<'a> RevocationSection<'a> {
     ) -> Result<RevocationSection<'a>, RevocationSectionError> {
         use RevocationSectionError::*;
 
-        const PAYLOAD_HEADER_SIZE: usize = mem::size_of::<u32>() * 2;
-
-        let version_size = mem::size_of::<u32>();
-        if data.len() < version_size {
+        if data.len() < VERSION_SIZE {
             return Err(MissingVersion);
         }
         let version =
-            u32::from_le_bytes(data[..version_size].try_into().unwrap());
+            u32::from_le_bytes(data[..VERSION_SIZE].try_into().unwrap());
         if version != 0 {
             return Err(InvalidVersion(version));
         }
 
-        data = &data[version_size..];
+        data = &data[VERSION_SIZE..];
         if data.len() < PAYLOAD_HEADER_SIZE {
             return Err(MissingHeader);
         }
@@ -156,23 +162,72 @@ impl<'a> RevocationSection<'a> {
         Ok(Self { previous, latest })
     }
 
+    /// Create a `RevocationSection` from previous and latest revocation
+    /// data.
+    ///
+    /// Use [`to_bytes`] to get the raw section data.
+    ///
+    /// [`to_bytes`]: Self::to_bytes
+    #[must_use]
+    pub fn new(
+        previous: &'a RevocationSbat,
+        latest: &'a RevocationSbat,
+    ) -> Self {
+        Self {
+            previous: previous.as_csv().as_bytes(),
+            latest: latest.as_csv().as_bytes(),
+        }
+    }
+
+    /// Get the raw section data.
+    ///
+    /// The layout matches the `.sbatlevel` section created by shim: the
+    /// header is followed by the null-terminated previous data, which
+    /// is followed by the null-terminated latest data. Passing the
+    /// output to [`parse`] gives back an identical `RevocationSection`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the offset of the latest data does not fit in a
+    /// [`u32`].
+    ///
+    /// [`parse`]: Self::parse
+    #[cfg(feature = "alloc")]
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let previous_offset = PAYLOAD_HEADER_SIZE;
+        let latest_offset = previous_offset
+            .checked_add(self.previous.len())
+            .and_then(|offset| offset.checked_add(1))
+            .unwrap();
+
+        let to_u32_bytes =
+            |offset| u32::try_from(offset).unwrap().to_le_bytes();
+
+        let mut data = Vec::new();
+        data.extend(0u32.to_le_bytes());
+        data.extend(to_u32_bytes(previous_offset));
+        data.extend(to_u32_bytes(latest_offset));
+        data.extend(self.previous);
+        data.push(0);
+        data.extend(self.latest);
+        data.push(0);
+        data
+    }
+
     /// Get the raw previous revocation data.
     ///
     /// This data be parsed with [`RevocationSbat::parse`].
-    ///
-    /// [`RevocationSbat::parse`]: crate::RevocationSbat::parse
     #[must_use]
-    pub fn previous(&self) -> &[u8] {
+    pub fn previous(&self) -> &'a [u8] {
         self.previous
     }
 
     /// Get the raw latest revocation data.
     ///
     /// This data be parsed with [`RevocationSbat::parse`].
-    ///
-    /// [`RevocationSbat::parse`]: crate::RevocationSbat::parse
     #[must_use]
-    pub fn latest(&self) -> &[u8] {
+    pub fn latest(&self) -> &'a [u8] {
         self.latest
     }
 }
@@ -11,14 +11,22 @@
 //! Typically this data is read from a UEFI variable. See the crate
 //! documentation for details of how it is used.
 
-use crate::csv::{CsvIter, trim_ascii_at_null};
-use crate::{Component, Entry, ImageSbat, ParseError};
+use crate::csv::{CsvIter, check_header, validate};
+use crate::revocation_index::revoking_component_sorted;
+use crate::{
+    Component, Entries, Entry, Generation, ImageSbat, ParseError, ParseWarning,
+    RevocationDate,
+};
+use arrayvec::ArrayVec;
 use ascii::AsciiStr;
 use core::ptr;
 
+#[cfg(feature = "alloc")]
+use rust_alloc::vec::Vec;
+
 /// The first entry has the component name and generation like the
 /// others, but may also have a date field.
-const MAX_HEADER_FIELDS: usize = 3;
+pub(crate) const MAX_HEADER_FIELDS: usize = 3;
 
 /// Whether an image is allowed or revoked.
 #[must_use]
@@ -32,6 +40,125 @@ pub enum ValidationResult<'a> {
     Revoked(Entry<'a>),
 }
 
+/// Revoked entry in an image.
+///
+/// See [`RevocationSbat::revoked_entries`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RevokedEntry<'i, 'r> {
+    /// The entry in the image metadata that is revoked.
+    pub entry: Entry<'i>,
+
+    /// The revocation component that matched the entry.
+    pub revocation: Component<'r>,
+}
+
+impl RevokedEntry<'_, '_> {
+    /// Generation of the component in the image.
+    #[must_use]
+    pub fn image_generation(&self) -> Generation {
+        self.entry.component.generation
+    }
+
+    /// Minimum generation of the component required by the revocation
+    /// data.
+    #[must_use]
+    pub fn minimum_generation(&self) -> Generation {
+        self.revocation.generation
+    }
+}
+
+/// Revocation data that revoked entries are looked up in.
+#[derive(Clone, Copy)]
+pub(crate) enum RevocationSource<'r> {
+    /// Unindexed revocation data.
+    Sbat(&'r RevocationSbat),
+
+    /// Revocation components sorted by name, as stored in an index.
+    Index(&'r [Component<'r>]),
+}
+
+impl<'r> RevocationSource<'r> {
+    fn revoking_component(self, input: &Component) -> Option<Component<'r>> {
+        match self {
+            Self::Sbat(revocations) => revocations.revoking_component(input),
+            Self::Index(components) => {
+                revoking_component_sorted(components, input)
+            }
+        }
+    }
+}
+
+/// Iterator over revoked entries in an [`ImageSbat`].
+///
+/// See [`RevocationSbat::revoked_entries`].
+pub struct RevokedEntries<'i, 'r> {
+    revocations: RevocationSource<'r>,
+    entries: Entries<'i>,
+}
+
+impl<'i, 'r> RevokedEntries<'i, 'r> {
+    pub(crate) fn new(
+        revocations: RevocationSource<'r>,
+        image_sbat: &'i ImageSbat,
+    ) -> Self {
+        Self {
+            revocations,
+            entries: image_sbat.entries(),
+        }
+    }
+}
+
+impl<'i, 'r> Iterator for RevokedEntries<'i, 'r> {
+    type Item = RevokedEntry<'i, 'r>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.find_map(|entry| {
+            self.revocations
+                .revoking_component(&entry.component)
+                .map(|revocation| RevokedEntry { entry, revocation })
+        })
+    }
+}
+
+/// Report of revoked entries in an image, with a fixed capacity.
+///
+/// See [`RevocationSbat::validate_image_report_array`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidationReport<'i, 'r, const N: usize> {
+    revoked: ArrayVec<RevokedEntry<'i, 'r>, N>,
+    num_revoked: usize,
+}
+
+impl<'i, 'r, const N: usize> ValidationReport<'i, 'r, N> {
+    /// Whether the image is allowed, i.e. no entries are revoked.
+    #[must_use]
+    pub fn is_allowed(&self) -> bool {
+        self.num_revoked == 0
+    }
+
+    /// Get the revoked entries. If there were more than `N` revoked
+    /// entries, only the first `N` are included; see [`is_complete`].
+    ///
+    /// [`is_complete`]: Self::is_complete
+    #[must_use]
+    pub fn revoked_entries(&self) -> &[RevokedEntry<'i, 'r>] {
+        &self.revoked
+    }
+
+    /// Total number of revoked entries, including any that did not fit
+    /// in the report.
+    #[must_use]
+    pub fn num_revoked(&self) -> usize {
+        self.num_revoked
+    }
+
+    /// Whether all revoked entries fit in the report.
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.revoked.len() == self.num_revoked
+    }
+}
+
 /// Iterator over revoked components in [`RevocationSbat`].
 ///
 /// See [`RevocationSbat::revoked_components`].
@@ -67,16 +194,29 @@ impl RevocationSbat {
     ///
     /// [`RevocationSection`]: crate::RevocationSection
     pub fn parse(input: &[u8]) -> Result<&Self, ParseError> {
-        let input = trim_ascii_at_null(input)?;
-
-        // Ensure that all components are valid.
-        let iter = CsvIter::<{ MAX_HEADER_FIELDS }>::new(input);
-        for record in iter {
-            let record = record?;
+        Self::parse_with_warnings(input, ParseWarning::log)
+    }
 
-            // Check that the first two fields are valid.
-            Component::from_record(&record)?;
-        }
+    /// Parse SBAT data from raw CSV, passing any warnings to
+    /// `on_warning`.
+    ///
+    /// This is the same as [`parse`], except that instead of logging,
+    /// a [`ParseWarning`] is reported for each questionable but valid
+    /// part of the input. See [`ImageSbat::parse_with_warnings`] for
+    /// the list of warnings.
+    ///
+    /// [`parse`]: Self::parse
+    pub fn parse_with_warnings(
+        input: &[u8],
+        on_warning: impl FnMut(ParseWarning),
+    ) -> Result<&Self, ParseError> {
+        // Ensure that all components are valid. Only the first two
+        // fields are checked.
+        let input = validate::<MAX_HEADER_FIELDS>(
+            input,
+            |record| Component::from_record(record).map(|_| ()),
+            on_warning,
+        )?;
 
         Ok(Self::from_ascii_str_unchecked(input))
     }
@@ -102,7 +242,9 @@ impl RevocationSbat {
 
     /// Date of the revocation data, used as a comparable version. This
     /// is optional and may not be present. Versions should be compared
-    /// lexicographically.
+    /// lexicographically, or parsed with [`revocation_date`].
+    ///
+    /// [`revocation_date`]: Self::revocation_date
     #[allow(clippy::missing_panics_doc)]
     #[must_use]
     pub fn date(&self) -> Option<&AsciiStr> {
@@ -112,12 +254,67 @@ impl RevocationSbat {
         record.get_field(2)
     }
 
+    /// Parsed [`date`](Self::date) of the revocation data. Returns
+    /// `None` if the date is missing or invalid.
+    #[must_use]
+    pub fn revocation_date(&self) -> Option<RevocationDate> {
+        RevocationDate::from_ascii(self.date()?).ok()
+    }
+
+    /// Check if this revocation data has a newer
+    /// [`revocation_date`](Self::revocation_date) than `other`.
+    ///
+    /// Data without a valid date is older than any data with one.
+    #[must_use]
+    pub fn is_newer_than(&self, other: &RevocationSbat) -> bool {
+        self.revocation_date() > other.revocation_date()
+    }
+
     /// Get an iterator over the entries.
     #[must_use]
     pub fn revoked_components(&self) -> RevokedComponents<'_> {
         RevokedComponents(CsvIter::new(&self.0))
     }
 
+    /// Get an iterator over the revoked components for `product` and
+    /// vendor builds of it. For example, with `grub` this finds
+    /// `grub`, `grub.fedora`, and so on. See [`ComponentName`].
+    ///
+    /// [`ComponentName`]: crate::ComponentName
+    pub fn revoked_components_derived_from<'s>(
+        &'s self,
+        product: &'s str,
+    ) -> impl Iterator<Item = Component<'s>> {
+        self.revoked_components()
+            .filter(move |component| component.is_derived_from(product))
+    }
+
+    /// SBAT format version: the generation of the `sbat` header.
+    ///
+    /// Returns `None` if the first record is not the header. Use
+    /// [`check_header`] to also reject a repeated header.
+    ///
+    /// [`check_header`]: Self::check_header
+    #[must_use]
+    pub fn sbat_version(&self) -> Option<Generation> {
+        let component = self.revoked_components().next()?;
+        (component.name == "sbat").then_some(component.generation)
+    }
+
+    /// Check that the `sbat` header is present, is the first record,
+    /// and is not repeated. If so, the SBAT format version is returned.
+    ///
+    /// Parsing accepts data with any of these problems, but reports a
+    /// [`ParseWarningKind::Header`] warning.
+    ///
+    /// [`ParseWarningKind::Header`]: crate::ParseWarningKind::Header
+    #[allow(clippy::missing_panics_doc)]
+    pub fn check_header(&self) -> Result<Generation, ParseError> {
+        check_header::<MAX_HEADER_FIELDS>(&self.0)?;
+        // OK to unwrap: the header is the first record.
+        Ok(self.sbat_version().unwrap())
+    }
+
     /// Check if the `input` [`Component`] is revoked.
     ///
     /// The `input` is checked against each revocation component. If the
@@ -128,12 +325,73 @@ impl RevocationSbat {
     /// allowed.
     #[must_use]
     pub fn is_component_revoked(&self, input: &Component) -> bool {
-        self.revoked_components().any(|revoked_component| {
+        self.revoking_component(input).is_some()
+    }
+
+    /// Get the first revocation component that revokes `input`, if any.
+    fn revoking_component(&self, input: &Component) -> Option<Component<'_>> {
+        self.revoked_components().find(|revoked_component| {
             input.name == revoked_component.name
                 && input.generation < revoked_component.generation
         })
     }
 
+    /// Get an iterator over all revoked entries in `image_sbat`.
+    ///
+    /// Unlike [`validate_image`], which stops at the first revoked
+    /// entry, this checks every entry in the image. Each item contains
+    /// the revoked image entry along with the revocation component that
+    /// matched it.
+    ///
+    /// [`validate_image`]: Self::validate_image
+    #[must_use]
+    pub fn revoked_entries<'i, 'r>(
+        &'r self,
+        image_sbat: &'i ImageSbat,
+    ) -> RevokedEntries<'i, 'r> {
+        RevokedEntries::new(RevocationSource::Sbat(self), image_sbat)
+    }
+
+    /// Get all revoked entries in `image_sbat`. The image is allowed if
+    /// the result is empty.
+    ///
+    /// See [`revoked_entries`] for details, and
+    /// [`validate_image_report_array`] for a variant that does not
+    /// require allocation.
+    ///
+    /// [`revoked_entries`]: Self::revoked_entries
+    /// [`validate_image_report_array`]: Self::validate_image_report_array
+    #[cfg(feature = "alloc")]
+    #[must_use]
+    pub fn validate_image_report<'i, 'r>(
+        &'r self,
+        image_sbat: &'i ImageSbat,
+    ) -> Vec<RevokedEntry<'i, 'r>> {
+        self.revoked_entries(image_sbat).collect()
+    }
+
+    /// Get all revoked entries in `image_sbat`, storing up to `N` of
+    /// them in a fixed-capacity [`ValidationReport`].
+    ///
+    /// All entries in the image are checked even if the report is full,
+    /// so [`ValidationReport::is_allowed`] is always accurate.
+    #[must_use]
+    pub fn validate_image_report_array<'i, 'r, const N: usize>(
+        &'r self,
+        image_sbat: &'i ImageSbat,
+    ) -> ValidationReport<'i, 'r, N> {
+        let mut report = ValidationReport {
+            revoked: ArrayVec::new(),
+            num_revoked: 0,
+        };
+        for revoked_entry in self.revoked_entries(image_sbat) {
+            // Ignore the error if the report is full.
+            let _ = report.revoked.try_push(revoked_entry);
+            report.num_revoked = report.num_revoked.saturating_add(1);
+        }
+        report
+    }
+
     /// Check if any component in `image_sbat` is revoked.
     ///
     /// Each component in the image metadata is checked against the
@@ -160,7 +418,10 @@ impl RevocationSbat {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Generation, RevocationSbat, Vendor};
+    use crate::{
+        Generation, HeaderProblem, ParseErrorKind, ParseWarningKind,
+        RevocationSbat, Vendor,
+    };
 
     #[cfg(feature = "alloc")]
     use crate::RevocationSbatOwned;
@@ -202,11 +463,79 @@ mod tests {
         parse_success_helper(&RevocationSbatOwned::parse(VALID_SBAT).unwrap());
     }
 
+    #[test]
+    fn revocation_date() {
+        let parse = |s| RevocationSbat::parse(s).unwrap();
+        let old = parse(b"sbat,1,2021030218\n");
+        let new = parse(b"sbat,1,2022052400\nshim,2\n");
+        let no_date = parse(b"sbat,1\n");
+        let invalid_date = parse(b"sbat,1,2022-05-24\n");
+
+        assert_eq!(
+            old.revocation_date(),
+            Some(RevocationDate::new(2021, 3, 2, Some(18)).unwrap())
+        );
+        assert_eq!(no_date.revocation_date(), None);
+        assert_eq!(invalid_date.revocation_date(), None);
+
+        assert!(new.is_newer_than(old));
+        assert!(!old.is_newer_than(new));
+        assert!(!old.is_newer_than(old));
+        assert!(old.is_newer_than(no_date));
+        assert!(old.is_newer_than(invalid_date));
+        assert!(!no_date.is_newer_than(invalid_date));
+    }
+
+    #[test]
+    fn revoked_components_derived_from() {
+        let revocations = RevocationSbat::parse(
+            b"sbat,1,2024010900\ngrub,4\ngrub.debian,4\nshim,4\n",
+        )
+        .unwrap();
+        let names: Vec<_> = revocations
+            .revoked_components_derived_from("grub")
+            .map(|component| component.name.as_str())
+            .collect();
+        assert_eq!(names, ["grub", "grub.debian"]);
+    }
+
+    #[test]
+    fn sbat_version() {
+        let revocations = RevocationSbat::parse(VALID_SBAT).unwrap();
+        assert_eq!(revocations.sbat_version(), Some(Generation::default()));
+        assert_eq!(revocations.check_header(), Ok(Generation::default()));
+
+        let revocations = RevocationSbat::parse(b"shim,2\n").unwrap();
+        assert_eq!(revocations.sbat_version(), None);
+        assert_eq!(
+            revocations.check_header().unwrap_err().kind(),
+            &ParseErrorKind::Header(HeaderProblem::Missing)
+        );
+    }
+
     #[test]
     fn too_few_fields() {
         let input = b"sbat";
 
-        assert_eq!(RevocationSbat::parse(input), Err(ParseError::TooFewFields));
+        assert_eq!(
+            RevocationSbat::parse(input).unwrap_err().kind(),
+            &ParseErrorKind::TooFewFields
+        );
+    }
+
+    #[test]
+    fn parse_with_warnings() {
+        let mut warnings = Vec::new();
+        RevocationSbat::parse_with_warnings(
+            b"sbat,1,2021030218,extra\n\nshim,2",
+            |warning| warnings.push(warning),
+        )
+        .unwrap();
+        assert_eq!(
+            warnings.iter().map(ParseWarning::kind).collect::<Vec<_>>(),
+            [ParseWarningKind::TooManyFields, ParseWarningKind::EmptyLine]
+        );
+        assert_eq!(warnings[0].position().column, 19);
     }
 
     #[test]
@@ -286,4 +615,80 @@ mod tests {
             Revoked(make_entry("compA", 1))
         );
     }
+
+    #[test]
+    fn revoked_entries() {
+        let revocations =
+            RevocationSbat::parse(b"sbat,1\ncompA,2\ncompB,3").unwrap();
+        let image =
+            ImageSbat::parse(b"sbat,1\ncompA,1\ncompB,3\ncompC,1\ncompB,2")
+                .unwrap();
+
+        let revoked: Vec<_> = revocations.revoked_entries(image).collect();
+        assert_eq!(
+            revoked,
+            [
+                RevokedEntry {
+                    entry: make_entry("compA", 1),
+                    revocation: make_component("compA", 2),
+                },
+                RevokedEntry {
+                    entry: make_entry("compB", 2),
+                    revocation: make_component("compB", 3),
+                },
+            ]
+        );
+        assert_eq!(revoked[1].image_generation(), Generation::new(2).unwrap());
+        assert_eq!(
+            revoked[1].minimum_generation(),
+            Generation::new(3).unwrap()
+        );
+
+        let image = ImageSbat::parse(b"sbat,1\ncompA,2").unwrap();
+        assert_eq!(revocations.revoked_entries(image).next(), None);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn validate_image_report() {
+        let revocations = RevocationSbat::parse(b"compA,2\ncompB,3").unwrap();
+
+        let image = ImageSbat::parse(b"compA,1\ncompB,2").unwrap();
+        assert_eq!(
+            revocations.validate_image_report(image),
+            revocations.revoked_entries(image).collect::<Vec<_>>()
+        );
+        assert_eq!(revocations.validate_image_report(image).len(), 2);
+
+        let image = ImageSbat::parse(b"compA,2\ncompB,3").unwrap();
+        assert!(revocations.validate_image_report(image).is_empty());
+    }
+
+    #[test]
+    fn validate_image_report_array() {
+        let revocations = RevocationSbat::parse(b"compA,2\ncompB,3").unwrap();
+        let image = ImageSbat::parse(b"compA,1\ncompB,2").unwrap();
+
+        let report = revocations.validate_image_report_array::<2>(image);
+        assert!(!report.is_allowed());
+        assert!(report.is_complete());
+        assert_eq!(report.num_revoked(), 2);
+        assert_eq!(
+            report.revoked_entries(),
+            revocations.revoked_entries(image).collect::<Vec<_>>()
+        );
+
+        // Capacity too small to hold all revoked entries.
+        let report = revocations.validate_image_report_array::<1>(image);
+        assert!(!report.is_allowed());
+        assert!(!report.is_complete());
+        assert_eq!(report.num_revoked(), 2);
+        assert_eq!(report.revoked_entries()[0].entry, make_entry("compA", 1));
+
+        let image = ImageSbat::parse(b"compA,2\ncompB,3").unwrap();
+        let report = revocations.validate_image_report_array::<1>(image);
+        assert!(report.is_allowed());
+        assert!(report.is_complete());
+        assert!(report.revoked_entries().is_empty());
+    }
 }
@@ -0,0 +1,303 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Parsing that matches shim's implementation.
+//!
+//! The main parsers in this crate ([`ImageSbat::parse`] and
+//! [`RevocationSbat::parse`]) are stricter than shim: they only allow a
+//! limited set of characters and require generations to be positive
+//! integers. This module instead reproduces the behavior of `csv.c` and
+//! `sbat.c` in [shim], which is useful for checking exactly what shim
+//! will accept and how it will compare generations.
+//!
+//! Shim's rules, as implemented here:
+//!
+//! * Data ends at the first null byte. Input of zero length is an
+//!   error.
+//! * A leading UTF-8 byte order mark is skipped.
+//! * Both `\r` and `\n` end a line, and empty lines are skipped.
+//! * Fields are split on `,` with no quoting and no whitespace
+//!   trimming. Any other byte is allowed in a field.
+//! * Only the first [`ParseOptions::num_columns`] fields of a row are
+//!   used; the rest are ignored.
+//! * A row with fewer than [`ParseOptions::required_columns`] fields
+//!   is an error, as is a row containing an empty field.
+//! * Generations are parsed like `atoi`: leading decimal digits are
+//!   used and anything after them is ignored, so `+1`, `-1`, and `abc`
+//!   are all zero. The result is truncated to [`u16`] before being
+//!   compared.
+//! * The first row of revocation data is compared against images like
+//!   any other row, but it also holds the revocation date.
+//!
+//! [`ImageSbat::parse`]: crate::ImageSbat::parse
+//! [`RevocationSbat::parse`]: crate::RevocationSbat::parse
+//! [shim]: https://github.com/rhboot/shim
+
+use arrayvec::ArrayVec;
+use core::fmt::{self, Display, Formatter};
+
+/// Maximum number of columns shim stores per row.
+const MAX_COLUMNS: usize = 6;
+
+/// UTF-8 byte order mark.
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Parse `text` as a number in the same way as shim's `atoi`.
+///
+/// Leading ASCII digits are parsed as a decimal number; parsing stops
+/// at the first non-digit. No sign or whitespace is accepted, so text
+/// that doesn't start with a digit is zero. Overflow wraps.
+#[must_use]
+pub fn atoi(text: &[u8]) -> i64 {
+    text.iter()
+        .map_while(|b| char::from(*b).to_digit(10))
+        .fold(0i64, |acc, digit| {
+            acc.wrapping_mul(10).wrapping_add(i64::from(digit))
+        })
+}
+
+/// Options for [`ShimSbat::parse`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseOptions {
+    /// Number of columns stored per row. Extra columns are ignored.
+    /// Values above six are treated as six.
+    pub num_columns: usize,
+
+    /// Minimum number of columns each row must have.
+    pub required_columns: usize,
+}
+
+impl ParseOptions {
+    /// Options used by shim for the `.sbat` section of an image
+    /// (`parse_sbat_section`). All six columns are required.
+    pub const IMAGE: Self = Self {
+        num_columns: 6,
+        required_columns: 6,
+    };
+
+    /// Options used by shim for revocation data such as the `SbatLevel`
+    /// variable (`parse_sbat_var_data`). The third column, used for the
+    /// date, is optional.
+    pub const REVOCATION: Self = Self {
+        num_columns: 3,
+        required_columns: 2,
+    };
+}
+
+/// Error returned by [`ShimSbat::parse`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ShimParseError {
+    /// The input is empty.
+    Empty,
+
+    /// A row has fewer columns than required.
+    TooFewColumns {
+        /// Zero-based index of the row.
+        row: usize,
+    },
+
+    /// A row has an empty column.
+    EmptyColumn {
+        /// Zero-based index of the row.
+        row: usize,
+
+        /// Zero-based index of the column.
+        column: usize,
+    },
+}
+
+impl Display for ShimParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "input is empty"),
+            Self::TooFewColumns { row } => {
+                write!(f, "row {row} does not have enough columns")
+            }
+            Self::EmptyColumn { row, column } => {
+                write!(f, "row {row} has an empty column {column}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for ShimParseError {}
+
+/// Row of SBAT data parsed with shim's rules.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ShimRow<'a> {
+    columns: ArrayVec<&'a [u8], MAX_COLUMNS>,
+}
+
+impl<'a> ShimRow<'a> {
+    /// Get the columns of the row.
+    #[must_use]
+    pub fn columns(&self) -> &[&'a [u8]] {
+        &self.columns
+    }
+
+    /// Component name (the first column).
+    #[must_use]
+    pub fn name(&self) -> &'a [u8] {
+        self.columns.first().copied().unwrap_or_default()
+    }
+
+    /// Raw component generation (the second column).
+    #[must_use]
+    pub fn generation_text(&self) -> &'a [u8] {
+        self.columns.get(1).copied().unwrap_or_default()
+    }
+
+    /// Component generation as compared by shim: the [`atoi`] value of
+    /// the second column, truncated to [`u16`].
+    #[must_use]
+    pub fn generation(&self) -> u16 {
+        let [low, high, ..] = atoi(self.generation_text()).to_le_bytes();
+        u16::from_le_bytes([low, high])
+    }
+}
+
+/// Iterator over the rows of [`ShimSbat`].
+///
+/// See [`ShimSbat::rows`].
+pub struct ShimRows<'a> {
+    lines: core::slice::Split<'a, u8, fn(&u8) -> bool>,
+    num_columns: usize,
+}
+
+impl<'a> Iterator for ShimRows<'a> {
+    type Item = ShimRow<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.lines.find(|line| !line.is_empty())?;
+        let columns = line
+            .split(|b| *b == b',')
+            .take(self.num_columns.min(MAX_COLUMNS))
+            .collect();
+        Some(ShimRow { columns })
+    }
+}
+
+/// SBAT data parsed with shim's rules.
+///
+/// See the [module documentation](self) for details of the rules.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ShimSbat<'a> {
+    data: &'a [u8],
+    options: ParseOptions,
+}
+
+impl<'a> ShimSbat<'a> {
+    /// Parse SBAT data in the same way as shim.
+    ///
+    /// Use [`ParseOptions::IMAGE`] for the `.sbat` section of an image,
+    /// and [`ParseOptions::REVOCATION`] for revocation data.
+    pub fn parse(
+        data: &'a [u8],
+        options: ParseOptions,
+    ) -> Result<Self, ShimParseError> {
+        if data.is_empty() {
+            return Err(ShimParseError::Empty);
+        }
+
+        let data = data.split(|b| *b == 0).next().unwrap_or_default();
+        let data = data.strip_prefix(UTF8_BOM).unwrap_or(data);
+        let sbat = Self { data, options };
+
+        for (row_index, row) in sbat.rows().enumerate() {
+            if row.columns.len() < options.required_columns {
+                return Err(ShimParseError::TooFewColumns { row: row_index });
+            }
+            if let Some(column) = row.columns.iter().position(|c| c.is_empty())
+            {
+                return Err(ShimParseError::EmptyColumn {
+                    row: row_index,
+                    column,
+                });
+            }
+        }
+
+        Ok(sbat)
+    }
+
+    /// Get an iterator over the rows.
+    #[must_use]
+    pub fn rows(&self) -> ShimRows<'a> {
+        // Signature must match what `split` passes.
+        #[allow(clippy::trivially_copy_pass_by_ref)]
+        fn is_line_end(b: &u8) -> bool {
+            *b == b'\r' || *b == b'\n'
+        }
+
+        ShimRows {
+            lines: self.data.split(is_line_end as fn(&u8) -> bool),
+            num_columns: self.options.num_columns,
+        }
+    }
+
+    /// Date of revocation data: the third column of the first row, if
+    /// present.
+    #[must_use]
+    pub fn date(&self) -> Option<&'a [u8]> {
+        self.rows().next()?.columns.get(2).copied()
+    }
+
+    /// Check `image` against this revocation data in the same way as
+    /// shim's `verify_sbat`.
+    ///
+    /// Each image row is compared against every revocation row with the
+    /// same name. If the image's [`ShimRow::generation`] is less than
+    /// the revocation's, the image row is returned. If no image row is
+    /// revoked, `None` is returned.
+    #[must_use]
+    pub fn first_revoked<'i>(
+        &self,
+        image: &ShimSbat<'i>,
+    ) -> Option<ShimRow<'i>> {
+        image.rows().find(|image_row| {
+            self.rows().any(|revocation| {
+                image_row.name() == revocation.name()
+                    && image_row.generation() < revocation.generation()
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atoi() {
+        assert_eq!(atoi(b""), 0);
+        assert_eq!(atoi(b"0"), 0);
+        assert_eq!(atoi(b"123"), 123);
+        assert_eq!(atoi(b"0012"), 12);
+        assert_eq!(atoi(b"12abc"), 12);
+        assert_eq!(atoi(b"abc"), 0);
+        assert_eq!(atoi(b"+1"), 0);
+        assert_eq!(atoi(b"-1"), 0);
+        assert_eq!(atoi(b" 1"), 0);
+    }
+
+    #[test]
+    fn test_generation_truncated() {
+        let sbat =
+            ShimSbat::parse(b"a,65537", ParseOptions::REVOCATION).unwrap();
+        assert_eq!(sbat.rows().next().unwrap().generation(), 1);
+    }
+
+    #[test]
+    fn test_error_display() {
+        // Don't bother testing the specific error messages, just ensure
+        // nothing panics.
+        let _ = format!("{}", ShimParseError::Empty);
+        let _ = format!("{}", ShimParseError::TooFewColumns { row: 0 });
+        let _ =
+            format!("{}", ShimParseError::EmptyColumn { row: 0, column: 1 });
+    }
+}
@@ -0,0 +1,396 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Unified Kernel Image (UKI) support.
+//!
+//! A [UKI] is a systemd-stub PE executable with the Linux kernel
+//! embedded in its [`LINUX_SECTION_NAME`] section, along with other
+//! sections such as [`OSREL_SECTION_NAME`], [`UNAME_SECTION_NAME`] and
+//! [`CMDLINE_SECTION_NAME`]. Both the stub and the embedded kernel can
+//! have a [`SBAT_SECTION_NAME`] section.
+//!
+//! When `ukify` builds a UKI, it merges the SBAT data of the stub and
+//! the kernel (plus a default `uki` entry) into the UKI's `.sbat`
+//! section. [`Uki::merged_sbat`] reproduces that merge with
+//! [`ImageSbatOwned::merge`].
+//!
+//! [`ImageSbatOwned::merge`]: crate::ImageSbatOwned::merge
+//!
+//! [`SBAT_SECTION_NAME`]: crate::SBAT_SECTION_NAME
+//! [UKI]: https://uapi-group.org/specifications/specs/unified_kernel_image/
+
+use crate::pe::{PeError, PeFile};
+use crate::{ImageSbat, RevocationSbat, ValidationResult};
+use core::fmt::{self, Display, Formatter};
+
+#[cfg(feature = "alloc")]
+use crate::{
+    BuildError, Generation, GenerationConflict, ImageSbatOwned, MergeError,
+};
+#[cfg(feature = "alloc")]
+use ascii::AsciiString;
+#[cfg(feature = "alloc")]
+use rust_alloc::vec::Vec;
+
+/// Section containing the embedded Linux kernel.
+pub const LINUX_SECTION_NAME: &str = ".linux";
+
+/// Section containing the os-release data of the OS.
+pub const OSREL_SECTION_NAME: &str = ".osrel";
+
+/// Section containing the kernel release (`uname -r`).
+pub const UNAME_SECTION_NAME: &str = ".uname";
+
+/// Section containing the kernel command line.
+pub const CMDLINE_SECTION_NAME: &str = ".cmdline";
+
+/// SBAT header written by `ukify` at the start of the merged data.
+pub const UKIFY_SBAT_HEADER: &str = "sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md\n";
+
+/// SBAT data added by `ukify` for the UKI itself when no `--sbat`
+/// option is given.
+pub const UKIFY_DEFAULT_SBAT: &str = "sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md\nuki,1,UKI,uki,1,https://uapi-group.org/specifications/specs/unified_kernel_image/\n";
+
+/// Error returned by [`Uki`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UkiError {
+    /// The UKI is not a valid PE executable, or its `.sbat` section is
+    /// invalid.
+    Stub(PeError),
+
+    /// The UKI does not have a [`LINUX_SECTION_NAME`] section.
+    MissingKernel,
+
+    /// The embedded kernel is not a valid PE executable, or its
+    /// `.sbat` section is invalid.
+    Kernel(PeError),
+
+    /// The merged SBAT data could not be written.
+    #[cfg(feature = "alloc")]
+    Merge(BuildError),
+
+    /// The same component name has different generations in the
+    /// merged SBAT data, and [`GenerationConflict::Error`] was
+    /// requested.
+    #[cfg(feature = "alloc")]
+    ConflictingGenerations {
+        /// Component name.
+        name: AsciiString,
+
+        /// Generation of the first entry with this name.
+        first: Generation,
+
+        /// Conflicting generation of a later entry.
+        second: Generation,
+    },
+}
+
+impl Display for UkiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stub(err) => write!(f, "stub: {err}"),
+            Self::MissingKernel => {
+                write!(f, "missing {LINUX_SECTION_NAME} section")
+            }
+            Self::Kernel(err) => write!(f, "kernel: {err}"),
+            #[cfg(feature = "alloc")]
+            Self::Merge(err) => write!(f, "failed to merge SBAT: {err}"),
+            #[cfg(feature = "alloc")]
+            Self::ConflictingGenerations {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "failed to merge SBAT: component {name} has conflicting \
+                 generations {first} and {second}"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for UkiError {}
+
+/// Unified Kernel Image.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Uki<'a> {
+    stub: PeFile<'a>,
+    kernel: &'a [u8],
+}
+
+impl<'a> Uki<'a> {
+    /// Parse a UKI in the file layout. The UKI must have a
+    /// [`LINUX_SECTION_NAME`] section; the embedded kernel is not
+    /// parsed until it is used.
+    pub fn parse(data: &'a [u8]) -> Result<Self, UkiError> {
+        let stub = PeFile::parse(data).map_err(UkiError::Stub)?;
+        let kernel = match stub.section_data_by_name(LINUX_SECTION_NAME) {
+            Ok(kernel) => kernel,
+            Err(PeError::MissingSection) => {
+                return Err(UkiError::MissingKernel);
+            }
+            Err(err) => return Err(UkiError::Stub(err)),
+        };
+        Ok(Self { stub, kernel })
+    }
+
+    /// The UKI as a PE executable. This is the systemd-stub with the
+    /// UKI sections added.
+    #[must_use]
+    pub fn stub(&self) -> &PeFile<'a> {
+        &self.stub
+    }
+
+    /// Raw data of the embedded kernel.
+    #[must_use]
+    pub fn kernel_data(&self) -> &'a [u8] {
+        self.kernel
+    }
+
+    /// Parse the embedded kernel as a PE executable.
+    pub fn kernel(&self) -> Result<PeFile<'a>, UkiError> {
+        PeFile::parse(self.kernel).map_err(UkiError::Kernel)
+    }
+
+    /// Get the data of a section of the UKI, such as
+    /// [`OSREL_SECTION_NAME`], with trailing nulls removed.
+    #[must_use]
+    pub fn section_data(&self, name: &str) -> Option<&'a [u8]> {
+        let data = self.stub.section_data_by_name(name).ok()?;
+        let end = data.iter().rposition(|b| *b != 0);
+        Some(end.map_or(&[][..], |end| &data[..=end]))
+    }
+
+    /// Parse the UKI's `.sbat` section.
+    ///
+    /// In a UKI built by `ukify`, this already contains the merged SBAT
+    /// data of the stub and the kernel.
+    pub fn stub_sbat(&self) -> Result<&'a ImageSbat, UkiError> {
+        self.stub.image_sbat().map_err(UkiError::Stub)
+    }
+
+    /// Parse the embedded kernel's `.sbat` section. Returns `None` if
+    /// the kernel does not have one.
+    pub fn kernel_sbat(&self) -> Result<Option<&'a ImageSbat>, UkiError> {
+        match self.kernel()?.image_sbat() {
+            Ok(image_sbat) => Ok(Some(image_sbat)),
+            Err(PeError::MissingSection) => Ok(None),
+            Err(err) => Err(UkiError::Kernel(err)),
+        }
+    }
+
+    /// Merge the SBAT data of the stub, the kernel (if it has any) and
+    /// `extra` the same way `ukify` does. Pass [`UKIFY_DEFAULT_SBAT`]
+    /// as `extra` to get what `ukify` produces without an `--sbat`
+    /// option.
+    ///
+    /// The data is merged with [`ImageSbatOwned::merge`], starting
+    /// with [`UKIFY_SBAT_HEADER`] so that it replaces the `sbat` entries
+    /// of the inputs. Unlike `ukify`, entries with the same component
+    /// name and generation as an earlier entry are skipped, so merging
+    /// a UKI whose `.sbat` section was already merged gives the same
+    /// result. `ukify` keeps every entry, so a component name with
+    /// different generations is handled according to `on_conflict`.
+    #[cfg(feature = "alloc")]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn merged_sbat(
+        &self,
+        extra: Option<&ImageSbat>,
+        on_conflict: GenerationConflict,
+    ) -> Result<ImageSbatOwned, UkiError> {
+        // OK to unwrap: the header is valid.
+        let header = ImageSbat::parse(UKIFY_SBAT_HEADER.as_bytes()).unwrap();
+        let kernel = self.kernel_sbat()?;
+        let sources = [Some(header), Some(self.stub_sbat()?), kernel, extra];
+        let images: Vec<_> = sources.into_iter().flatten().collect();
+
+        ImageSbatOwned::merge(&images, on_conflict).map_err(|err| match err {
+            MergeError::ConflictingGenerations {
+                name,
+                first,
+                second,
+            } => UkiError::ConflictingGenerations {
+                name: name.to_owned(),
+                first,
+                second,
+            },
+            MergeError::Build(err) => UkiError::Merge(err),
+        })
+    }
+
+    /// Validate the stub's and the kernel's SBAT data against
+    /// `revocations`. The stub is checked first; if neither is
+    /// revoked, the UKI is allowed.
+    pub fn validate(
+        &self,
+        revocations: &RevocationSbat,
+    ) -> Result<ValidationResult<'a>, UkiError> {
+        let result = revocations.validate_image(self.stub_sbat()?);
+        if result != ValidationResult::Allowed {
+            return Ok(result);
+        }
+        match self.kernel_sbat()? {
+            Some(kernel) => Ok(revocations.validate_image(kernel)),
+            None => Ok(ValidationResult::Allowed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pe::tests::{TestSection, make_pe};
+    #[cfg(feature = "alloc")]
+    use ascii::IntoAsciiString;
+
+    const STUB_SBAT: &[u8] = b"sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md\nsystemd-stub,1,The systemd Developers,systemd,256,https://systemd.io/\n";
+    const KERNEL_SBAT: &[u8] =
+        b"sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md\nlinux,1,The Linux Developers,linux,6.10,https://linux.org\n";
+
+    fn make_uki(stub_sbat: &[u8], kernel: &[u8]) -> Vec<u8> {
+        make_pe(
+            &[
+                TestSection::new(b".sbat", stub_sbat),
+                TestSection::new(b".osrel", b"ID=test\n\0\0"),
+                TestSection::new(b".linux", kernel),
+            ],
+            true,
+        )
+    }
+
+    fn make_kernel(sbat: Option<&[u8]>) -> Vec<u8> {
+        match sbat {
+            Some(sbat) => make_pe(&[TestSection::new(b".sbat", sbat)], true),
+            None => make_pe(&[TestSection::new(b".text", &[0xc3])], true),
+        }
+    }
+
+    #[test]
+    fn test_uki() {
+        let kernel = make_kernel(Some(KERNEL_SBAT));
+        let data = make_uki(STUB_SBAT, &kernel);
+        let uki = Uki::parse(&data).unwrap();
+        assert_eq!(uki.kernel_data(), kernel);
+        assert_eq!(uki.section_data(OSREL_SECTION_NAME).unwrap(), b"ID=test\n");
+        assert!(uki.section_data(CMDLINE_SECTION_NAME).is_none());
+        assert_eq!(
+            uki.stub_sbat().unwrap(),
+            ImageSbat::parse(STUB_SBAT).unwrap()
+        );
+        assert_eq!(
+            uki.kernel_sbat().unwrap().unwrap(),
+            ImageSbat::parse(KERNEL_SBAT).unwrap()
+        );
+
+        // Kernel without SBAT.
+        let kernel = make_kernel(None);
+        let data = make_uki(STUB_SBAT, &kernel);
+        assert_eq!(Uki::parse(&data).unwrap().kernel_sbat(), Ok(None));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(
+            Uki::parse(b"MZ"),
+            Err(UkiError::Stub(PeError::InvalidDosHeader))
+        );
+
+        let data = make_pe(&[TestSection::new(b".sbat", STUB_SBAT)], true);
+        assert_eq!(Uki::parse(&data), Err(UkiError::MissingKernel));
+
+        let data = make_uki(STUB_SBAT, b"not a PE");
+        let uki = Uki::parse(&data).unwrap();
+        assert_eq!(
+            uki.kernel_sbat(),
+            Err(UkiError::Kernel(PeError::InvalidDosHeader))
+        );
+        assert!(uki.stub_sbat().is_ok());
+    }
+
+    #[test]
+    fn test_validate() {
+        let kernel = make_kernel(Some(KERNEL_SBAT));
+        let data = make_uki(STUB_SBAT, &kernel);
+        let uki = Uki::parse(&data).unwrap();
+
+        let revocations = RevocationSbat::parse(b"sbat,1\n").unwrap();
+        assert_eq!(uki.validate(revocations), Ok(ValidationResult::Allowed));
+
+        let revocations = RevocationSbat::parse(b"sbat,1\nlinux,2\n").unwrap();
+        let ValidationResult::Revoked(entry) =
+            uki.validate(revocations).unwrap()
+        else {
+            panic!("not revoked");
+        };
+        assert_eq!(entry.component.name, "linux");
+
+        let revocations =
+            RevocationSbat::parse(b"sbat,1\nsystemd-stub,2\nlinux,2\n")
+                .unwrap();
+        let ValidationResult::Revoked(entry) =
+            uki.validate(revocations).unwrap()
+        else {
+            panic!("not revoked");
+        };
+        assert_eq!(entry.component.name, "systemd-stub");
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_merged_sbat() {
+        let kernel = make_kernel(Some(KERNEL_SBAT));
+        let data = make_uki(STUB_SBAT, &kernel);
+        let uki = Uki::parse(&data).unwrap();
+
+        let extra = ImageSbat::parse(UKIFY_DEFAULT_SBAT.as_bytes()).unwrap();
+        let merged = uki
+            .merged_sbat(Some(extra), GenerationConflict::Error)
+            .unwrap();
+        let expected = "sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md
+systemd-stub,1,The systemd Developers,systemd,256,https://systemd.io/
+linux,1,The Linux Developers,linux,6.10,https://linux.org
+uki,1,UKI,uki,1,https://uapi-group.org/specifications/specs/unified_kernel_image/
+";
+        assert_eq!(merged.as_csv(), expected);
+
+        // Merging again with the merged data as the stub's SBAT gives
+        // the same result.
+        let data = make_uki(expected.as_bytes(), &kernel);
+        let uki = Uki::parse(&data).unwrap();
+        assert_eq!(
+            uki.merged_sbat(Some(extra), GenerationConflict::Error),
+            Ok(merged)
+        );
+
+        // A component with different generations in the stub and the
+        // extra data.
+        let extra =
+            ImageSbat::parse(b"sbat,1\nsystemd-stub,2,S,s,1,u\n").unwrap();
+        assert_eq!(
+            uki.merged_sbat(Some(extra), GenerationConflict::Error),
+            Err(UkiError::ConflictingGenerations {
+                name: "systemd-stub".into_ascii_string().unwrap(),
+                first: Generation::new(1).unwrap(),
+                second: Generation::new(2).unwrap(),
+            })
+        );
+        let merged = uki
+            .merged_sbat(Some(extra), GenerationConflict::Max)
+            .unwrap();
+        assert!(merged.as_csv().as_str().contains("\nsystemd-stub,2,S,"));
+
+        // Kernel without SBAT, and no extra data.
+        let kernel = make_kernel(None);
+        let data = make_uki(STUB_SBAT, &kernel);
+        let uki = Uki::parse(&data).unwrap();
+        assert_eq!(
+            uki.merged_sbat(None, GenerationConflict::Error).unwrap(),
+            ImageSbatOwned::parse(STUB_SBAT).unwrap()
+        );
+    }
+}
@@ -0,0 +1,810 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Access to shim's UEFI variables.
+//!
+//! Shim stores the current revocation data in the `SbatLevel` variable,
+//! which is only accessible at boot time, and mirrors it to the
+//! runtime-accessible `SbatLevelRT` variable so that the OS can read
+//! it. The `SbatPolicy` variable (typically set with `mokutil
+//! --set-sbat-policy`) asks shim to update `SbatLevel` on the next
+//! boot. All three variables use shim's vendor GUID,
+//! [`SHIM_LOCK_GUID`].
+//!
+//! The [`VariableStore`] trait provides typed access to these
+//! variables on top of a few raw variable operations. Two
+//! implementations are provided: [`Efivarfs`] (with the `std` feature)
+//! reads and writes the files of a Linux efivarfs mount, and
+//! [`MemoryVariableStore`] keeps variables in memory, which is useful
+//! for tests.
+//!
+//! [`sbat_level_update`] predicts what shim will do to `SbatLevel` on
+//! the next boot, given the revocation data embedded in it and the
+//! current variables.
+
+use crate::shim::atoi;
+use crate::{
+    ParseError, RevocationSbat, RevocationSbatOwned, RevocationSection,
+};
+use core::fmt::{self, Debug, Display, Formatter};
+use rust_alloc::collections::BTreeMap;
+use rust_alloc::string::{String, ToString};
+use rust_alloc::vec::Vec;
+
+/// Vendor GUID of shim's variables.
+pub const SHIM_LOCK_GUID: &str = "605dab50-e046-4300-abb6-3dd810dd8b23";
+
+/// Variable attribute: the variable is stored in non-volatile memory.
+pub const EFI_VARIABLE_NON_VOLATILE: u32 = 0x1;
+
+/// Variable attribute: the variable is accessible at boot time.
+pub const EFI_VARIABLE_BOOTSERVICE_ACCESS: u32 = 0x2;
+
+/// Variable attribute: the variable is accessible at runtime (and so
+/// visible in efivarfs).
+pub const EFI_VARIABLE_RUNTIME_ACCESS: u32 = 0x4;
+
+/// Variable attribute: writes must be signed with a time-based
+/// authentication descriptor.
+pub const EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS: u32 = 0x20;
+
+/// Attributes shim uses for `SbatLevel`.
+pub const SBAT_LEVEL_ATTRIBUTES: u32 =
+    EFI_VARIABLE_NON_VOLATILE | EFI_VARIABLE_BOOTSERVICE_ACCESS;
+
+/// Attributes `mokutil` uses for `SbatPolicy`. The variable must be
+/// writable at runtime so that it can be set from the OS.
+pub const SBAT_POLICY_ATTRIBUTES: u32 = EFI_VARIABLE_NON_VOLATILE
+    | EFI_VARIABLE_BOOTSERVICE_ACCESS
+    | EFI_VARIABLE_RUNTIME_ACCESS;
+
+/// Attributes shim uses for `SbatLevelRT`.
+pub const SBAT_LEVEL_RT_ATTRIBUTES: u32 =
+    EFI_VARIABLE_BOOTSERVICE_ACCESS | EFI_VARIABLE_RUNTIME_ACCESS;
+
+/// Name and vendor GUID of a UEFI variable.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct VariableName<'a> {
+    /// Variable name.
+    pub name: &'a str,
+
+    /// Vendor GUID, in the lowercase form used by efivarfs.
+    pub vendor: &'a str,
+}
+
+impl<'a> VariableName<'a> {
+    /// Shim's boot-time revocation data.
+    pub const SBAT_LEVEL: Self = Self::new("SbatLevel", SHIM_LOCK_GUID);
+
+    /// Runtime copy of [`SBAT_LEVEL`](Self::SBAT_LEVEL), created by
+    /// shim on each boot.
+    pub const SBAT_LEVEL_RT: Self = Self::new("SbatLevelRT", SHIM_LOCK_GUID);
+
+    /// Requested update of `SbatLevel`. See [`SbatPolicy`].
+    pub const SBAT_POLICY: Self = Self::new("SbatPolicy", SHIM_LOCK_GUID);
+
+    /// Create a `VariableName`.
+    #[must_use]
+    pub const fn new(name: &'a str, vendor: &'a str) -> Self {
+        Self { name, vendor }
+    }
+}
+
+impl Display for VariableName<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.name, self.vendor)
+    }
+}
+
+/// Contents of a UEFI variable.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Variable {
+    /// Variable attributes, such as [`EFI_VARIABLE_NON_VOLATILE`].
+    pub attributes: u32,
+
+    /// Variable data.
+    pub data: Vec<u8>,
+}
+
+impl Variable {
+    /// Create a `Variable`.
+    #[must_use]
+    pub fn new(attributes: u32, data: Vec<u8>) -> Self {
+        Self { attributes, data }
+    }
+}
+
+/// Value of the `SbatPolicy` variable, which tells shim how to update
+/// `SbatLevel` on the next boot. Shim deletes the variable once it has
+/// acted on it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SbatPolicy {
+    /// Apply shim's latest revocations.
+    Latest,
+
+    /// Apply shim's previous revocations, as is done when the variable
+    /// is not set. `mokutil` calls this `previous`.
+    Automatic,
+
+    /// Reset `SbatLevel` to the original minimal revocations. Shim only
+    /// does this when Secure Boot is disabled. `mokutil` calls this
+    /// `delete`.
+    Reset,
+
+    /// A value shim does not recognize. Shim treats this the same as
+    /// the variable not being set.
+    Unknown(u8),
+}
+
+impl SbatPolicy {
+    /// Convert from the single byte stored in the variable.
+    #[must_use]
+    pub fn from_byte(byte: u8) -> Self {
+        match byte {
+            1 => Self::Latest,
+            2 => Self::Automatic,
+            3 => Self::Reset,
+            _ => Self::Unknown(byte),
+        }
+    }
+
+    /// Convert to the single byte stored in the variable.
+    #[must_use]
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::Latest => 1,
+            Self::Automatic => 2,
+            Self::Reset => 3,
+            Self::Unknown(byte) => byte,
+        }
+    }
+}
+
+/// Error returned by the typed accessors of [`VariableStore`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum VariableError<E> {
+    /// The underlying store failed.
+    Store(E),
+
+    /// The variable does not contain valid revocation data.
+    InvalidRevocations(ParseError),
+
+    /// The `SbatPolicy` variable is not exactly one byte long. The
+    /// actual length is included.
+    InvalidPolicySize(usize),
+}
+
+impl<E: Display> Display for VariableError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Store(err) => write!(f, "{err}"),
+            Self::InvalidRevocations(err) => {
+                write!(f, "invalid revocation data: {err}")
+            }
+            Self::InvalidPolicySize(size) => {
+                write!(f, "invalid SbatPolicy size: {size}")
+            }
+        }
+    }
+}
+
+impl<E: Debug + Display> core::error::Error for VariableError<E> {}
+
+/// Storage of UEFI variables.
+///
+/// Implementations provide the raw [`get_variable`],
+/// [`set_variable`] and [`delete_variable`] operations; the other
+/// methods read and write shim's variables on top of them.
+///
+/// [`get_variable`]: Self::get_variable
+/// [`set_variable`]: Self::set_variable
+/// [`delete_variable`]: Self::delete_variable
+pub trait VariableStore {
+    /// Error returned by the raw operations.
+    type Error;
+
+    /// Read a variable. Returns `None` if it does not exist.
+    fn get_variable(
+        &self,
+        name: VariableName,
+    ) -> Result<Option<Variable>, Self::Error>;
+
+    /// Create or replace a variable.
+    fn set_variable(
+        &mut self,
+        name: VariableName,
+        variable: &Variable,
+    ) -> Result<(), Self::Error>;
+
+    /// Delete a variable. Deleting a variable that does not exist is
+    /// not an error.
+    fn delete_variable(
+        &mut self,
+        name: VariableName,
+    ) -> Result<(), Self::Error>;
+
+    /// Read revocation data from the variable `name`. Returns `None` if
+    /// the variable does not exist.
+    fn revocations(
+        &self,
+        name: VariableName,
+    ) -> Result<Option<RevocationSbatOwned>, VariableError<Self::Error>> {
+        let Some(variable) =
+            self.get_variable(name).map_err(VariableError::Store)?
+        else {
+            return Ok(None);
+        };
+        RevocationSbatOwned::parse(&variable.data)
+            .map(Some)
+            .map_err(VariableError::InvalidRevocations)
+    }
+
+    /// Read the `SbatLevel` variable. This is only accessible at boot
+    /// time, so it is not visible in efivarfs.
+    fn sbat_level(
+        &self,
+    ) -> Result<Option<RevocationSbatOwned>, VariableError<Self::Error>> {
+        self.revocations(VariableName::SBAT_LEVEL)
+    }
+
+    /// Read the `SbatLevelRT` variable.
+    fn sbat_level_rt(
+        &self,
+    ) -> Result<Option<RevocationSbatOwned>, VariableError<Self::Error>> {
+        self.revocations(VariableName::SBAT_LEVEL_RT)
+    }
+
+    /// Write `revocations` to the `SbatLevel` variable, with the
+    /// attributes shim uses.
+    fn set_sbat_level(
+        &mut self,
+        revocations: &RevocationSbat,
+    ) -> Result<(), Self::Error> {
+        let data = revocations.as_csv().as_bytes().to_vec();
+        self.set_variable(
+            VariableName::SBAT_LEVEL,
+            &Variable::new(SBAT_LEVEL_ATTRIBUTES, data),
+        )
+    }
+
+    /// Write `revocations` to the `SbatLevelRT` variable, with the
+    /// attributes shim uses.
+    fn set_sbat_level_rt(
+        &mut self,
+        revocations: &RevocationSbat,
+    ) -> Result<(), Self::Error> {
+        let data = revocations.as_csv().as_bytes().to_vec();
+        self.set_variable(
+            VariableName::SBAT_LEVEL_RT,
+            &Variable::new(SBAT_LEVEL_RT_ATTRIBUTES, data),
+        )
+    }
+
+    /// Read the `SbatPolicy` variable. Returns `None` if it does not
+    /// exist.
+    fn sbat_policy(
+        &self,
+    ) -> Result<Option<SbatPolicy>, VariableError<Self::Error>> {
+        let Some(variable) = self
+            .get_variable(VariableName::SBAT_POLICY)
+            .map_err(VariableError::Store)?
+        else {
+            return Ok(None);
+        };
+        match variable.data[..] {
+            [byte] => Ok(Some(SbatPolicy::from_byte(byte))),
+            _ => Err(VariableError::InvalidPolicySize(variable.data.len())),
+        }
+    }
+
+    /// Write the `SbatPolicy` variable, as `mokutil --set-sbat-policy`
+    /// does.
+    fn set_sbat_policy(
+        &mut self,
+        policy: SbatPolicy,
+    ) -> Result<(), Self::Error> {
+        self.set_variable(
+            VariableName::SBAT_POLICY,
+            &Variable::new(SBAT_POLICY_ATTRIBUTES, [policy.to_byte()].to_vec()),
+        )
+    }
+
+    /// Delete the `SbatPolicy` variable.
+    fn delete_sbat_policy(&mut self) -> Result<(), Self::Error> {
+        self.delete_variable(VariableName::SBAT_POLICY)
+    }
+}
+
+/// In-memory [`VariableStore`].
+///
+/// Operations never fail. This is intended for tests, and for
+/// simulating what shim does to a set of variables.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MemoryVariableStore {
+    variables: BTreeMap<(String, String), Variable>,
+}
+
+impl MemoryVariableStore {
+    /// Create an empty `MemoryVariableStore`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get an iterator over all variables, sorted by name and vendor.
+    pub fn variables(
+        &self,
+    ) -> impl Iterator<Item = (VariableName<'_>, &Variable)> {
+        self.variables.iter().map(|((name, vendor), variable)| {
+            (VariableName::new(name, vendor), variable)
+        })
+    }
+
+    fn key(name: VariableName) -> (String, String) {
+        (name.name.to_string(), name.vendor.to_string())
+    }
+}
+
+impl VariableStore for MemoryVariableStore {
+    type Error = core::convert::Infallible;
+
+    fn get_variable(
+        &self,
+        name: VariableName,
+    ) -> Result<Option<Variable>, Self::Error> {
+        Ok(self.variables.get(&Self::key(name)).cloned())
+    }
+
+    fn set_variable(
+        &mut self,
+        name: VariableName,
+        variable: &Variable,
+    ) -> Result<(), Self::Error> {
+        self.variables.insert(Self::key(name), variable.clone());
+        Ok(())
+    }
+
+    fn delete_variable(
+        &mut self,
+        name: VariableName,
+    ) -> Result<(), Self::Error> {
+        self.variables.remove(&Self::key(name));
+        Ok(())
+    }
+}
+
+/// Revocation data shim writes to `SbatLevel` when resetting it.
+pub const SBAT_LEVEL_ORIGINAL: &[u8] = b"sbat,1,2021030218\n";
+
+/// What shim does to the `SbatLevel` variable at boot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SbatLevelAction {
+    /// The existing variable is left unchanged.
+    Keep,
+
+    /// The variable is created, or replaced, with revocation data
+    /// embedded in shim.
+    Update,
+
+    /// The variable is replaced with [`SBAT_LEVEL_ORIGINAL`].
+    Reset,
+}
+
+/// Outcome of [`sbat_level_update`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SbatLevelUpdate<'a> {
+    /// What shim does to `SbatLevel`.
+    pub action: SbatLevelAction,
+
+    /// Contents of `SbatLevel` after the update. Shim also copies this
+    /// to `SbatLevelRT`.
+    pub sbat_level: &'a [u8],
+
+    /// Whether shim deletes the `SbatPolicy` variable.
+    pub delete_policy: bool,
+}
+
+/// Decide how shim updates the `SbatLevel` variable at boot.
+///
+/// * `section` is the revocation data embedded in shim (its
+///   `.sbatlevel` section).
+/// * `sbat_level` is the current `SbatLevel` variable, if it exists.
+/// * `policy` is the value of the `SbatPolicy` variable, if it exists.
+/// * `secure_boot` is whether Secure Boot is enabled.
+///
+/// This follows `set_sbat_uefi_variable` in shim 15.8. The candidate
+/// revocations are chosen from `policy`:
+///
+/// * [`SbatPolicy::Latest`]: the latest revocations.
+/// * [`SbatPolicy::Automatic`]: the previous revocations.
+/// * [`SbatPolicy::Reset`], unknown values, or no policy: the previous
+///   revocations if Secure Boot is enabled, otherwise `SbatLevel` is
+///   reset to [`SBAT_LEVEL_ORIGINAL`].
+///
+/// If `SbatPolicy` is set, shim deletes it regardless of its value.
+///
+/// Unless resetting, the existing variable is kept if it has shim's
+/// attributes, starts with an `sbat` row at least as long as the
+/// original revocations, and its version and date are not older than
+/// the candidate's. Versions are compared as numbers and dates as
+/// strings. Otherwise the variable is replaced with the candidate.
+#[must_use]
+pub fn sbat_level_update<'a>(
+    section: &RevocationSection<'a>,
+    sbat_level: Option<&'a Variable>,
+    policy: Option<SbatPolicy>,
+    secure_boot: bool,
+) -> SbatLevelUpdate<'a> {
+    let delete_policy = policy.is_some();
+    let candidate = match policy {
+        Some(SbatPolicy::Latest) => Some(section.latest()),
+        Some(SbatPolicy::Automatic) => Some(section.previous()),
+        Some(SbatPolicy::Reset | SbatPolicy::Unknown(_)) | None => {
+            secure_boot.then(|| section.previous())
+        }
+    };
+
+    let (action, data) = match (candidate, sbat_level) {
+        (None, _) => (SbatLevelAction::Reset, SBAT_LEVEL_ORIGINAL),
+        (Some(candidate), Some(current))
+            if is_preserved(current, candidate) =>
+        {
+            (SbatLevelAction::Keep, current.data.as_slice())
+        }
+        (Some(candidate), _) => (SbatLevelAction::Update, candidate),
+    };
+
+    SbatLevelUpdate {
+        action,
+        sbat_level: data,
+        delete_policy,
+    }
+}
+
+/// Check if shim keeps the `current` `SbatLevel` variable rather than
+/// replacing it with `candidate`. Like shim's
+/// `check_sbat_var_attributes`, the variable may also be time-based
+/// authenticated.
+fn is_preserved(current: &Variable, candidate: &[u8]) -> bool {
+    let authenticated = SBAT_LEVEL_ATTRIBUTES
+        | EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS;
+    (current.attributes == SBAT_LEVEL_ATTRIBUTES
+        || current.attributes == authenticated)
+        && current.data.len() >= SBAT_LEVEL_ORIGINAL.len()
+        && current.data.starts_with(b"sbat,")
+        && version_and_date(&current.data) >= version_and_date(candidate)
+}
+
+/// Get the version and date from the `sbat` row at the start of
+/// revocation data. Missing fields are treated as zero or empty.
+fn version_and_date(revocations: &[u8]) -> (i64, &[u8]) {
+    let line = revocations
+        .split(|b| matches!(b, b'\r' | b'\n' | 0))
+        .next()
+        .unwrap_or_default();
+    let mut fields = line.split(|b| *b == b',').skip(1);
+    let version = atoi(fields.next().unwrap_or_default());
+    let date = fields.next().unwrap_or_default();
+    (version, date)
+}
+
+#[cfg(feature = "std")]
+pub use efivarfs::Efivarfs;
+
+#[cfg(feature = "std")]
+mod efivarfs {
+    use super::{Variable, VariableName, VariableStore};
+    use std::fs;
+    use std::io::{self, ErrorKind};
+    use std::path::{Path, PathBuf};
+
+    /// Size of the attributes at the start of each file.
+    const ATTRIBUTES_SIZE: usize = 4;
+
+    /// [`VariableStore`] backed by a Linux efivarfs mount.
+    ///
+    /// Each variable is a file named `<name>-<vendor GUID>` in the root
+    /// directory. The file contains the variable's attributes as a
+    /// little-endian [`u32`], followed by the data.
+    ///
+    /// Note that the kernel marks most efivarfs files as immutable, so
+    /// writing or deleting an existing variable may fail with a
+    /// permission error until the flag is removed (for example with
+    /// `chattr -i`).
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct Efivarfs {
+        root: PathBuf,
+    }
+
+    impl Efivarfs {
+        /// Standard mount point of efivarfs.
+        pub const DEFAULT_ROOT: &'static str = "/sys/firmware/efi/efivars";
+
+        /// Create an `Efivarfs` using the files in `root`. This is
+        /// normally [`DEFAULT_ROOT`](Self::DEFAULT_ROOT), but can be
+        /// any directory, for example in tests.
+        pub fn new(root: impl Into<PathBuf>) -> Self {
+            Self { root: root.into() }
+        }
+
+        /// Root directory.
+        #[must_use]
+        pub fn root(&self) -> &Path {
+            &self.root
+        }
+
+        /// Path of the file for the variable `name`.
+        #[must_use]
+        pub fn path(&self, name: VariableName) -> PathBuf {
+            self.root.join(name.to_string())
+        }
+    }
+
+    impl Default for Efivarfs {
+        fn default() -> Self {
+            Self::new(Self::DEFAULT_ROOT)
+        }
+    }
+
+    impl VariableStore for Efivarfs {
+        type Error = io::Error;
+
+        fn get_variable(
+            &self,
+            name: VariableName,
+        ) -> Result<Option<Variable>, Self::Error> {
+            let mut data = match fs::read(self.path(name)) {
+                Ok(data) => data,
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
+            };
+            let Some(attributes) = data.get(..ATTRIBUTES_SIZE) else {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "variable is missing its attributes",
+                ));
+            };
+            // OK to unwrap: the slice has the right length.
+            let attributes = u32::from_le_bytes(attributes.try_into().unwrap());
+            data.drain(..ATTRIBUTES_SIZE);
+            Ok(Some(Variable::new(attributes, data)))
+        }
+
+        fn set_variable(
+            &mut self,
+            name: VariableName,
+            variable: &Variable,
+        ) -> Result<(), Self::Error> {
+            // efivarfs requires the attributes and data to be written
+            // in a single write.
+            let mut data = variable.attributes.to_le_bytes().to_vec();
+            data.extend(&variable.data);
+            fs::write(self.path(name), data)
+        }
+
+        fn delete_variable(
+            &mut self,
+            name: VariableName,
+        ) -> Result<(), Self::Error> {
+            match fs::remove_file(self.path(name)) {
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+                result => result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REVOCATIONS: &[u8] = b"sbat,1,2023012900\nshim,2\ngrub,3\n";
+
+    /// Exercise the typed accessors of `store`, which starts empty.
+    fn check_store<S: VariableStore>(store: &mut S)
+    where
+        S::Error: Debug,
+    {
+        assert_eq!(store.sbat_level().unwrap(), None);
+        assert_eq!(store.sbat_level_rt().unwrap(), None);
+        assert_eq!(store.sbat_policy().unwrap(), None);
+
+        let revocations = RevocationSbat::parse(REVOCATIONS).unwrap();
+        store.set_sbat_level(revocations).unwrap();
+        store.set_sbat_level_rt(revocations).unwrap();
+        assert_eq!(store.sbat_level().unwrap().unwrap(), revocations);
+        assert_eq!(store.sbat_level_rt().unwrap().unwrap(), revocations);
+        let variable = store
+            .get_variable(VariableName::SBAT_LEVEL_RT)
+            .unwrap()
+            .unwrap();
+        assert_eq!(variable.attributes, SBAT_LEVEL_RT_ATTRIBUTES);
+        assert_eq!(variable.data, REVOCATIONS);
+
+        for policy in [
+            SbatPolicy::Latest,
+            SbatPolicy::Automatic,
+            SbatPolicy::Reset,
+            SbatPolicy::Unknown(0xff),
+        ] {
+            store.set_sbat_policy(policy).unwrap();
+            assert_eq!(store.sbat_policy().unwrap(), Some(policy));
+        }
+        store.delete_sbat_policy().unwrap();
+        assert_eq!(store.sbat_policy().unwrap(), None);
+        // Deleting again is not an error.
+        store.delete_sbat_policy().unwrap();
+
+        let name = VariableName::SBAT_POLICY;
+        store
+            .set_variable(name, &Variable::new(7, vec![1, 2]))
+            .unwrap();
+        assert!(matches!(
+            store.sbat_policy(),
+            Err(VariableError::InvalidPolicySize(2))
+        ));
+
+        let name = VariableName::SBAT_LEVEL;
+        store
+            .set_variable(name, &Variable::new(7, b"sbat,x".to_vec()))
+            .unwrap();
+        assert!(matches!(
+            store.sbat_level(),
+            Err(VariableError::InvalidRevocations(_))
+        ));
+    }
+
+    #[test]
+    fn test_memory_variable_store() {
+        let mut store = MemoryVariableStore::new();
+        check_store(&mut store);
+
+        let names: Vec<_> = store
+            .variables()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "SbatLevel-605dab50-e046-4300-abb6-3dd810dd8b23",
+                "SbatLevelRT-605dab50-e046-4300-abb6-3dd810dd8b23",
+                "SbatPolicy-605dab50-e046-4300-abb6-3dd810dd8b23",
+            ]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_efivarfs() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut store = Efivarfs::new(dir.path());
+        assert_eq!(store.root(), dir.path());
+        check_store(&mut store);
+
+        // The file has the attributes followed by the data.
+        let path = dir
+            .path()
+            .join("SbatLevelRT-605dab50-e046-4300-abb6-3dd810dd8b23");
+        assert_eq!(store.path(VariableName::SBAT_LEVEL_RT), path);
+        let mut expected = vec![6, 0, 0, 0];
+        expected.extend(REVOCATIONS);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+
+        // SbatPolicy is written with runtime access, as mokutil does.
+        store.set_sbat_policy(SbatPolicy::Latest).unwrap();
+        let policy_path = store.path(VariableName::SBAT_POLICY);
+        assert_eq!(std::fs::read(policy_path).unwrap(), [7, 0, 0, 0, 1]);
+
+        // A file too short to have the attributes.
+        std::fs::write(&path, [6, 0]).unwrap();
+        let err = store.sbat_level_rt().unwrap_err();
+        let VariableError::Store(err) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        assert_eq!(
+            Efivarfs::default().root(),
+            std::path::Path::new("/sys/firmware/efi/efivars")
+        );
+    }
+
+    #[test]
+    fn test_sbat_level_update() {
+        use SbatLevelAction::*;
+
+        const PREVIOUS: &[u8] = b"sbat,1,2023012900\nshim,2\ngrub,3\n";
+        const LATEST: &[u8] = b"sbat,1,2024010900\nshim,4\ngrub,4\n";
+        let previous = RevocationSbat::parse(PREVIOUS).unwrap();
+        let latest = RevocationSbat::parse(LATEST).unwrap();
+        let section = RevocationSection::new(previous, latest);
+
+        let var =
+            |data: &[u8]| Variable::new(SBAT_LEVEL_ATTRIBUTES, data.into());
+        let older = var(b"sbat,1,2022052400\ngrub,2\n");
+        let same = var(PREVIOUS);
+        let between = var(b"sbat,1,2023100300\nshim,3\n");
+        let newer = var(b"sbat,1,2025010100\nshim,5\n");
+        let newer_version = var(b"sbat,2,2000010100\n");
+        let wrong_attributes = Variable::new(
+            SBAT_LEVEL_ATTRIBUTES | EFI_VARIABLE_RUNTIME_ACCESS,
+            newer.data.clone(),
+        );
+        let authenticated = Variable::new(
+            SBAT_LEVEL_ATTRIBUTES
+                | EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS,
+            newer.data.clone(),
+        );
+        let too_short = var(b"sbat,1,2025\n");
+        let not_sbat = var(b"shim,1,2025010100\n");
+
+        let check = |current: Option<&Variable>,
+                     policy: Option<SbatPolicy>,
+                     secure_boot: bool,
+                     expected: (SbatLevelAction, &[u8])| {
+            let update =
+                sbat_level_update(&section, current, policy, secure_boot);
+            assert_eq!(
+                (update.action, update.sbat_level),
+                expected,
+                "{current:?} {policy:?} {secure_boot}"
+            );
+            assert_eq!(update.delete_policy, policy.is_some());
+        };
+
+        // No policy.
+        check(None, None, true, (Update, PREVIOUS));
+        check(Some(&older), None, true, (Update, PREVIOUS));
+        check(Some(&same), None, true, (Keep, PREVIOUS));
+        check(Some(&between), None, true, (Keep, &between.data));
+        check(
+            Some(&newer_version),
+            None,
+            true,
+            (Keep, &newer_version.data),
+        );
+        check(Some(&wrong_attributes), None, true, (Update, PREVIOUS));
+        check(Some(&authenticated), None, true, (Keep, &newer.data));
+        check(Some(&too_short), None, true, (Update, PREVIOUS));
+        check(Some(&not_sbat), None, true, (Update, PREVIOUS));
+        check(Some(&newer), None, false, (Reset, SBAT_LEVEL_ORIGINAL));
+        check(None, None, false, (Reset, SBAT_LEVEL_ORIGINAL));
+
+        // Latest.
+        let policy = Some(SbatPolicy::Latest);
+        check(None, policy, true, (Update, LATEST));
+        check(Some(&between), policy, true, (Update, LATEST));
+        check(Some(&newer), policy, true, (Keep, &newer.data));
+        check(Some(&older), policy, false, (Update, LATEST));
+
+        // Automatic.
+        let policy = Some(SbatPolicy::Automatic);
+        check(Some(&older), policy, true, (Update, PREVIOUS));
+        check(Some(&between), policy, false, (Keep, &between.data));
+
+        // Reset, and unknown values, only reset without Secure Boot.
+        for policy in [SbatPolicy::Reset, SbatPolicy::Unknown(0)] {
+            let policy = Some(policy);
+            check(Some(&newer), policy, true, (Keep, &newer.data));
+            check(Some(&older), policy, true, (Update, PREVIOUS));
+            check(Some(&newer), policy, false, (Reset, SBAT_LEVEL_ORIGINAL));
+        }
+    }
+
+    #[test]
+    fn test_sbat_policy() {
+        for byte in 0..=u8::MAX {
+            assert_eq!(SbatPolicy::from_byte(byte).to_byte(), byte);
+        }
+        assert_eq!(SbatPolicy::from_byte(1), SbatPolicy::Latest);
+        assert_eq!(SbatPolicy::from_byte(2), SbatPolicy::Automatic);
+        assert_eq!(SbatPolicy::from_byte(3), SbatPolicy::Reset);
+    }
+}
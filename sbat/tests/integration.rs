@@ -199,15 +199,12 @@ fn assert_revoked(revocations_csv: &[u8], metadata_csv: &[u8]) {
     let revocations = RevocationSbat::parse(revocations_csv).unwrap();
     let image_sbat = ImageSbat::parse(metadata_csv).unwrap();
 
-    assert!(matches!(
-        revocations.validate_image(&image_sbat),
-        Revoked(_)
-    ));
+    assert!(matches!(revocations.validate_image(image_sbat), Revoked(_)));
 }
 
 fn assert_allowed(revocations_csv: &[u8], metadata_csv: &[u8]) {
     let revocations = RevocationSbat::parse(revocations_csv).unwrap();
     let image_sbat = ImageSbat::parse(metadata_csv).unwrap();
 
-    assert_eq!(revocations.validate_image(&image_sbat), Allowed);
+    assert_eq!(revocations.validate_image(image_sbat), Allowed);
 }
@@ -1,7 +1,7 @@
 use sbat::{RevocationSection, RevocationSectionError};
 
 #[cfg(feature = "alloc")]
-use sbat::RevocationSbatOwned;
+use sbat::{RevocationSbat, RevocationSbatOwned};
 
 /// Parse the actual `.sbatlevel` data in shim as of 2023-01-29.
 #[cfg(feature = "alloc")]
@@ -110,3 +110,46 @@ fn test_sbat_level_section_errors() {
         Err(RevocationSectionError::MissingLatestNull),
     );
 }
+
+/// Check that `to_bytes` reproduces the layout created by shim.
+#[cfg(feature = "alloc")]
+#[test]
+fn test_revocation_section_to_bytes() {
+    let data = include_bytes!("sbatlevel.section");
+    let sbat_level_section = RevocationSection::parse(data).unwrap();
+    assert_eq!(sbat_level_section.to_bytes(), data);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_revocation_section_round_trip() {
+    let previous = RevocationSbat::parse(b"sbat,1,2022052400\ngrub,2").unwrap();
+    let latest = RevocationSbat::parse(b"sbat,1,2023012900\nshim,2").unwrap();
+
+    let section = RevocationSection::new(previous, latest);
+    let data = section.to_bytes();
+    #[rustfmt::skip]
+    let header = [
+        // Version.
+        0, 0, 0, 0,
+        // Previous offset.
+        8, 0, 0, 0,
+        // Latest offset.
+        33, 0, 0, 0,
+    ];
+    assert_eq!(data[..12], header);
+
+    let parsed = RevocationSection::parse(&data).unwrap();
+    assert_eq!(parsed, section);
+    assert_eq!(RevocationSbat::parse(parsed.previous()).unwrap(), previous);
+    assert_eq!(RevocationSbat::parse(parsed.latest()).unwrap(), latest);
+
+    // Empty revocation data is also valid.
+    let empty = RevocationSbat::parse(b"").unwrap();
+    let section = RevocationSection::new(empty, empty);
+    assert_eq!(
+        section.to_bytes(),
+        [0, 0, 0, 0, 8, 0, 0, 0, 9, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(RevocationSection::parse(&section.to_bytes()), Ok(section));
+}
@@ -0,0 +1,223 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! This file tests the shim-compatible parser using vectors adapted
+//! from shim's own tests:
+//! <https://github.com/rhboot/shim/blob/HEAD/test-sbat.c>
+//! <https://github.com/rhboot/shim/blob/HEAD/test-csv.c>
+
+use sbat::shim::{ParseOptions, ShimParseError, ShimSbat};
+use sbat::{ImageSbat, RevocationSbat};
+
+fn image(data: &[u8]) -> Result<ShimSbat<'_>, ShimParseError> {
+    ShimSbat::parse(data, ParseOptions::IMAGE)
+}
+
+fn revocations(data: &[u8]) -> Result<ShimSbat<'_>, ShimParseError> {
+    ShimSbat::parse(data, ParseOptions::REVOCATION)
+}
+
+fn names<'a>(sbat: &ShimSbat<'a>) -> Vec<&'a [u8]> {
+    sbat.rows().map(|row| row.name()).collect()
+}
+
+#[test]
+fn test_parse_sbat_section_zero_size() {
+    assert_eq!(image(b""), Err(ShimParseError::Empty));
+}
+
+#[test]
+fn test_parse_sbat_section_no_newline() {
+    let sbat = image(b"test1,1,SBAT test1,acme,1,testURL").unwrap();
+    assert_eq!(sbat.rows().count(), 1);
+}
+
+#[test]
+fn test_parse_sbat_section_no_commas() {
+    assert_eq!(
+        image(b"test1"),
+        Err(ShimParseError::TooFewColumns { row: 0 })
+    );
+}
+
+#[test]
+fn test_parse_sbat_section_too_few_elem() {
+    assert_eq!(
+        image(b"test1,1,acme"),
+        Err(ShimParseError::TooFewColumns { row: 0 })
+    );
+}
+
+#[test]
+fn test_parse_sbat_section_too_many_elem() {
+    let sbat = image(
+        b"test1,1,SBAT test1,acme1,1,testURL1,other1,stuff,is,here\n\
+          test2,2,SBAT test2,acme2,2,testURL2,other2",
+    )
+    .unwrap();
+    let rows: Vec<_> = sbat.rows().collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(
+        rows[0].columns(),
+        [
+            b"test1".as_slice(),
+            b"1",
+            b"SBAT test1",
+            b"acme1",
+            b"1",
+            b"testURL1"
+        ]
+    );
+    assert_eq!(rows[1].columns()[5], b"testURL2");
+}
+
+#[test]
+fn test_parse_sbat_section_empty_column() {
+    assert_eq!(
+        image(b"test1,1,,acme,1,testURL\n"),
+        Err(ShimParseError::EmptyColumn { row: 0, column: 2 })
+    );
+    // A trailing comma beyond the six stored columns is ignored.
+    assert!(image(b"test1,1,SBAT test1,acme,1,testURL,\n").is_ok());
+}
+
+#[test]
+fn test_parse_sbat_section_three_entries() {
+    let sbat = image(
+        b"test1,1,SBAT test1,acme1,1,testURL1\n\
+          test2,2,SBAT test2,acme2,2,testURL2\n\
+          test3,3,SBAT test3,acme3,3,testURL3\n",
+    )
+    .unwrap();
+    assert_eq!(names(&sbat), [b"test1".as_slice(), b"test2", b"test3"]);
+    let generations: Vec<_> = sbat.rows().map(|row| row.generation()).collect();
+    assert_eq!(generations, [1, 2, 3]);
+}
+
+#[test]
+fn test_csv_line_endings_and_bom() {
+    let sbat = image(
+        b"\xef\xbb\xbf\r\n\
+          test1,1,SBAT test1,acme1,1,testURL1\r\n\r\n\
+          test2,2,SBAT test2,acme2,2,testURL2\r\
+          test3,3,SBAT test3,acme3,3,testURL3\n\n",
+    )
+    .unwrap();
+    assert_eq!(names(&sbat), [b"test1".as_slice(), b"test2", b"test3"]);
+}
+
+#[test]
+fn test_csv_whitespace_is_not_trimmed() {
+    let sbat = image(b" test1 ,1,SBAT test1,acme1,1,testURL1").unwrap();
+    assert_eq!(sbat.rows().next().unwrap().name(), b" test1 ");
+}
+
+#[test]
+fn test_csv_data_ends_at_null() {
+    let sbat = image(
+        b"test1,1,SBAT test1,acme1,1,testURL1\n\0\
+          garbage that would not parse",
+    )
+    .unwrap();
+    assert_eq!(names(&sbat), [b"test1".as_slice()]);
+
+    // Data that only contains a null is not empty, just has no rows.
+    assert_eq!(image(b"\0").unwrap().rows().count(), 0);
+}
+
+#[test]
+fn test_parse_sbat_var() {
+    let sbat_var = revocations(b"sbat,1,2021030218\ntest1,1\n").unwrap();
+    assert_eq!(names(&sbat_var), [b"sbat".as_slice(), b"test1"]);
+    assert_eq!(sbat_var.date(), Some(b"2021030218".as_slice()));
+
+    // The date only comes from the first row.
+    let sbat_var = revocations(b"sbat,1\ntest1,1,2021030218\n").unwrap();
+    assert_eq!(sbat_var.date(), None);
+
+    assert_eq!(
+        revocations(b"sbat,1\ntest1\n"),
+        Err(ShimParseError::TooFewColumns { row: 1 })
+    );
+    assert_eq!(
+        revocations(b"sbat,1,\n"),
+        Err(ShimParseError::EmptyColumn { row: 0, column: 2 })
+    );
+}
+
+#[test]
+fn test_verify_sbat() {
+    let entries = b"test1,1,SBAT test1,acme1,1,testURL1\n\
+                    test2,2,SBAT test2,acme2,2,testURL2\n";
+    let image = image(entries).unwrap();
+
+    // Match one exact.
+    let var = revocations(b"test1,1\n").unwrap();
+    assert_eq!(var.first_revoked(&image), None);
+
+    // Match one higher.
+    let var = revocations(b"test2,1\n").unwrap();
+    assert_eq!(var.first_revoked(&image), None);
+
+    // Reject one.
+    let var = revocations(b"test2,3\n").unwrap();
+    assert_eq!(var.first_revoked(&image).unwrap().name(), b"test2");
+
+    // Reject many.
+    let var = revocations(b"test1,2\ntest2,3\n").unwrap();
+    assert_eq!(var.first_revoked(&image).unwrap().name(), b"test1");
+
+    // Match many exact.
+    let var = revocations(b"test1,1\ntest2,2\n").unwrap();
+    assert_eq!(var.first_revoked(&image), None);
+
+    // Match with a different name.
+    let var = revocations(b"foo,5\n").unwrap();
+    assert_eq!(var.first_revoked(&image), None);
+
+    // Mixed names.
+    let var = revocations(b"foo,5\ntest1,1\n").unwrap();
+    assert_eq!(var.first_revoked(&image), None);
+    let var = revocations(b"foo,5\ntest1,1\ntest2,5\n").unwrap();
+    assert_eq!(var.first_revoked(&image).unwrap().name(), b"test2");
+}
+
+#[test]
+fn test_verify_sbat_header_row() {
+    // The `sbat` header row of the revocations is compared like any
+    // other row.
+    let image = image(
+        b"sbat,1,SBAT Version,sbat,1,https://github.com/rhboot/shim/blob/main/SBAT.md\n",
+    )
+    .unwrap();
+    let var = revocations(b"sbat,2,2021030218\n").unwrap();
+    assert_eq!(var.first_revoked(&image).unwrap().name(), b"sbat");
+}
+
+#[test]
+fn test_generation_parsing() {
+    // Shim parses generations with `atoi`, so these are all zero and
+    // are revoked by any non-zero generation.
+    for generation in ["+1", "-1", "abc", "0"] {
+        let data = format!("test1,{generation},a,b,c,d\n");
+        let image = image(data.as_bytes()).unwrap();
+        assert_eq!(image.rows().next().unwrap().generation(), 0);
+        let var = revocations(b"test1,1\n").unwrap();
+        assert!(var.first_revoked(&image).is_some());
+
+        // The strict parser rejects all of these.
+        assert!(ImageSbat::parse(data.as_bytes()).is_err());
+    }
+
+    // Trailing garbage is ignored by shim.
+    let image = image(b"test1,2abc,a,b,c,d\n").unwrap();
+    assert_eq!(image.rows().next().unwrap().generation(), 2);
+
+    // Revocation data with a `+` is rejected by the strict parser too.
+    assert!(RevocationSbat::parse(b"sbat,1\ntest1,+1\n").is_err());
+}